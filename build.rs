@@ -0,0 +1,30 @@
+use std::path::Path;
+use std::process::Command;
+
+/*
+ * commit the tool was built from, for --version and tt_migration_meta;
+ * left out when not building from a git checkout
+ */
+
+fn main ()
+{
+    for f in [".git/HEAD", ".git/refs"]
+    {
+        if Path::new(f).exists()
+        {
+            println!("cargo:rerun-if-changed={}", f);
+        }
+    }
+
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .and_then(|o| String::from_utf8(o.stdout).ok());
+
+    if let Some(c) = commit
+    {
+        println!("cargo:rustc-env=TT_GIT_COMMIT={}", c.trim());
+    }
+}
@@ -0,0 +1,108 @@
+use std::fs;
+use std::io::Read;
+
+use rusqlite::Connection;
+use rusqlite::OpenFlags;
+use serde_json::json;
+use serde_json::Value;
+
+use crate::args::OutputFormat;
+use crate::error;
+use crate::error::MigrationError;
+use crate::inspect;
+use crate::schema;
+
+/*
+ * --validate-new-against-app, the tables the tool writes compared with
+ * the ones the new app expects, from its .sql schema or a db it created
+ *
+ * only the tables the tool writes are compared (the app may have more),
+ * tt_migration_meta is the tool's own and left out; names compare as
+ * sqlite does, types case-insensitively
+ */
+
+const OWN_TABLES : [&str; 1] = ["tt_migration_meta"];
+
+pub fn open_expected ( file : &str ) -> Result<Connection, MigrationError>
+{
+    let mut header = [0u8; 16];
+    let is_db = fs::File::open(file)
+        .and_then(|mut f| f.read_exact(&mut header))
+        .is_ok_and(|_| &header == b"SQLite format 3\0");
+
+    if is_db
+    {
+        return Connection::open_with_flags(file, OpenFlags::SQLITE_OPEN_READ_ONLY).map_err(error::io);
+    }
+
+    let sql = fs::read_to_string(file)
+        .map_err(|e| MigrationError::Io(format!("{:?}: {}", file, e)))?;
+    let db = Connection::open_in_memory().map_err(error::io)?;
+    db.execute_batch(&sql)
+        .map_err(|e| MigrationError::Schema(format!("{:?} isn't a schema sqlite can run: {}", file, e)))?;
+
+    Ok(db)
+}
+
+/*
+ * (table, column, written type, expected type, difference), "-" where
+ * one side has nothing
+ */
+
+pub fn differences ( written : &Connection, expected : &Connection )
+    -> Result<Vec<[String; 5]>, MigrationError>
+{
+    let expected_tables = schema::table_names(expected)?;
+    let mut tables : Vec<String> = schema::table_names(written)?.into_iter()
+        .filter(|t| !OWN_TABLES.contains(&t.as_str()) && !t.starts_with("sqlite_"))
+        .collect();
+    tables.sort();
+
+    let dash = || "-".to_string();
+    let mut diffs = Vec::new();
+
+    for table in tables
+    {
+        if !expected_tables.contains(&table)
+        {
+            diffs.push([table, dash(), dash(), dash(), "table not in the app's schema".to_string()]);
+            continue;
+        }
+
+        let ours   = schema::columns(written, &table)?;
+        let theirs = schema::columns(expected, &table)?;
+        let find   = |cols : &[(String, String)], name : &str|
+            cols.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, t)| t.clone());
+
+        for (name, t) in &ours
+        {
+            match find(&theirs, name)
+            {
+                None                                  => diffs.push([table.clone(), name.clone(), t.clone(), dash(),
+                                                                     "not in the app's table".to_string()]),
+                Some(e) if !e.eq_ignore_ascii_case(t) => diffs.push([table.clone(), name.clone(), t.clone(), e,
+                                                                     "type differs".to_string()]),
+                Some(_)                               => (),
+            }
+        }
+        for (name, t) in &theirs
+        {
+            if find(&ours, name).is_none()
+            {
+                diffs.push([table.clone(), name.clone(), dash(), t.clone(),
+                            "the app has it, the tool doesn't write it".to_string()]);
+            }
+        }
+    }
+
+    Ok(diffs)
+}
+
+pub fn print ( format : &OutputFormat, diffs : &[[String; 5]] )
+{
+    let rows : Vec<Vec<Value>> = diffs.iter()
+        .map(|d| d.iter().map(|v| json!(v)).collect())
+        .collect();
+
+    inspect::print_rows(format, &["table", "column", "written", "expected", "difference"], &rows);
+}
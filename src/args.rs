@@ -0,0 +1,1138 @@
+use std::env;
+use std::process;
+
+use timetracker_transitiontool::error::EXIT_USAGE;
+use timetracker_transitiontool::old::OldTables;
+use timetracker_transitiontool::old::SourceZone;
+use timetracker_transitiontool::options::AddedFallback;
+use timetracker_transitiontool::options::DateTrust;
+use timetracker_transitiontool::options::MigrationOptions;
+use timetracker_transitiontool::round::RoundMode;
+use timetracker_transitiontool::schema;
+
+use crate::new;
+
+/*
+ * Command line options
+ * kept deliberately simple, no external parser
+ *
+ * not listed in USAGE, for tests only:
+ *   --seed-for-tests <n>   fixed clock for reproducible output, see main.rs
+ */
+
+pub const USAGE : &str =
+"Usage: timetracker_transitiontool [OPTIONS] [OLD_PATH]
+       timetracker_transitiontool doctor [OPTIONS] [OLD_PATH]
+
+OLD_PATH is the old db (or json dump); if omitted it's taken from TT_OLD_DB,
+and asked for interactively if that isn't set either;
+the old db may also be a sqlite uri, eg file:/mnt/ro/productivity.db?immutable=1
+
+doctor checks the old db for orphaned history, activities without history,
+invalid or disagreeing dates, odd or NULL hours and duplicate days; it only
+reads (--format applies)
+
+Options:
+    --input-format <sqlite|json|sql>
+                        format of the old data, default sqlite
+                        (json: same fields as the old tables, see json.rs;
+                        sql: a text dump of the old db, loaded into memory)
+    --from-sql <file>   migrate from sql dump <file>, short for
+                        --input-format sql <file>
+    --old-activities-table <name>
+                        name of the old activities table, default activities
+    --old-history-table <name>
+                        name of the old history table, default history
+    --old-activities-db <path>
+    --old-history-db <path>
+                        the old db split in two files, activities in one and
+                        history in the other; both instead of OLD_PATH
+    --require-history   stop if the old db has no history table, instead of
+                        migrating only its activities
+    --from-version <v>  read the old db as layout v instead of detecting it:
+                        classic (history with year/month/day columns, the
+                        archived C++ app) or datetime (a single datetime
+                        column)
+    --normalize-timezone-dates
+                        for the old history with a datetime column: take the
+                        day of each datetime in --source-timezone (those
+                        without an offset are taken as utc) and derive the
+                        week from that; lists the rows that move to another day
+    --source-timezone <zone>
+                        zone the old app recorded in: utc, local (of this
+                        machine) or an offset like +02:00
+    --convert-timezone  for the old history with a datetime column: the
+                        datetimes were written in --from-tz, take their days
+                        as if in --to-tz instead (recorded while travelling
+                        or before a move); lists the rows that move to
+                        another day, and so possibly another week
+    --from-tz <zone>    zone the datetimes were written in, as with
+                        --source-timezone
+    --to-tz <zone>      zone to move them to
+    --old-hours-column <name>
+                        column of the old history holding the hours,
+                        default hours_on_day
+    --old-date-column <name>
+                        column of the old history holding the date text,
+                        default date
+    --extra-column <old>[=<new>]
+                        also copy column <old> of the old activities into
+                        column <new> of tt_activities (added if missing),
+                        repeatable
+    --strict-types      stop if a column of the old db holds values of another
+                        type than expected (like hours stored as text),
+                        instead of converting them with a warning
+    --strict-names      stop if an activity has a NULL or blank name, instead of
+                        migrating it as (unnamed #<id>)
+    --max-name-length <n>
+                        warn about activity names longer than n characters
+    --truncate-names    with --max-name-length, also cut those names to n
+                        characters (ending in an ellipsis)
+    --rename-activity <old>=<new>
+                        migrate the activity named <old> (exactly) as <new>,
+                        its history stays with it; repeatable
+    --strip-suffix-glob <pattern>
+                        cut the end of activity names matching <pattern> (a
+                        glob: (*) strips Reading (books) to Reading),
+                        the shortest one that matches; with --merge-by-name
+                        and --on-duplicate-name merge, activities left with
+                        the same name become one
+    --transform-config <file>
+                        transforms per field from a json file (see
+                        transform_config.rs), eg scale the hours of the history
+                        or uppercase names: trim, upper, lower, replace, prefix,
+                        suffix; multiply, add, at_least, at_most, round
+    --skip-inactive     don't migrate deactivated activities (nor their history)
+    --include-name <pattern>
+                        only migrate activities whose name matches, repeatable
+    --exclude-name <pattern>
+                        don't migrate activities whose name matches, repeatable
+                        (patterns: substring, or glob if containing * or ?,
+                        case-insensitive; history follows its activity)
+    --activity <name>   only migrate this one activity and all of its history;
+                        <name> as for --include-name, an exact name wins,
+                        fails listing the candidates unless exactly one matches
+    --trust <fields|string>
+                        which side wins when a history row's `date` text and
+                        year/month/day fields disagree; the other one gets
+                        rewritten (without, mismatches are only reported)
+    --trust-numeric     same as --trust fields
+    --trust-string      same as --trust string
+    --year-range <from>..<to>
+                        years a history day has to fall in, default
+                        1900..2200 (ends included)
+    --invalid-date-policy <error|skip>
+                        what to do about history rows with a year outside of
+                        --year-range or no real day (like 2024-02-30): stop
+                        before writing, listing them (default), or leave them
+                        out, listing them
+    --added-fallback <choice>[,<choice>...]
+                        what to store for activities whose added date is
+                        empty or unreadable, first choice that works wins:
+                        earliest-history (first day with history), now
+                        (day of the run) or epoch (1970-01-01); without,
+                        such dates are migrated as they are
+    --compact-history   sum up multiple history rows of an activity on the
+                        same day into a single row
+    --merge-source-dups sum up history rows of an activity with the same date
+                        into the first of them (see --report-source-dups); unlike
+                        --compact-history the date text decides, as in the new db
+    --dedup-history     keep only the first history row of an activity on a
+                        date; the new tt_history gets UNIQUE(id, date) and
+                        rows it already has are skipped, so running again
+                        doesn't add them twice
+    --zero-fill-gaps    add a 0 hours history row for every day between the first
+                        and the last day of an activity without one, with its
+                        iso week (for apps expecting a row on every day)
+    --only-recompute    don't read the old totals at all: activities are written
+                        with 0 hours and get the sum of their history once it's
+                        written; lists the totals at the end
+    --round-mode <half-up|half-even>
+                        how hours exactly halfway get rounded to 6 decimals,
+                        away from zero (default) or to even (banker's)
+    --limit <n>         only migrate the first n history rows (after filtering)
+    --limit-activities <n>
+                        only migrate the first n activities (after filtering)
+    --sample <n>        migrate n history rows picked at random (from all
+                        years) and their activities, to try things out
+    --sample-seed <n>   seed of the --sample pick, default 0; the same seed
+                        picks the same rows
+    --db-path <file>    write to this file instead of the new db in the
+                        timetracker's config folder; its folder is created
+                        if needed (also used by --report)
+    --since-last-run    incremental: only migrate the history from the day of the
+                        last --since-last-run into the new db on (all of it the
+                        first time), updating the hours of days already there;
+                        implies --prefer-existing and --dedup-history
+    --activity-checksums
+                        with --since-last-run (or --watch): keep a hash of every
+                        activity and its whole history in the new db, and next
+                        time only migrate the activities whose hash changed,
+                        with all of their history (edits of older days
+                        included); the others are skipped
+    --watch             keep the new db in sync while the old app is still in
+                        use: a --since-last-run migration every --interval
+                        seconds until interrupted (OLD_PATH has to be given)
+    --interval <s>      seconds between the runs of --watch, default 300
+    --merge-into <file> merge the old data into the new db <file> (created if
+                        missing), wherever it is: --db-path <file> plus
+                        --prefer-existing; --dedup-history and the other
+                        options apply as usual
+    --synchronous <off|normal|full>
+                        PRAGMA synchronous of the new db, default full;
+                        normal/off are faster but a crash or power loss
+                        during the run can leave (with off: corrupt) a new
+                        db behind, only use them on scratch dbs
+    --atomic-file       write to a temp file next to the new db (a copy of it,
+                        if it exists) and rename that over it once everything,
+                        checks included, went through; a failed or killed run
+                        leaves the new db as it was
+    --out-key <passphrase>
+                        create (or open) the new db encrypted with sqlcipher,
+                        for a new app expecting that; only in builds with
+                        --features sqlcipher
+    --no-create         only update an existing new db, never create the
+                        config folder or the db itself
+    --prune-empty-activities
+                        don't migrate activities that are left without any
+                        history rows once all filters/limits applied
+    --stamp-migrated    add a migrated_at column to both new tables, holding
+                        the time of this run for every migrated row
+    --store-minutes     also store the hours as whole minutes, in integer
+                        columns minutestotal and minutesonday (added to the
+                        new tables); lists rows that lose time below a minute
+    --export-weekly <file>
+                        after migrating, write the hours of every activity per
+                        iso week (summed up from tt_history) to csv <file>
+    --json              print the summary at the end (counts, date range,
+                        hours per year, the warnings of the run) as json
+    -q, --quiet         less output; no progress bar, only a line now and then,
+                        no next steps at the end
+    --verbose-sql       log every statement run against the new db, with its
+                        parameters, to stderr
+    --check-foreign-keys
+                        after migrating, check the new db for history rows
+                        of missing activities; fails the run if any are found
+    --verify-foreign-keys
+                        same as --check-foreign-keys
+    --continue-on-insert-error
+                        don't stop at a row that fails to insert, report all
+                        of them at the end (and still exit non-zero); rows
+                        are committed one by one as in any run (but one with
+                        --strict), so the new db is left with everything
+                        that could be written
+    --quarantine <file> write every row that failed to insert (see above), with
+                        the reason, to a json file that can be fed back in
+                        with --input-format json once fixed; csv if <file>
+                        ends in .csv
+    --audit-log <file>  append one json line per decision of the run (rows read,
+                        skipped with the reason, substituted dates, recomputed
+                        totals, rows inserted or failed) to <file>
+    --confirm-totals    before writing, show the total hours of the old data and
+                        of what is about to be written and ask to go on (asks
+                        for an explicit yes if they differ)
+    --verify-checksums  after migrating, compare a content hash of the migrated
+                        rows with one of the old rows (as they should have been
+                        written) per table; fails the run if they differ, and
+                        records the hashes in tt_migration_meta
+    --prefer-existing   merge activities already in the new db (same id)
+                        instead of failing on them: their name and added date
+                        stay, and so does their total if it's larger or more
+                        precise than the old one; their old history is still
+                        added (see --dedup-history)
+    --merge-by-name     match activities by name instead of id (ignoring case
+                        and surrounding or repeated blanks): old activities
+                        named alike are one (see --on-duplicate-name), and
+                        one named like an activity of an existing new db is
+                        merged into that one, as with --prefer-existing; the
+                        history follows
+    --on-duplicate-name <merge|suffix|error>
+                        with --merge-by-name, what to do about old
+                        activities named alike: merge them into the first
+                        one (totals summed, history combined under its id),
+                        suffix the others' names with (2), (3), ... or stop
+                        (default), listing them
+    --dedup-activities-by-name <merge|report>
+                        old activities named alike (ignoring case and
+                        surrounding or repeated blanks), the same project
+                        entered twice: merge them into the first one, as
+                        --on-duplicate-name merge does (without comparing
+                        names to an existing new db), or only list them with
+                        their totals and history rows (--format applies)
+    --remap-ids         let the new db pick the ids of the migrated activities
+                        (for a tt_activities already using its own ids, eg
+                        with AUTOINCREMENT); history follows them, the old to
+                        new ids are listed
+    --emit-sql <file>   write the statements creating and filling the new db to
+                        sql script <file> instead of running them; the new db
+                        is left as it is (the script is for it as it is now)
+    --output-sql-schema-only <file>
+                        only write the statements creating an empty new db to
+                        <file>, with what --dedup-history, --store-minutes,
+                        --stamp-migrated and --extra-column (reading the
+                        column types from OLD_PATH) add to it
+    --validate-new-against-app <file>
+                        compare the tables a run with these options writes
+                        with the ones the new app expects, from its .sql
+                        schema or a db it created; lists the differing
+                        columns and types and exits with 2 if there are any
+    --dry-run           do everything but write: nothing is created; against an
+                        existing new db, list the activities that would be
+                        added or get other hours, and the history rows that
+                        would be added
+    --check-space       before writing, check that the destination's disk has
+                        room for about the size of the old db (the default)
+    --skip-space-check  don't, e.g. if the filesystem reports its free space
+                        wrongly
+    --parallel-verify <n>
+                        with --verify-checksums, read the new db back in n
+                        threads, each hashing a range of activity ids
+    --strict, --abort-on-warning
+                        stop at the first WARNING, as if it were an error; the
+                        new db is written in a single transaction then, and
+                        left as it was
+    --force             don't fail the run on failed checks, only report them;
+                        also allows writing into a new db whose recorded
+                        schema version differs from this tool's
+    --copy-local        read the old db from a copy in the temp folder (removed
+                        afterwards), for old dbs on a network share or a
+                        read-only mount; a failed or short copy is retried
+    --page-size <n>     read the old history n rows per query instead of in a
+                        single one (when streaming it), bounds what sqlite
+                        holds on the reading side too
+    --max-memory <MiB>  old dbs larger than this get their history streamed
+                        row by row instead of buffered, default 256
+    --bench             migrate into an in-memory db (nothing is written) and
+                        print the time each phase took: read, transform,
+                        insert and verify (--format applies)
+
+Inspection (read-only, nothing gets migrated):
+    --list-activities   list the old activities
+    --stats             print totals and counts of the old data
+    --name-encoding-report
+                        list activity names with invalid UTF-8, control or
+                        replacement characters
+    --report-source-dups
+                        list history rows of an activity sharing a date, with
+                        their hours (likely entered twice)
+    --make-fixture <file>
+                        write a small copy of the old db to <file> to attach
+                        to a bug report: the same schema, up to 200 history
+                        rows (boundary cases first: turn of the year, near
+                        midnight, odd hours, orphans) and their activities,
+                        with the names scrubbed
+    --diff-only <old-a> <old-b>
+                        list what changed from old db <old-a> to the later
+                        <old-b>: activities added, removed or renamed and
+                        history days added, removed or with other hours
+    --format <table|csv|json>
+                        output format of the above, default table
+
+Reports (read-only, on an already migrated new db):
+    --report <monthly>  hours of tt_history per month (--format applies)
+    --list-backups      list the backups next to the new db, named
+                        <db>.bak-<time>, newest first (--format applies)
+
+    --print-config      print the old and new db paths and the value of every
+                        option, with where it comes from (default, env, flag
+                        or implied by another flag), and exit; reads nothing
+    -V, --version       print the version of this tool and exit
+    -h, --help          print this help and exit";
+
+#[derive(Debug, Default, PartialEq)]
+pub enum InputFormat {
+    #[default]
+    Sqlite,
+    Json,
+    Sql,
+}
+
+#[derive(Debug, Default)]
+pub enum OutputFormat {
+    #[default]
+    Table,
+    Csv,
+    Json,
+}
+
+#[derive(Debug)]
+pub enum Report {
+    Monthly,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ActivityDedup {
+    Merge,
+    Report,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub enum DuplicateName {
+    Merge,
+    Suffix,
+    #[default]
+    Error,
+}
+
+impl DuplicateName
+{
+    pub fn name ( &self ) -> &'static str
+    {
+        match self
+        {
+            DuplicateName::Merge  => "merge",
+            DuplicateName::Suffix => "suffix",
+            DuplicateName::Error  => "error",
+        }
+    }
+}
+
+pub struct Args {
+    pub old_path        : Option<String>,
+    pub old_activities_db : Option<String>,
+    pub old_path_from_env : bool,		// TT_OLD_DB, no OLD_PATH given
+    pub input_format    : InputFormat,
+    pub list_activities : bool,
+    pub stats           : bool,
+    pub doctor          : bool,
+    pub diff_only       : Option<(String, String)>,
+    pub name_encoding_report : bool,
+    pub report_source_dups : bool,
+    pub dedup_activities : Option<ActivityDedup>,
+    pub make_fixture    : Option<String>,
+    pub format          : OutputFormat,
+    pub report          : Option<Report>,
+    pub list_backups    : bool,
+    pub max_memory      : u64,
+    pub parallel_verify : Option<usize>,
+    pub page_size       : Option<usize>,
+    pub stamp_migrated  : bool,
+    pub store_minutes   : bool,
+    pub db_path         : Option<String>,
+    pub synchronous     : &'static str,
+    pub no_create       : bool,
+    pub verbose_sql     : bool,
+    pub check_foreign_keys : bool,
+    pub verify_checksums : bool,
+    pub confirm_totals  : bool,
+    pub continue_on_insert_error : bool,
+    pub quarantine      : Option<String>,
+    pub audit_log       : Option<String>,
+    pub dry_run         : bool,
+    pub since_last_run  : bool,
+    pub activity_checksums : bool,
+    pub watch           : bool,
+    pub interval        : u64,
+    pub normalize_timezone_dates : bool,
+    pub convert_timezone : bool,
+    pub merge_into      : Option<String>,
+    pub emit_sql        : Option<String>,
+    pub schema_only     : Option<String>,
+    pub validate_app    : Option<String>,
+    pub atomic_file     : bool,
+    pub out_key         : Option<String>,
+    pub bench           : bool,
+    pub prefer_existing : bool,
+    pub remap_ids       : bool,
+    pub strict_types    : bool,
+    pub strip_suffix    : Option<String>,
+    pub transform_config : Option<String>,
+    pub merge_by_name   : bool,
+    pub on_duplicate_name : DuplicateName,
+    pub copy_local      : bool,
+    pub check_space     : bool,
+    pub force           : bool,
+    pub strict          : bool,
+    pub quiet           : bool,
+    pub json            : bool,
+    pub export_weekly   : Option<String>,
+    pub old_tables      : OldTables,
+    pub options         : MigrationOptions,
+    pub extra_columns   : Vec<(String, String)>,
+    pub rename_activity : Vec<(String, String)>,
+    pub strict_names    : bool,
+    pub only_recompute  : bool,
+    pub zero_fill_gaps  : bool,
+    pub require_history : bool,
+    pub max_name_length : Option<usize>,
+    pub truncate_names  : bool,
+    pub seed_for_tests  : Option<u32>,
+    pub print_config    : bool,
+    pub given           : Vec<String>,	// flags as typed, for --print-config
+}
+
+impl Default for Args
+{
+    fn default() -> Self
+    {
+        Args {
+            old_path        : None,
+            old_activities_db : None,
+            old_path_from_env : false,
+            input_format    : Default::default(),
+            list_activities : false,
+            stats           : false,
+            doctor          : false,
+            diff_only       : None,
+            name_encoding_report : false,
+            report_source_dups : false,
+            dedup_activities : None,
+            make_fixture    : None,
+            format          : Default::default(),
+            report          : None,
+            list_backups    : false,
+            max_memory      : 256,
+            parallel_verify : None,
+            page_size       : None,
+            stamp_migrated  : false,
+            store_minutes   : false,
+            db_path         : None,
+            synchronous     : "FULL",
+            no_create       : false,
+            verbose_sql     : false,
+            check_foreign_keys : false,
+            verify_checksums : false,
+            confirm_totals  : false,
+            continue_on_insert_error : false,
+            quarantine      : None,
+            audit_log       : None,
+            dry_run         : false,
+            since_last_run  : false,
+            activity_checksums : false,
+            watch           : false,
+            interval        : 300,
+            normalize_timezone_dates : false,
+            convert_timezone : false,
+            merge_into      : None,
+            emit_sql        : None,
+            schema_only     : None,
+            validate_app    : None,
+            atomic_file     : false,
+            out_key         : None,
+            bench           : false,
+            prefer_existing : false,
+            remap_ids       : false,
+            strict_types    : false,
+            strip_suffix    : None,
+            transform_config : None,
+            merge_by_name   : false,
+            on_duplicate_name : Default::default(),
+            copy_local      : false,
+            check_space     : true,
+            force           : false,
+            strict          : false,
+            quiet           : false,
+            json            : false,
+            export_weekly   : None,
+            old_tables      : Default::default(),
+            options         : Default::default(),
+            extra_columns   : Vec::new(),
+            rename_activity : Vec::new(),
+            strict_names    : false,
+            only_recompute  : false,
+            zero_fill_gaps  : false,
+            require_history : false,
+            max_name_length : None,
+            truncate_names  : false,
+            seed_for_tests  : None,
+            print_config    : false,
+            given           : Vec::new(),
+        }
+    }
+}
+
+fn number<T : std::str::FromStr> ( it : &mut impl Iterator<Item = String>, flag : &str ) -> T
+{
+    let v = value(it, flag);
+    match v.parse()
+    {
+        Ok(n)  => n,
+        Err(_) => usage_error(&format!("Invalid number for {}: {}", flag, v)),
+    }
+}
+
+fn usage_error ( msg : &str ) -> !
+{
+    eprintln!("{}", msg);
+    eprintln!("{}", USAGE);
+    process::exit(EXIT_USAGE.into());
+}
+
+fn ident ( it : &mut impl Iterator<Item = String>, flag : &str ) -> String
+{
+    let v = value(it, flag);
+    if !schema::is_plain_ident(&v)
+    {
+        usage_error(&format!("Invalid name for {}: {:?} (letters, digits and _ only, \
+                              not starting with a digit)", flag, v));
+    }
+    v
+}
+
+fn zone ( it : &mut impl Iterator<Item = String>, flag : &str ) -> SourceZone
+{
+    let v = value(it, flag);
+    SourceZone::parse(&v).unwrap_or_else(||
+        usage_error(&format!("Unknown {}: {} (utc, local or an offset like +02:00)", flag, v)))
+}
+
+fn value ( it : &mut impl Iterator<Item = String>, flag : &str ) -> String
+{
+    match it.next()
+    {
+        Some(v) => v,
+        None    => usage_error(&format!("Missing value for {}", flag)),
+    }
+}
+
+impl Args
+{
+    pub fn parse() -> Args
+    {
+        let mut args : Args = Default::default();
+
+        let mut it = env::args().skip(1);
+
+        let mut from_tz = None;
+        let mut to_tz   = None;
+        let mut on_duplicate = None;
+
+        while let Some(a) = it.next()
+        {
+            if a.starts_with('-')
+            {
+                args.given.push(a.clone());
+            }
+            match a.as_str()
+            {
+                "--input-format" =>
+                {
+                    args.input_format = match value(&mut it, &a).as_str()
+                    {
+                        "sqlite" => InputFormat::Sqlite,
+                        "json"   => InputFormat::Json,
+                        "sql"    => InputFormat::Sql,
+                        v        => usage_error(&format!("Unknown input format: {}", v)),
+                    }
+                }
+                "--from-sql" =>
+                {
+                    args.input_format = InputFormat::Sql;
+                    args.old_path = Some(value(&mut it, &a));
+                }
+                "--old-activities-table" => args.old_tables.activities = ident(&mut it, &a),
+                "--old-history-table" => args.old_tables.history = ident(&mut it, &a),
+                "--old-activities-db" => args.old_activities_db = Some(value(&mut it, &a)),
+                "--old-history-db" => args.old_tables.history_db = Some(value(&mut it, &a)),
+                "--from-version" =>
+                {
+                    let v = value(&mut it, &a);
+                    match schema::old_version(&v)
+                    {
+                        Some(l) => args.old_tables.layout = Some(l),
+                        None    =>
+                        {
+                            let known : Vec<&str> = schema::OLD_VERSIONS.iter()
+                                .map(|(n, _)| *n).collect();
+                            usage_error(&format!("Unknown --from-version: {} (supported: {})",
+                                                 v, known.join(", ")));
+                        }
+                    }
+                }
+                "--old-hours-column" => args.old_tables.hours_column = ident(&mut it, &a),
+                "--old-date-column" => args.old_tables.date_column = ident(&mut it, &a),
+                "--extra-column" =>
+                {
+                    let v = value(&mut it, &a);
+                    let (old, new) = v.split_once('=').unwrap_or((&v, &v));
+                    if !schema::is_plain_ident(old) || !schema::is_plain_ident(new)
+                    {
+                        usage_error(&format!("Invalid --extra-column: {:?} (names: letters, \
+                                              digits and _ only, not starting with a digit)", v));
+                    }
+                    args.extra_columns.push((old.to_string(), new.to_string()));
+                }
+                "--rename-activity" =>
+                {
+                    let v = value(&mut it, &a);
+                    match v.split_once('=')
+                    {
+                        Some((old, new)) if !old.is_empty() && !new.trim().is_empty() =>
+                            args.rename_activity.push((old.to_string(), new.to_string())),
+                        _ => usage_error(&format!(
+                            "Invalid --rename-activity: {:?} (expected \"Old Name=New Name\")", v)),
+                    }
+                }
+                "--strict-names" => args.strict_names = true,
+                "--require-history" => args.require_history = true,
+                "--max-name-length" =>
+                {
+                    let n = number(&mut it, &a);
+                    if n == 0
+                    {
+                        usage_error("--max-name-length needs at least 1");
+                    }
+                    args.max_name_length = Some(n);
+                }
+                "--truncate-names" => args.truncate_names = true,
+                "--skip-inactive" => args.options.skip_inactive = true,
+                "--compact-history" => args.options.compact_history = true,
+                "--dedup-history" => args.options.dedup_history = true,
+                "--merge-source-dups" => args.options.merge_source_dups = true,
+                "--only-recompute" => args.only_recompute = true,
+                "--zero-fill-gaps" => args.zero_fill_gaps = true,
+                "--added-fallback" =>
+                {
+                    for v in value(&mut it, &a).split(',')
+                    {
+                        args.options.added_fallback.push(match v.trim()
+                        {
+                            "earliest-history" => AddedFallback::EarliestHistory,
+                            "now"              => AddedFallback::Now,
+                            "epoch"            => AddedFallback::Epoch,
+                            v => usage_error(&format!("Unknown --added-fallback: {}", v)),
+                        });
+                    }
+                }
+                "--trust" =>
+                {
+                    args.options.trust_dates = match value(&mut it, &a).as_str()
+                    {
+                        "fields" => Some(DateTrust::Numeric),
+                        "string" => Some(DateTrust::String),
+                        v        => usage_error(&format!("Unknown --trust value: {}", v)),
+                    }
+                }
+                "--year-range" =>
+                {
+                    let v = value(&mut it, &a);
+                    args.options.years = v.split_once("..")
+                        .and_then(|(a, b)| Some((a.trim().parse().ok()?, b.trim().parse().ok()?)))
+                        .filter(|(a, b) : &(i32, i32)| a <= b && *a >= 1 && *b <= 9999)
+                        .unwrap_or_else(|| usage_error(&format!(
+                            "Invalid --year-range: {} (like 1900..2200, years 1 to 9999)", v)));
+                }
+                "--invalid-date-policy" =>
+                {
+                    args.options.skip_invalid_dates = match value(&mut it, &a).as_str()
+                    {
+                        "error" => false,
+                        "skip"  => true,
+                        v       => usage_error(&format!("Unknown --invalid-date-policy: {}", v)),
+                    }
+                }
+                "--trust-numeric" => args.options.trust_dates = Some(DateTrust::Numeric),
+                "--trust-string"  => args.options.trust_dates = Some(DateTrust::String),
+                "--round-mode" =>
+                {
+                    args.options.round_mode = match value(&mut it, &a).as_str()
+                    {
+                        "half-up"   => RoundMode::HalfUp,
+                        "half-even" => RoundMode::HalfEven,
+                        v           => usage_error(&format!("Unknown --round-mode: {}", v)),
+                    }
+                }
+                "--max-memory" => args.max_memory = number(&mut it, &a),
+                "--page-size" =>
+                {
+                    let n : usize = number(&mut it, &a);
+                    if n == 0
+                    {
+                        usage_error("--page-size needs at least 1");
+                    }
+                    args.page_size = Some(n);
+                }
+                "--parallel-verify" =>
+                {
+                    let n = number(&mut it, &a);
+                    if n == 0
+                    {
+                        usage_error("--parallel-verify needs at least 1");
+                    }
+                    args.parallel_verify = Some(n);
+                }
+                "--check-foreign-keys" | "--verify-foreign-keys" => args.check_foreign_keys = true,
+                "--verify-checksums" => args.verify_checksums = true,
+                "--confirm-totals" => args.confirm_totals = true,
+                "--continue-on-insert-error" => args.continue_on_insert_error = true,
+                "--quarantine" => args.quarantine = Some(value(&mut it, &a)),
+                "--audit-log" => args.audit_log = Some(value(&mut it, &a)),
+                "--prune-empty-activities" => args.options.prune_empty_activities = true,
+                "--dry-run" => args.dry_run = true,
+                "--emit-sql" => args.emit_sql = Some(value(&mut it, &a)),
+                "--output-sql-schema-only" => args.schema_only = Some(value(&mut it, &a)),
+                "--validate-new-against-app" => args.validate_app = Some(value(&mut it, &a)),
+                "--atomic-file" => args.atomic_file = true,
+                "--out-key" => args.out_key = Some(value(&mut it, &a)),
+                "--bench" => args.bench = true,
+                "--prefer-existing" => args.prefer_existing = true,
+                "--remap-ids" => args.remap_ids = true,
+                "--strict-types" => args.strict_types = true,
+                "--strip-suffix-glob" => args.strip_suffix = Some(value(&mut it, &a)),
+                "--transform-config" => args.transform_config = Some(value(&mut it, &a)),
+                "--merge-by-name" => args.merge_by_name = true,
+                "--on-duplicate-name" =>
+                {
+                    on_duplicate = Some(match value(&mut it, &a).as_str()
+                    {
+                        "merge"  => DuplicateName::Merge,
+                        "suffix" => DuplicateName::Suffix,
+                        "error"  => DuplicateName::Error,
+                        v        => usage_error(&format!("Unknown --on-duplicate-name: {}", v)),
+                    });
+                }
+                "--dedup-activities-by-name" =>
+                {
+                    args.dedup_activities = Some(match value(&mut it, &a).as_str()
+                    {
+                        "merge"  => ActivityDedup::Merge,
+                        "report" => ActivityDedup::Report,
+                        v        => usage_error(&format!("Unknown --dedup-activities-by-name: {}", v)),
+                    });
+                }
+                "--copy-local" => args.copy_local = true,
+                "--check-space" => args.check_space = true,
+                "--skip-space-check" => args.check_space = false,
+                "--force" => args.force = true,
+                "--strict" | "--abort-on-warning" => args.strict = true,
+                "-q" | "--quiet" => args.quiet = true,
+                "--json" => args.json = true,
+                "--export-weekly" => args.export_weekly = Some(value(&mut it, &a)),
+                "--verbose-sql" => args.verbose_sql = true,
+                "--db-path" => args.db_path = Some(value(&mut it, &a)),
+                "--normalize-timezone-dates" => args.normalize_timezone_dates = true,
+                "--source-timezone" => args.old_tables.source_timezone = Some(zone(&mut it, &a)),
+                "--convert-timezone" => args.convert_timezone = true,
+                "--from-tz" => from_tz = Some(zone(&mut it, &a)),
+                "--to-tz" => to_tz = Some(zone(&mut it, &a)),
+                "--since-last-run" => args.since_last_run = true,
+                "--activity-checksums" => args.activity_checksums = true,
+                "--watch" => args.watch = true,
+                "--interval" =>
+                {
+                    args.interval = number(&mut it, &a);
+                    if args.interval == 0
+                    {
+                        usage_error("--interval needs at least 1");
+                    }
+                }
+                "--merge-into" => args.merge_into = Some(value(&mut it, &a)),
+                "--synchronous" =>
+                {
+                    args.synchronous = match value(&mut it, &a).to_lowercase().as_str()
+                    {
+                        "off"    => "OFF",
+                        "normal" => "NORMAL",
+                        "full"   => "FULL",
+                        v        => usage_error(&format!("Unknown --synchronous value: {}", v)),
+                    }
+                }
+                "--no-create" => args.no_create = true,
+                "--stamp-migrated" => args.stamp_migrated = true,
+                "--store-minutes" => args.store_minutes = true,
+                "--limit" => args.options.limit = Some(number(&mut it, &a)),
+                "--limit-activities" => args.options.limit_activities = Some(number(&mut it, &a)),
+                "--sample" => args.options.sample = Some(number(&mut it, &a)),
+                "--sample-seed" => args.options.sample_seed = number(&mut it, &a),
+                "--activity" => args.options.activity = Some(value(&mut it, &a)),
+                "--include-name" => args.options.include_name.push(value(&mut it, &a)),
+                "--exclude-name" => args.options.exclude_name.push(value(&mut it, &a)),
+                "--list-activities" => args.list_activities = true,
+                "--stats" => args.stats = true,
+                "--diff-only" =>
+                {
+                    let a = value(&mut it, &a);
+                    let b = value(&mut it, "--diff-only");
+                    args.diff_only = Some((a, b));
+                }
+                "--name-encoding-report" => args.name_encoding_report = true,
+                "--report-source-dups" => args.report_source_dups = true,
+                "--make-fixture" => args.make_fixture = Some(value(&mut it, &a)),
+                "--format" =>
+                {
+                    args.format = match value(&mut it, &a).as_str()
+                    {
+                        "table" => OutputFormat::Table,
+                        "csv"   => OutputFormat::Csv,
+                        "json"  => OutputFormat::Json,
+                        v       => usage_error(&format!("Unknown output format: {}", v)),
+                    }
+                }
+                "--report" =>
+                {
+                    args.report = match value(&mut it, &a).as_str()
+                    {
+                        "monthly" => Some(Report::Monthly),
+                        v         => usage_error(&format!("Unknown report: {}", v)),
+                    }
+                }
+                "--list-backups" => args.list_backups = true,
+                "--seed-for-tests" => args.seed_for_tests = Some(number(&mut it, &a)),
+                "--print-config" => args.print_config = true,
+                "-V" | "--version" =>
+                {
+                    println!("timetracker_transitiontool {}", new::tool_version());
+                    process::exit(0);
+                }
+                "-h" | "--help" =>
+                {
+                    println!("{}", USAGE);
+                    process::exit(0);
+                }
+                "doctor" if args.old_path.is_none() && !args.doctor => args.doctor = true,
+                _ if a.starts_with('-') =>
+                    usage_error(&format!("Unknown argument: {}", a)),
+                _ if args.old_path.is_none() => args.old_path = Some(a),
+                _ => usage_error(&format!("Unexpected argument: {}", a)),
+            }
+        }
+
+        match (args.normalize_timezone_dates, args.old_tables.source_timezone.is_some())
+        {
+            (true, false) => usage_error("--normalize-timezone-dates needs --source-timezone"),
+            (false, true) => usage_error("--source-timezone only applies with --normalize-timezone-dates"),
+            _             => {}
+        }
+        match (args.convert_timezone, from_tz, to_tz)
+        {
+            (true, _, _) if args.normalize_timezone_dates =>
+                usage_error("--convert-timezone and --normalize-timezone-dates both pick the \
+                             zone of the days, use one"),
+            (true, Some(from), Some(to)) =>
+            {
+                args.old_tables.datetime_timezone = Some(from);
+                args.old_tables.source_timezone   = Some(to);
+            }
+            (true, _, _)                 => usage_error("--convert-timezone needs --from-tz and --to-tz"),
+            (false, None, None)          => {}
+            (false, _, _)                =>
+                usage_error("--from-tz and --to-tz only apply with --convert-timezone"),
+        }
+        match (args.merge_by_name, args.dedup_activities, on_duplicate)
+        {
+            (_, Some(ActivityDedup::Merge), Some(_)) =>
+                usage_error("--dedup-activities-by-name merge always merges, --on-duplicate-name doesn't go with it"),
+            (_, Some(ActivityDedup::Merge), None)    => args.on_duplicate_name = DuplicateName::Merge,
+            (false, _, Some(_))                      =>
+                usage_error("--on-duplicate-name only applies with --merge-by-name"),
+            (_, _, p)                                => args.on_duplicate_name = p.unwrap_or_default(),
+        }
+        if args.atomic_file && args.emit_sql.is_some()
+        {
+            usage_error("--atomic-file and --emit-sql don't go together, --emit-sql \
+                         doesn't write to the new db");
+        }
+        if args.out_key.is_some() && !cfg!(feature = "sqlcipher")
+        {
+            usage_error("--out-key needs a build with sqlcipher: cargo build --features sqlcipher");
+        }
+        if args.bench
+        {
+            for (set, flag) in [(args.atomic_file, "--atomic-file"), (args.emit_sql.is_some(), "--emit-sql"),
+                                (args.dry_run, "--dry-run"), (args.watch, "--watch")]
+            {
+                if set
+                {
+                    usage_error(&format!("--bench migrates into memory, {} doesn't go with it", flag));
+                }
+            }
+        }
+        match (&args.old_activities_db, &args.old_tables.history_db)
+        {
+            (Some(p), Some(_)) =>
+            {
+                if args.old_path.is_some()
+                {
+                    usage_error("--old-activities-db and --old-history-db replace OLD_PATH, don't give both");
+                }
+                if args.input_format != InputFormat::Sqlite
+                {
+                    usage_error("--old-activities-db and --old-history-db read sqlite dbs, not json or sql");
+                }
+                for (set, flag) in [(args.copy_local, "--copy-local"), (args.make_fixture.is_some(), "--make-fixture")]
+                {
+                    if set
+                    {
+                        usage_error(&format!("{} takes a single old db, not one split in two files", flag));
+                    }
+                }
+                args.old_path = Some(p.clone());
+            }
+            (None, None) => (),
+            _            => usage_error("--old-activities-db and --old-history-db go together"),
+        }
+        if args.old_path.is_none()
+        {
+            if let Some(p) = std::env::var("TT_OLD_DB").ok().filter(|p| !p.is_empty())
+            {
+                args.old_path          = Some(p);
+                args.old_path_from_env = true;
+            }
+        }
+        if args.make_fixture.is_some() && args.input_format == InputFormat::Json
+        {
+            usage_error("--make-fixture copies an old sqlite db (or .sql dump), not json");
+        }
+        if args.watch && args.old_path.is_none()
+        {
+            usage_error("--watch needs OLD_PATH (or TT_OLD_DB), there's no one to ask for it every cycle");
+        }
+        if args.watch || args.since_last_run
+        {
+            args.since_last_run        = true;
+            args.prefer_existing       = true;
+            args.options.dedup_history = true;
+        }
+        if args.zero_fill_gaps && args.since_last_run
+        {
+            usage_error("--zero-fill-gaps doesn't go with --since-last-run (or --watch), \
+                         its 0 hours rows would overwrite days logged in the new app since");
+        }
+        if args.only_recompute
+        {
+            for (set, flag) in [(args.verify_checksums, "--verify-checksums"), (args.emit_sql.is_some(), "--emit-sql")]
+            {
+                if set
+                {
+                    usage_error(&format!("--only-recompute takes the totals from the written history, \
+                                          {} doesn't go with it", flag));
+                }
+            }
+            args.old_tables.ignore_hours_total = true;
+            args.options.recompute_totals      = false;
+        }
+        if args.activity_checksums
+        {
+            if !args.since_last_run
+            {
+                usage_error("--activity-checksums only applies with --since-last-run or --watch");
+            }
+            if args.remap_ids || args.merge_by_name || args.dedup_activities.is_some()
+            {
+                usage_error("--activity-checksums keeps the hashes by old id, --remap-ids, \
+                             --merge-by-name and --dedup-activities-by-name change them");
+            }
+        }
+        if let Some(p) = &args.merge_into
+        {
+            if args.db_path.as_ref().is_some_and(|d| d != p)
+            {
+                usage_error("--merge-into and --db-path name different new dbs");
+            }
+            args.db_path         = Some(p.clone());
+            args.prefer_existing = true;
+        }
+
+        args
+    }
+
+    /*
+     * --print-config, (flags, value) of every option; aliases separated by
+     * ", ", the first of them the one shown
+     */
+
+    pub fn settings ( &self ) -> Vec<(&'static str, String)>
+    {
+        fn opt<T : std::fmt::Debug> ( v : &Option<T> ) -> String
+        {
+            v.as_ref().map(|v| format!("{:?}", v)).unwrap_or_else(|| "-".to_string())
+        }
+        let o = &self.options;
+        let t = &self.old_tables;
+
+        vec![
+            ("--input-format", format!("{:?}", self.input_format)),
+            ("--old-activities-table", t.activities.clone()),
+            ("--old-history-table", t.history.clone()),
+            ("--old-history-db", opt(&t.history_db)),
+            ("--from-version", opt(&t.layout)),
+            ("--old-hours-column", t.hours_column.clone()),
+            ("--old-date-column", t.date_column.clone()),
+            ("--extra-column", format!("{:?}", self.extra_columns)),
+            ("--require-history", self.require_history.to_string()),
+            ("--normalize-timezone-dates", self.normalize_timezone_dates.to_string()),
+            ("--source-timezone", opt(&t.source_timezone)),
+            ("--convert-timezone", self.convert_timezone.to_string()),
+            ("--from-tz", opt(&t.datetime_timezone)),
+            ("--strict-types", self.strict_types.to_string()),
+            ("--strict-names", self.strict_names.to_string()),
+            ("--max-name-length", opt(&self.max_name_length)),
+            ("--truncate-names", self.truncate_names.to_string()),
+            ("--rename-activity", format!("{:?}", self.rename_activity)),
+            ("--strip-suffix-glob", opt(&self.strip_suffix)),
+            ("--transform-config", opt(&self.transform_config)),
+            ("--skip-inactive", o.skip_inactive.to_string()),
+            ("--include-name", format!("{:?}", o.include_name)),
+            ("--exclude-name", format!("{:?}", o.exclude_name)),
+            ("--activity", opt(&o.activity)),
+            ("--trust, --trust-numeric, --trust-string", opt(&o.trust_dates)),
+            ("--year-range", format!("{}..{}", o.years.0, o.years.1)),
+            ("--invalid-date-policy", if o.skip_invalid_dates { "skip" } else { "error" }.to_string()),
+            ("--added-fallback", format!("{:?}", o.added_fallback)),
+            ("--compact-history", o.compact_history.to_string()),
+            ("--merge-source-dups", o.merge_source_dups.to_string()),
+            ("--dedup-history", o.dedup_history.to_string()),
+            ("--zero-fill-gaps", self.zero_fill_gaps.to_string()),
+            ("--only-recompute", self.only_recompute.to_string()),
+            ("--round-mode", format!("{:?}", o.round_mode)),
+            ("--limit", opt(&o.limit)),
+            ("--limit-activities", opt(&o.limit_activities)),
+            ("--sample", opt(&o.sample)),
+            ("--sample-seed", o.sample_seed.to_string()),
+            ("--db-path", opt(&self.db_path)),
+            ("--since-last-run", self.since_last_run.to_string()),
+            ("--activity-checksums", self.activity_checksums.to_string()),
+            ("--watch", self.watch.to_string()),
+            ("--interval", self.interval.to_string()),
+            ("--merge-into", opt(&self.merge_into)),
+            ("--synchronous", self.synchronous.to_string()),
+            ("--atomic-file", self.atomic_file.to_string()),
+            ("--out-key", self.out_key.as_ref().map(|_| "(set)").unwrap_or("-").to_string()),
+            ("--no-create", self.no_create.to_string()),
+            ("--prune-empty-activities", o.prune_empty_activities.to_string()),
+            ("--stamp-migrated", self.stamp_migrated.to_string()),
+            ("--store-minutes", self.store_minutes.to_string()),
+            ("--export-weekly", opt(&self.export_weekly)),
+            ("--json", self.json.to_string()),
+            ("--verbose-sql", self.verbose_sql.to_string()),
+            ("--check-foreign-keys, --verify-foreign-keys", self.check_foreign_keys.to_string()),
+            ("--continue-on-insert-error", self.continue_on_insert_error.to_string()),
+            ("--quarantine", opt(&self.quarantine)),
+            ("--audit-log", opt(&self.audit_log)),
+            ("--confirm-totals", self.confirm_totals.to_string()),
+            ("--verify-checksums", self.verify_checksums.to_string()),
+            ("--prefer-existing", self.prefer_existing.to_string()),
+            ("--merge-by-name", self.merge_by_name.to_string()),
+            ("--on-duplicate-name", self.on_duplicate_name.name().to_string()),
+            ("--dedup-activities-by-name", opt(&self.dedup_activities)),
+            ("--remap-ids", self.remap_ids.to_string()),
+            ("--emit-sql", opt(&self.emit_sql)),
+            ("--output-sql-schema-only", opt(&self.schema_only)),
+            ("--validate-new-against-app", opt(&self.validate_app)),
+            ("--dry-run", self.dry_run.to_string()),
+            ("--check-space, --skip-space-check", self.check_space.to_string()),
+            ("--parallel-verify", opt(&self.parallel_verify)),
+            ("--strict, --abort-on-warning", self.strict.to_string()),
+            ("--force", self.force.to_string()),
+            ("--copy-local", self.copy_local.to_string()),
+            ("--page-size", opt(&self.page_size)),
+            ("--max-memory", self.max_memory.to_string()),
+            ("--bench", self.bench.to_string()),
+            ("-q, --quiet", self.quiet.to_string()),
+            ("--format", format!("{:?}", self.format)),
+            ("--seed-for-tests", opt(&self.seed_for_tests)),
+        ]
+    }
+
+    /*
+     * inspection modes only read and print, no migration takes place
+     */
+
+    pub fn inspecting ( &self ) -> bool
+    {
+        self.list_activities || self.stats || self.doctor || self.name_encoding_report
+            || self.report_source_dups
+            || self.dedup_activities == Some(ActivityDedup::Report)
+            || self.make_fixture.is_some()
+    }
+}
@@ -0,0 +1,126 @@
+use std::fs;
+use std::path;
+
+use rusqlite::Connection;
+
+use crate::error;
+use crate::error::MigrationError;
+
+/*
+ * --atomic-file, the run writes to a temp file next to the new db and
+ * renames it over the db once everything (inserts, checks) went through;
+ * until then the db is left as it was, whatever happens to the run
+ *
+ * an existing db gets copied to the temp file first, its -wal along with
+ * it (the run holds the lock, nobody else writes meanwhile). dropped
+ * without commit the temp file is removed again; one left behind by a
+ * killed run is named .<db>.tmp-<pid> and can be deleted
+ */
+
+const SIDECARS : [&str; 3] = ["-wal", "-shm", "-journal"];
+
+pub struct AtomicFile {
+    pub temp : path::PathBuf,
+    target   : path::PathBuf,
+    done     : bool,
+}
+
+fn with_suffix ( p : &path::Path, suffix : &str ) -> path::PathBuf
+{
+    let mut s = p.as_os_str().to_owned();
+    s.push(suffix);
+    path::PathBuf::from(s)
+}
+
+impl AtomicFile
+{
+    pub fn new ( dbpath : &path::Path, exists : bool ) -> Result<AtomicFile, MigrationError>
+    {
+        let name = dbpath.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+        let temp = dbpath.with_file_name(format!(".{}.tmp-{}", name, std::process::id()));
+
+        let atomic = AtomicFile { temp, target : dbpath.to_path_buf(), done : false };
+        atomic.remove_temp();
+
+        if exists
+        {
+            fs::copy(dbpath, &atomic.temp).map_err(|e| MigrationError::Io(format!(
+                "can't copy {:?} to {:?}: {}", dbpath, atomic.temp, e)))?;
+
+            let wal = with_suffix(dbpath, "-wal");
+            if wal.exists()
+            {
+                fs::copy(&wal, with_suffix(&atomic.temp, "-wal"))?;
+            }
+        }
+
+        Ok(atomic)
+    }
+
+    /*
+     * the rename replaces the db in one step; syncing the folder makes it
+     * survive a crash right after
+     *
+     * a -wal of the db is checkpointed into it first (the temp file has a
+     * copy), so a failed rename still leaves the db whole; the sidecars
+     * only go once the temp file is in place, they belong to the old db
+     */
+
+    pub fn commit ( mut self ) -> Result<(), MigrationError>
+    {
+        if with_suffix(&self.target, "-wal").exists()
+        {
+            self.checkpoint_target()?;
+        }
+        fs::rename(&self.temp, &self.target).map_err(|e| MigrationError::Io(format!(
+            "can't rename {:?} to {:?}: {}", self.temp, self.target, e)))?;
+        self.done = true;
+
+        for s in SIDECARS
+        {
+            let _ = fs::remove_file(with_suffix(&self.target, s));
+        }
+
+        if let Some(dir) = self.target.parent().filter(|d| !d.as_os_str().is_empty())
+        {
+            fs::File::open(dir).and_then(|d| d.sync_all())?;
+        }
+
+        Ok(())
+    }
+
+    fn checkpoint_target ( &self ) -> Result<(), MigrationError>
+    {
+        let db = Connection::open(&self.target).map_err(error::io)?;
+        let busy : i64 = db.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| row.get(0))
+            .map_err(error::io)?;
+
+        if busy != 0
+        {
+            return Err(MigrationError::Io(format!(
+                "{:?} is in use, its -wal can't be checkpointed; the db is left as it was", self.target)));
+        }
+
+        Ok(())
+    }
+
+    fn remove_temp ( &self )
+    {
+        let _ = fs::remove_file(&self.temp);
+        for s in SIDECARS
+        {
+            let _ = fs::remove_file(with_suffix(&self.temp, s));
+        }
+    }
+}
+
+impl Drop for AtomicFile
+{
+    fn drop ( &mut self )
+    {
+        if !self.done
+        {
+            self.remove_temp();
+        }
+    }
+}
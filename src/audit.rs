@@ -0,0 +1,55 @@
+use std::fs;
+use std::io::Write;
+
+use serde_json::Map;
+use serde_json::Value;
+
+use crate::error::MigrationError;
+
+/*
+ * --audit-log, one json object per line for everything the run decided,
+ * appended so the log of several runs can be kept in one file
+ *
+ * every line has "time" (rfc 3339) and "event", plus the event's fields;
+ * without --audit-log recording does nothing
+ */
+
+pub struct AuditLog {
+    file : Option<fs::File>,
+    path : String,
+}
+
+impl AuditLog
+{
+    pub fn open ( path : &Option<String> ) -> Result<AuditLog, MigrationError>
+    {
+        let file = match path
+        {
+            Some(p) => Some(fs::OpenOptions::new().create(true).append(true).open(p)
+                .map_err(|e| MigrationError::Io(format!(
+                    "can't open audit log {:?}: {}", p, e)))?),
+            None    => None,
+        };
+
+        Ok(AuditLog { file, path : path.clone().unwrap_or_default() })
+    }
+
+    /// `fields` has to be a json object, its keys are added to the line.
+    pub fn record ( &mut self, event : &str, fields : Value ) -> Result<(), MigrationError>
+    {
+        let Some(file) = &mut self.file else { return Ok(()) };
+
+        let mut line = Map::new();
+        line.insert("time".to_string(),
+                    Value::String(chrono::Local::now().to_rfc3339()));
+        line.insert("event".to_string(), Value::String(event.to_string()));
+        if let Value::Object(m) = fields
+        {
+            line.extend(m);
+        }
+
+        writeln!(file, "{}", Value::Object(line))
+            .map_err(|e| MigrationError::Io(format!(
+                "can't write audit log {:?}: {}", self.path, e)))
+    }
+}
@@ -0,0 +1,111 @@
+use std::fs;
+use std::path;
+
+use chrono::DateTime;
+use chrono::Local;
+use chrono::NaiveDateTime;
+use chrono::TimeZone;
+use serde_json::json;
+use serde_json::Value;
+
+use crate::args::OutputFormat;
+use crate::error::MigrationError;
+use crate::inspect;
+
+/*
+ * --list-backups, copies of the new db next to it named <db>.bak-<time>,
+ * newest first
+ *
+ * <time> is read as YYYYMMDD-HHMMSS (also with T, or dashes in the date),
+ * rfc 3339 or unix seconds; a backup whose suffix is none of these goes
+ * by its modification time instead
+ */
+
+const SUFFIX_FORMATS : [&str; 3] = [
+    "%Y%m%d-%H%M%S",
+    "%Y%m%dT%H%M%S",
+    "%Y-%m-%d-%H%M%S",
+];
+
+fn suffix_time ( suffix : &str ) -> Option<DateTime<Local>>
+{
+    SUFFIX_FORMATS.iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(suffix, f).ok())
+        .and_then(|dt| Local.from_local_datetime(&dt).earliest())
+        .or_else(|| DateTime::parse_from_rfc3339(suffix).ok().map(|dt| dt.with_timezone(&Local)))
+        .or_else(|| suffix.parse::<i64>().ok()
+            .and_then(|s| DateTime::from_timestamp(s, 0))
+            .map(|dt| dt.with_timezone(&Local)))
+}
+
+pub struct Backup {
+    pub path      : path::PathBuf,
+    pub time      : DateTime<Local>,
+    pub from_name : bool,			// time read from the suffix, not the mtime
+    pub size      : u64,
+}
+
+pub fn find ( dbpath : &path::Path ) -> Result<Vec<Backup>, MigrationError>
+{
+    let dir = match dbpath.parent()
+    {
+        Some(d) if !d.as_os_str().is_empty() => d.to_path_buf(),
+        _                                    => path::PathBuf::from("."),
+    };
+    let prefix = format!("{}.bak-",
+        dbpath.file_name().map(|n| n.to_string_lossy()).unwrap_or_default());
+
+    let mut backups = Vec::new();
+    if !dir.is_dir()
+    {
+        return Ok(backups);
+    }
+
+    for entry in fs::read_dir(&dir)?
+    {
+        let entry = entry?;
+        let name  = entry.file_name().to_string_lossy().into_owned();
+        let Some(suffix) = name.strip_prefix(&prefix) else { continue };
+
+        let meta = entry.metadata()?;
+        if !meta.is_file()
+        {
+            continue;
+        }
+
+        let (time, from_name) = match suffix_time(suffix)
+        {
+            Some(t) => (t, true),
+            None    => (meta.modified().map(DateTime::<Local>::from)?, false),
+        };
+        backups.push(Backup { path : entry.path(), time, from_name, size : meta.len() });
+    }
+
+    backups.sort_by(|a, b| b.time.cmp(&a.time).then_with(|| b.path.cmp(&a.path)));
+
+    Ok(backups)
+}
+
+pub fn list ( format : &OutputFormat, dbpath : &path::Path ) -> Result<(), MigrationError>
+{
+    let backups = find(dbpath)?;
+
+    let rows : Vec<Vec<Value>> = backups.iter()
+        .map(|b| vec![
+            json!(b.path.display().to_string()),
+            json!(b.time.format("%Y-%m-%d %H:%M:%S").to_string()),
+            json!(if b.from_name { "name" } else { "mtime" }),
+            json!(b.size),
+        ])
+        .collect();
+
+    inspect::print_rows(format, &["backup", "time", "from", "bytes"], &rows);
+
+    if let OutputFormat::Table = format
+    {
+        println!();
+        println!("{} backups of {:?}", backups.len(), dbpath);
+    }
+
+    Ok(())
+}
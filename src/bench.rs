@@ -0,0 +1,65 @@
+use std::time;
+
+use serde_json::json;
+use serde_json::Value;
+
+use crate::args::OutputFormat;
+use crate::inspect;
+
+/*
+ * wall time of the phases of a run, each mark ends the phase running
+ * since the one before (or since the old db's path is known); printed by
+ * --bench, which migrates into an in-memory db
+ *
+ * streamed history is read while inserting, its reading counts as insert
+ */
+
+pub struct Phases {
+    last   : time::Instant,
+    phases : Vec<(&'static str, time::Duration)>,
+}
+
+impl Phases
+{
+    pub fn new () -> Phases
+    {
+        Phases { last : time::Instant::now(), phases : Vec::new() }
+    }
+
+    pub fn mark ( &mut self, phase : &'static str )
+    {
+        let now = time::Instant::now();
+        self.phases.push((phase, now - self.last));
+        self.last = now;
+    }
+
+    pub fn print ( &self, format : &OutputFormat, history_rows : usize )
+    {
+        let total : f64 = self.phases.iter().map(|(_, d)| d.as_secs_f64()).sum();
+        let share = |d : f64| match total > 0.
+        {
+            true  => format!("{:.1}%", 100. * d / total),
+            false => "-".to_string(),
+        };
+
+        let mut rows : Vec<Vec<Value>> = self.phases.iter()
+            .map(|(p, d)| vec![json!(p), json!(format!("{:.3}", d.as_secs_f64())),
+                               json!(share(d.as_secs_f64()))])
+            .collect();
+        rows.push(vec![json!("total"), json!(format!("{:.3}", total)), json!(share(total))]);
+
+        println!("Bench: time by phase, into an in-memory db");
+        inspect::print_rows(format, &["phase", "seconds", "share"], &rows);
+
+        if let Some((_, insert)) = self.phases.iter().find(|(p, _)| *p == "insert")
+        {
+            let secs = insert.as_secs_f64();
+            println!("Insert: {} history rows in {:.3} s{}", history_rows, secs,
+                     match secs > 0.
+                     {
+                         true  => format!(", {:.0} rows/s", history_rows as f64 / secs),
+                         false => String::new(),
+                     });
+        }
+    }
+}
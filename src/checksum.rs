@@ -0,0 +1,280 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::path;
+use std::thread;
+
+use chrono::Datelike;
+use chrono::NaiveDate;
+use rusqlite::Connection;
+use rusqlite::OpenFlags;
+
+use crate::error;
+use crate::error::MigrationError;
+use crate::new;
+use crate::round6;
+use crate::old::OldActivity;
+use crate::old::OldHistory;
+
+/*
+ * Content hashes for --verify-checksums
+ *
+ * each row is serialized field by field the way it should end up in the
+ * new db (activities: id, name, added, hourstotal; history: id, year,
+ * month, day, isoweek, isoweekyear, hoursonday, date) and fed to a
+ * 64 bit FNV-1a hash in row order; the expected side is computed from the
+ * old rows with the same transformations the insert applies (hours
+ * rounded to 6 decimals, isoweekyear derived from the date), the actual
+ * side by reading back the new db
+ *
+ * activities are hashed ordered by id, history in insertion order
+ */
+
+pub const ALGORITHM : &str = "fnv1a64";
+
+const FNV_OFFSET : u64 = 0xcbf29ce484222325;
+const FNV_PRIME  : u64 = 0x100000001b3;
+
+pub struct Checksum {
+    state : u64,
+    pub rows  : usize,
+}
+
+impl Checksum
+{
+    pub fn new() -> Checksum
+    {
+        Checksum { state : FNV_OFFSET, rows : 0 }
+    }
+
+    fn feed ( &mut self, bytes : &[u8] )
+    {
+        for b in bytes
+        {
+            self.state ^= *b as u64;
+            self.state = self.state.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    /*
+     * fields separated by 0x1f, rows terminated by 0x1e, so moving text
+     * between fields or rows changes the hash
+     */
+
+    fn row ( &mut self, fields : &[String] )
+    {
+        for (i, f) in fields.iter().enumerate()
+        {
+            if i > 0 { self.feed(&[0x1f]); }
+            self.feed(f.as_bytes());
+        }
+        self.feed(&[0x1e]);
+        self.rows += 1;
+    }
+
+    pub fn add_activity ( &mut self, e : &OldActivity )
+    {
+        self.row(&[
+            e.id.to_string(),
+            e.name.clone(),
+            e.added_when.clone(),
+            round6(e.hours_total).to_string(),
+        ]);
+    }
+
+    pub fn add_history ( &mut self, e : &OldHistory )
+    {
+        let isoweekyear = u32::try_from(e.month).ok()
+            .zip(u32::try_from(e.day).ok())
+            .and_then(|(m, d)| NaiveDate::from_ymd_opt(e.year, m, d))
+            .map(|d| d.iso_week().year().to_string())
+            .unwrap_or_default();
+
+        self.history_row(e, isoweekyear);
+    }
+
+    fn history_row ( &mut self, e : &OldHistory, isoweekyear : String )
+    {
+        self.row(&[
+            e.id_activity.to_string(),
+            e.year.to_string(),
+            e.month.to_string(),
+            e.day.to_string(),
+            e.weeknumber.to_string(),
+            isoweekyear,
+            round6(e.hours_on_day).to_string(),
+            e.date.clone(),
+        ]);
+    }
+
+    /// As stored in the meta table, eg `fnv1a64:00c0ffee00c0ffee`.
+    pub fn value ( &self ) -> String
+    {
+        format!("{}:{:016x}", ALGORITHM, self.state)
+    }
+}
+
+/*
+ * --activity-checksums, one hash per activity over its row and all of its
+ * history (in row order) as read from the old db, kept in the new db's
+ * meta table from run to run; an activity whose hash is the same as last
+ * time has neither changed nor got new history
+ */
+
+pub fn per_activity ( oldact : &[OldActivity], oldhis : &[OldHistory] ) -> BTreeMap<i32, String>
+{
+    let mut sums : HashMap<i32, Checksum> = oldact.iter()
+        .map(|e| {
+            let mut c = Checksum::new();
+            c.add_activity(e);
+            (e.id, c)
+        })
+        .collect();
+
+    for e in oldhis
+    {
+        if let Some(c) = sums.get_mut(&e.id_activity) { c.add_history(e); }
+    }
+
+    sums.into_iter().map(|(id, c)| (id, c.value())).collect()
+}
+
+/*
+ * read back what got written for the activities in `ids`, rows of the
+ * same activities already in the db before this run would be included
+ * too (an existing db can't hold them though, ids are unique)
+ */
+
+pub fn of_new_db ( db_new : &Connection, ids : &HashSet<i32> )
+    -> Result<(Checksum, Checksum), MigrationError>
+{
+    of_new_db_in(db_new, ids, (i32::MIN, i32::MAX))
+}
+
+fn of_new_db_in ( db_new : &Connection, ids : &HashSet<i32>, (lo, hi) : (i32, i32) )
+    -> Result<(Checksum, Checksum), MigrationError>
+{
+    let mut act = Checksum::new();
+    let mut his = Checksum::new();
+
+    let mut stmt = db_new
+        .prepare("SELECT id, name, added, hourstotal FROM tt_activities
+                  WHERE id BETWEEN ?1 AND ?2 ORDER BY id")
+        .map_err(error::partial)?;
+
+    let iter = stmt.query_map([lo, hi], |row| {
+        Ok(OldActivity {
+            id           : row.get(0)?,
+            group_id     : 0,
+            name         : row.get(1)?,
+            added_when   : row.get(2)?,
+            is_activated : 1,
+            hours_total  : row.get(3)?,
+            extra        : Vec::new(),
+        })
+    }).map_err(error::partial)?;
+
+    for e in iter
+    {
+        let e = e.map_err(error::partial)?;
+        if ids.contains(&e.id) { act.add_activity(&e); }
+    }
+
+    let mut stmt = db_new
+        .prepare("SELECT id, year, month, day, isoweek, isoweekyear, hoursonday, date
+                  FROM tt_history WHERE id BETWEEN ?1 AND ?2 ORDER BY rowid")
+        .map_err(error::partial)?;
+
+    let iter = stmt.query_map([lo, hi], |row| {
+        Ok((row.get::<_, i32>(5)?, OldHistory {
+            id_activity  : row.get(0)?,
+            year         : row.get(1)?,
+            month        : row.get(2)?,
+            day          : row.get(3)?,
+            weeknumber   : row.get(4)?,
+            hours_on_day : row.get(6)?,
+            date         : row.get(7)?,
+        }))
+    }).map_err(error::partial)?;
+
+    for e in iter
+    {
+        let (isoweekyear, e) = e.map_err(error::partial)?;
+        if ids.contains(&e.id_activity) { his.history_row(&e, isoweekyear.to_string()); }
+    }
+
+    Ok((act, his))
+}
+
+/*
+ * --parallel-verify: the activity ids split into consecutive ranges with
+ * about the same number of activities, hashed per range; the expected
+ * side is fed while inserting, the new db is read back by one thread per
+ * range, each with its own read-only connection (a connection can't be
+ * shared between threads)
+ */
+
+pub struct Ranges {
+    pub bounds : Vec<(i32, i32)>,
+    pub act    : Vec<Checksum>,
+    pub his    : Vec<Checksum>,
+}
+
+impl Ranges
+{
+    pub fn new ( ids : &[i32], n : usize ) -> Ranges
+    {
+        let mut sorted = ids.to_vec();
+        sorted.sort();
+        sorted.dedup();
+
+        let size = sorted.len().div_ceil(n).max(1);
+        let bounds : Vec<(i32, i32)> = sorted.chunks(size)
+            .map(|c| (c[0], c[c.len() - 1]))
+            .collect();
+
+        Ranges {
+            act    : bounds.iter().map(|_| Checksum::new()).collect(),
+            his    : bounds.iter().map(|_| Checksum::new()).collect(),
+            bounds,
+        }
+    }
+
+    fn index ( &self, id : i32 ) -> Option<usize>
+    {
+        let i = self.bounds.partition_point(|(_, hi)| *hi < id);
+        self.bounds.get(i).filter(|(lo, _)| *lo <= id).map(|_| i)
+    }
+
+    pub fn add_activity ( &mut self, e : &OldActivity )
+    {
+        if let Some(i) = self.index(e.id) { self.act[i].add_activity(e); }
+    }
+
+    pub fn add_history ( &mut self, e : &OldHistory )
+    {
+        if let Some(i) = self.index(e.id_activity) { self.his[i].add_history(e); }
+    }
+
+    /*
+     * (activities, history) of every range as read back from the new db
+     */
+
+    pub fn of_new_db ( &self, path : &path::Path, key : Option<&str>, ids : &HashSet<i32> )
+        -> Result<Vec<(Checksum, Checksum)>, MigrationError>
+    {
+        thread::scope(|scope| {
+            let workers : Vec<_> = self.bounds.iter()
+                .map(|range| scope.spawn(move || {
+                    let conn = new::open_keyed(path, OpenFlags::SQLITE_OPEN_READ_ONLY, key)?;
+                    of_new_db_in(&conn, ids, *range)
+                }))
+                .collect();
+
+            workers.into_iter()
+                .map(|w| w.join().unwrap_or_else(|_| Err(MigrationError::Partial(
+                    "a verify thread panicked".to_string()))))
+                .collect()
+        })
+    }
+}
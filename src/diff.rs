@@ -0,0 +1,210 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use rusqlite::Connection;
+use serde_json::json;
+use serde_json::Value;
+
+use crate::args::OutputFormat;
+use crate::error;
+use crate::error::MigrationError;
+use crate::inspect;
+use crate::old;
+use crate::old::OldActivity;
+use crate::old::OldHistory;
+use crate::old::OldTables;
+use crate::round6;
+
+/*
+ * --dry-run against an existing new db: what the migration would change
+ *
+ * activities are matched by id; history rows by activity, date and
+ * hours (after rounding), a row equal to one already in the new db is
+ * not counted as added (a real run would still insert it a second time)
+ */
+
+pub struct Diff {
+    activities : HashMap<i32, (String, f64)>,	// of the new db
+    history    : HashSet<(i32, String, String)>,
+    pub history_added    : usize,
+    pub history_existing : usize,
+}
+
+fn key ( id : i32, date : String, hours : f64 ) -> (i32, String, String)
+{
+    (id, date, round6(hours).to_string())
+}
+
+impl Diff
+{
+    pub fn read ( db_new : &Connection ) -> Result<Diff, MigrationError>
+    {
+        let mut stmt = db_new
+            .prepare("SELECT id, name, hourstotal FROM tt_activities")
+            .map_err(error::schema)?;
+        let iter = stmt
+            .query_map([], |row| Ok((row.get(0)?, (row.get(1)?, row.get(2)?))))
+            .map_err(error::schema)?;
+
+        let mut activities = HashMap::new();
+        for e in iter
+        {
+            let (id, v) = e.map_err(error::schema)?;
+            activities.insert(id, v);
+        }
+
+        let mut stmt = db_new
+            .prepare("SELECT id, date, hoursonday FROM tt_history")
+            .map_err(error::schema)?;
+        let iter = stmt
+            .query_map([], |row| Ok(key(row.get(0)?, row.get(1)?, row.get(2)?)))
+            .map_err(error::schema)?;
+
+        let mut history = HashSet::new();
+        for e in iter { history.insert(e.map_err(error::schema)?); }
+
+        Ok(Diff { activities, history, history_added : 0, history_existing : 0 })
+    }
+
+    pub fn history_row ( &mut self, e : &OldHistory )
+    {
+        if self.history.contains(&key(e.id_activity, e.date.clone(), e.hours_on_day))
+        {
+            self.history_existing += 1;
+        }
+        else
+        {
+            self.history_added += 1;
+        }
+    }
+
+    pub fn print ( &self, oldact : &[OldActivity] )
+    {
+        let added : Vec<&OldActivity> = oldact.iter()
+            .filter(|e| !self.activities.contains_key(&e.id))
+            .collect();
+        let changed : Vec<(&OldActivity, &(String, f64))> = oldact.iter()
+            .filter_map(|e| self.activities.get(&e.id).map(|v| (e, v)))
+            .filter(|(e, (_, hours))| round6(e.hours_total) != round6(*hours))
+            .collect();
+
+        println!();
+        println!("Dry run, nothing is written; compared to the existing new db:");
+
+        println!("    activities added : {}", added.len());
+        for e in &added
+        {
+            println!("        {:>5}  {}  ({} hours)", e.id, e.name, round6(e.hours_total));
+        }
+
+        println!("    activities with other hours : {}", changed.len());
+        for (e, (name, hours)) in &changed
+        {
+            println!("        {:>5}  {}  {} -> {} hours", e.id, name, hours, round6(e.hours_total));
+        }
+
+        println!("    history rows added : {}", self.history_added);
+        if self.history_existing > 0
+        {
+            println!("    history rows already there : {} (a real run adds them again)",
+                     self.history_existing);
+        }
+        if oldact.len() > added.len()
+        {
+            println!("NOTE: {} activities already exist by id, a real run fails on them \
+                      unless --continue-on-insert-error is set", oldact.len() - added.len());
+        }
+    }
+}
+
+/*
+ * --diff-only, what changed from one old db snapshot (a) to a later one (b)
+ *
+ * activities by id: added, removed, renamed; history by activity and
+ * day (hours of several rows on a day summed up): added, removed, hours
+ * changed
+ */
+
+fn history_by_day ( oldhis : &[OldHistory] ) -> BTreeMap<(i32, String), f64>
+{
+    let mut days = BTreeMap::new();
+    for e in oldhis
+    {
+        *days.entry((e.id_activity, e.date.clone())).or_insert(0.) += e.hours_on_day;
+    }
+    days
+}
+
+pub fn old_dbs ( format : &OutputFormat, (a, b) : (&str, &str), tables : &OldTables )
+    -> Result<(), MigrationError>
+{
+    let read = |path : &str| -> Result<(Vec<OldActivity>, Vec<OldHistory>), MigrationError> {
+        let db = old::open(path, tables)?;
+        Ok((old::read_old_activities_in(&db, tables)?, old::read_old_history_in(&db, tables)?))
+    };
+    let (act_a, his_a) = read(a)?;
+    let (act_b, his_b) = read(b)?;
+
+    let names_a : BTreeMap<i32, &str> = act_a.iter().map(|e| (e.id, e.name.as_str())).collect();
+    let names_b : BTreeMap<i32, &str> = act_b.iter().map(|e| (e.id, e.name.as_str())).collect();
+
+    let mut rows : Vec<Vec<Value>> = Vec::new();
+
+    for (id, name) in &names_b
+    {
+        match names_a.get(id)
+        {
+            None =>
+                rows.push(vec![json!("activity added"), json!(id), json!(""),
+                               json!(null), json!(name)]),
+            Some(before) if before != name =>
+                rows.push(vec![json!("activity renamed"), json!(id), json!(""),
+                               json!(before), json!(name)]),
+            Some(_) => {}
+        }
+    }
+    for (id, name) in &names_a
+    {
+        if !names_b.contains_key(id)
+        {
+            rows.push(vec![json!("activity removed"), json!(id),
+                           json!(""), json!(name), json!(null)]);
+        }
+    }
+
+    let days_a = history_by_day(&his_a);
+    let days_b = history_by_day(&his_b);
+
+    for ((id, date), hours) in &days_b
+    {
+        match days_a.get(&(*id, date.clone()))
+        {
+            None =>
+                rows.push(vec![json!("history added"), json!(id), json!(date),
+                               json!(null), json!(round6(*hours))]),
+            Some(before) if round6(*before) != round6(*hours) =>
+                rows.push(vec![json!("history changed"), json!(id), json!(date),
+                               json!(round6(*before)), json!(round6(*hours))]),
+            Some(_) => {}
+        }
+    }
+    for ((id, date), hours) in &days_a
+    {
+        if !days_b.contains_key(&(*id, date.clone()))
+        {
+            rows.push(vec![json!("history removed"), json!(id), json!(date),
+                           json!(round6(*hours)), json!(null)]);
+        }
+    }
+
+    inspect::print_rows(format, &["change", "activity", "date", "before", "after"], &rows);
+
+    if let OutputFormat::Table = format
+    {
+        println!();
+        println!("{} changes from {:?} to {:?}", rows.len(), a, b);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,204 @@
+use std::collections::HashSet;
+
+use chrono::NaiveDate;
+use rusqlite::Connection;
+use rusqlite::types::Value;
+use serde_json::json;
+
+use crate::args::OutputFormat;
+use crate::error;
+use crate::error::MigrationError;
+use crate::inspect;
+use crate::old::OldTables;
+use crate::schema;
+
+/*
+ * `doctor`, read-only health check of the old db
+ *
+ * the history is read with untyped values, so rows the normal reader
+ * would choke on (NULL hours, text in numeric fields) get reported
+ * instead of ending the run
+ */
+
+const SAMPLES : usize = 3;
+
+const CATEGORIES : [&str; 7] = [
+    "orphaned history",		// history of an activity id that doesn't exist
+    "ghost activities",		// activities without any history
+    "invalid dates",		// year/month/day or date text isn't a real day
+    "out-of-range hours",	// below 0 or above 24 on a day
+    "date mismatches",		// date text and year/month/day disagree
+    "duplicate rows",		// more than one row of an activity on a day
+    "null hours",
+];
+
+struct Finding {
+    count   : usize,
+    samples : Vec<String>,
+}
+
+fn integer ( v : &Value ) -> Option<i64>
+{
+    match v
+    {
+        Value::Integer(i)                  => Some(*i),
+        Value::Real(f) if f.fract() == 0.0 => Some(*f as i64),
+        Value::Text(t)                     => t.trim().parse().ok(),
+        _                                  => None,
+    }
+}
+
+fn real ( v : &Value ) -> Option<f64>
+{
+    match v
+    {
+        Value::Integer(i) => Some(*i as f64),
+        Value::Real(f)    => Some(*f),
+        Value::Text(t)    => t.trim().parse().ok(),
+        _                 => None,
+    }
+}
+
+fn shown ( v : &Value ) -> String
+{
+    match v
+    {
+        Value::Null       => "NULL".to_string(),
+        Value::Integer(i) => i.to_string(),
+        Value::Real(f)    => f.to_string(),
+        Value::Text(t)    => format!("{:?}", t),
+        Value::Blob(b)    => format!("<{} bytes>", b.len()),
+    }
+}
+
+pub fn run ( format : &OutputFormat, db_old : &Connection, tables : &OldTables )
+    -> Result<(), MigrationError>
+{
+    let mut findings : Vec<Finding> = CATEGORIES.iter()
+        .map(|_| Finding { count : 0, samples : Vec::new() })
+        .collect();
+
+    let mut found = |category : usize, sample : String| {
+        let f = &mut findings[category];
+        f.count += 1;
+        if f.samples.len() < SAMPLES { f.samples.push(sample); }
+    };
+
+    /*
+     * activities, only their ids and names matter here
+     */
+
+    let mut stmt = db_old
+        .prepare(&format!("SELECT id, name FROM {}", schema::quote_ident(&tables.activities)))
+        .map_err(error::schema)?;
+    let iter = stmt
+        .query_map([], |row| Ok((row.get::<_, Value>(0)?, row.get::<_, Value>(1)?)))
+        .map_err(error::schema)?;
+
+    let mut activities : Vec<(i64, String)> = Vec::new();
+    for e in iter
+    {
+        let (id, name) = e.map_err(error::schema)?;
+        activities.push((integer(&id).unwrap_or(-1), shown(&name)));
+    }
+    let ids : HashSet<i64> = activities.iter().map(|(id, _)| *id).collect();
+
+    /*
+     * history, row by row
+     */
+
+    let mut stmt = db_old
+        .prepare(&format!("SELECT rowid, * FROM {}", schema::quote_ident(&tables.history)))
+        .map_err(error::schema)?;
+    let mut rows = stmt.query([]).map_err(error::schema)?;
+
+    let mut used : HashSet<i64> = HashSet::new();
+    let mut days : HashSet<(i64, NaiveDate)> = HashSet::new();
+
+    while let Some(row) = rows.next().map_err(error::schema)?
+    {
+        let mut v : Vec<Value> = Vec::new();
+        for i in 0..8 { v.push(row.get::<_, Value>(i).map_err(error::schema)?); }
+
+        let rowid = integer(&v[0]).unwrap_or(-1);
+        let id    = integer(&v[1]);
+        let what  = format!("rowid {}: activity {}, {}", rowid, shown(&v[1]), shown(&v[7]));
+
+        match id
+        {
+            Some(id) if ids.contains(&id) => { used.insert(id); }
+            _ => found(0, what.clone()),
+        }
+
+        let numeric = match (integer(&v[2]), integer(&v[3]), integer(&v[4]))
+        {
+            (Some(y), Some(m), Some(d)) => i32::try_from(y).ok()
+                .zip(u32::try_from(m).ok().zip(u32::try_from(d).ok()))
+                .and_then(|(y, (m, d))| NaiveDate::from_ymd_opt(y, m, d)),
+            _ => None,
+        };
+        let parsed = match &v[7]
+        {
+            Value::Text(t) => NaiveDate::parse_from_str(t, "%Y-%m-%d").ok(),
+            _              => None,
+        };
+
+        match (numeric, parsed)
+        {
+            (Some(n), Some(p)) if n != p =>
+                found(4, format!("{} vs numeric {}", what, n)),
+            (Some(_), Some(_)) => {}
+            _ =>
+                found(2, format!("{} ({}-{}-{})", what, shown(&v[2]), shown(&v[3]), shown(&v[4]))),
+        }
+
+        if let (Some(id), Some(day)) = (id, numeric.or(parsed))
+        {
+            if !days.insert((id, day))
+            {
+                found(5, what.clone());
+            }
+        }
+
+        match (&v[6], real(&v[6]))
+        {
+            (Value::Null, _) => found(6, what),
+            (_, Some(h)) if (0.0..=24.0).contains(&h) => {}
+            (h, _) => found(3, format!("{} has {} hours", what, shown(h))),
+        }
+    }
+
+    for (id, name) in &activities
+    {
+        if !used.contains(id)
+        {
+            found(1, format!("activity {} ({})", id, name));
+        }
+    }
+
+    /*
+     * summary: one row per category
+     */
+
+    let report : Vec<Vec<serde_json::Value>> = CATEGORIES.iter().zip(&findings)
+        .map(|(c, f)| vec![json!(c), json!(f.count), json!(f.samples.join("; "))])
+        .collect();
+
+    inspect::print_rows(format, &["problem", "count", "samples"], &report);
+
+    if let OutputFormat::Table = format
+    {
+        let total : usize = findings.iter().map(|f| f.count).sum();
+        println!();
+        if total == 0
+        {
+            println!("No problems found in {} activities", activities.len());
+        }
+        else
+        {
+            println!("Problems found: {}, see --help for the flags dealing with them", total);
+        }
+    }
+
+    Ok(())
+}
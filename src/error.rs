@@ -0,0 +1,83 @@
+use std::fmt;
+use std::io;
+
+/*
+ * Everything that can make a migration fail, grouped by what a calling
+ * script might want to react to. Each group maps to its own exit code.
+ */
+
+pub const EXIT_OK       : u8 = 0;
+pub const EXIT_USAGE    : u8 = 1;	// invalid command line (see args.rs)
+pub const EXIT_SCHEMA   : u8 = 2;
+pub const EXIT_ABORTED  : u8 = 3;
+pub const EXIT_PARTIAL  : u8 = 4;
+pub const EXIT_IO       : u8 = 5;
+
+/// Why reading or migrating failed, see [`MigrationError::exit_code`].
+#[derive(Debug)]
+pub enum MigrationError {
+    Schema(String),		// old data doesn't have the expected layout
+    Aborted(String),	// user chose not to continue
+    Partial(String),	// writing/verifying the new db failed midway
+    Io(String),			// files, folders, opening dbs
+}
+
+impl MigrationError
+{
+    /// Exit code of the command line tool for this kind of failure.
+    pub fn exit_code ( &self ) -> u8
+    {
+        match self
+        {
+            MigrationError::Schema(_)  => EXIT_SCHEMA,
+            MigrationError::Aborted(_) => EXIT_ABORTED,
+            MigrationError::Partial(_) => EXIT_PARTIAL,
+            MigrationError::Io(_)      => EXIT_IO,
+        }
+    }
+}
+
+impl fmt::Display for MigrationError
+{
+    fn fmt ( &self, f : &mut fmt::Formatter ) -> fmt::Result
+    {
+        match self
+        {
+            MigrationError::Schema(m)  => write!(f, "schema mismatch: {}", m),
+            MigrationError::Aborted(m) => write!(f, "aborted: {}", m),
+            MigrationError::Partial(m) => write!(f, "partial migration: {}", m),
+            MigrationError::Io(m)      => write!(f, "i/o error: {}", m),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+impl From<io::Error> for MigrationError
+{
+    fn from ( e : io::Error ) -> Self
+    {
+        MigrationError::Io(e.to_string())
+    }
+}
+
+/*
+ * rusqlite errors can't be classified on their own, whether a failing
+ * statement means a schema mismatch or a half written db depends on
+ * which side it ran against; so callers pick the variant
+ */
+
+pub fn schema ( e : rusqlite::Error ) -> MigrationError
+{
+    MigrationError::Schema(e.to_string())
+}
+
+pub fn partial ( e : rusqlite::Error ) -> MigrationError
+{
+    MigrationError::Partial(e.to_string())
+}
+
+pub fn io ( e : rusqlite::Error ) -> MigrationError
+{
+    MigrationError::Io(e.to_string())
+}
@@ -0,0 +1,270 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path;
+
+use rusqlite::Connection;
+use rusqlite::types::Value;
+use rusqlite::types::ValueRef;
+
+use crate::error;
+use crate::error::MigrationError;
+use crate::old::OldTables;
+use crate::schema;
+
+/*
+ * --make-fixture, a small copy of the old db to attach to a bug report:
+ * the schema exactly as it is (every table, index, view and trigger by
+ * its own CREATE statement), a sample of the history and only the
+ * activities it refers to, their names scrubbed
+ *
+ * the sample keeps the rows migrations trip over first: around the turn
+ * of the year (iso weeks 52, 53 and 1), close to midnight, without hours
+ * or with text in them, of activities that don't exist; the rest of it is
+ * spread evenly over the whole history. other tables only get their
+ * schema, there's no telling what they hold
+ */
+
+const SAMPLE : usize = 200;		// history rows kept at most
+const EDGES  : usize = 100;		// of those, boundary cases at most
+const GHOSTS : usize = 10;		// activities without sampled history kept
+
+pub struct Fixture {
+    pub activities : usize,
+    pub history    : usize,
+    pub history_of : usize,		// history rows of the old db
+    pub tables     : usize,		// other tables, schema only
+}
+
+/*
+ * letters and digits become x (ä outside of ascii), the rest stays: the
+ * length, blanks, punctuation and invalid utf-8 survive, the name doesn't.
+ * names that were different stay different
+ */
+
+fn scrub ( name : &[u8], id : i64, seen : &mut Vec<(Vec<u8>, Vec<u8>)> ) -> Vec<u8>
+{
+    if let Some((_, s)) = seen.iter().find(|(orig, _)| orig == name)
+    {
+        return s.clone();
+    }
+
+    let mut scrubbed : Vec<u8> = match std::str::from_utf8(name)
+    {
+        Ok(t)  => t.chars()
+            .map(|c| match c
+            {
+                c if c.is_ascii_alphanumeric() => 'x',
+                c if c.is_alphanumeric()       => 'ä',
+                c                              => c,
+            })
+            .collect::<String>()
+            .into_bytes(),
+        Err(_) => name.iter()
+            .map(|&c| match c
+            {
+                c if c.is_ascii_alphanumeric() => b'x',
+                c if c >= 0x80                 => 0xe4,	// ä in latin-1, still invalid
+                c                              => c,
+            })
+            .collect(),
+    };
+
+    if seen.iter().any(|(_, s)| *s == scrubbed)
+    {
+        scrubbed.extend(format!(" {}", id).bytes());
+    }
+    seen.push((name.to_vec(), scrubbed.clone()));
+    scrubbed
+}
+
+fn rowids ( db : &Connection, sql : &str ) -> Result<Vec<i64>, MigrationError>
+{
+    let mut stmt = db.prepare(sql).map_err(error::schema)?;
+    let ids = stmt.query_map([], |row| row.get(0)).map_err(error::schema)?
+        .collect::<Result<_, _>>().map_err(error::schema)?;
+    Ok(ids)
+}
+
+fn boundary_cases ( db : &Connection, tables : &OldTables ) -> Result<String, MigrationError>
+{
+    let hours = schema::quote_ident(&tables.hours_column);
+    let dates = match schema::old_history_layout(db, tables)?
+    {
+        schema::HistoryLayout::Split    =>
+            "(month = 12 AND day >= 28) OR (month = 1 AND day <= 4) \
+             OR weeknumber >= 52 OR weeknumber <= 1".to_string(),
+        schema::HistoryLayout::Combined =>
+            "substr(datetime, 6, 5) >= '12-28' OR substr(datetime, 6, 5) <= '01-04' \
+             OR substr(datetime, 12, 2) IN ('00', '23')".to_string(),
+    };
+
+    Ok(format!("{} OR {} IS NULL OR typeof({}) NOT IN ('integer', 'real') \
+                OR id_activity NOT IN (SELECT id FROM {})",
+               dates, hours, hours, schema::quote_ident(&tables.activities)))
+}
+
+/*
+ * values are copied as they are stored: text goes through as its bytes
+ * (cast back to text), it isn't necessarily valid utf-8
+ */
+
+fn copy_rows ( db_old  : &Connection,
+               out     : &Connection,
+               table   : &str,
+               rowids  : &BTreeSet<i64>,
+               scrub_c : Option<usize> ) -> Result<usize, MigrationError>
+{
+    let cols = schema::column_names(db_old, table)?.len();
+    let mut read = db_old
+        .prepare(&format!("SELECT * FROM {} WHERE rowid = ?1", schema::quote_ident(table)))
+        .map_err(error::schema)?;
+
+    let mut seen = Vec::new();
+    for id in rowids
+    {
+        let mut row : Vec<(Value, bool)> = read
+            .query_row([id], |row| (0..cols)
+                .map(|i| Ok(match row.get_ref(i)?
+                {
+                    ValueRef::Text(t) => (Value::Blob(t.to_vec()), true),
+                    v                 => (v.into(), false),
+                }))
+                .collect())
+            .map_err(error::schema)?;
+        if let Some((Value::Blob(name), _)) = scrub_c.map(|c| &mut row[c])
+        {
+            *name = scrub(name, *id, &mut seen);
+        }
+
+        let params = row.iter().enumerate()
+            .map(|(i, (_, text))| match text
+            {
+                true  => format!("CAST(?{} AS TEXT)", i + 1),
+                false => format!("?{}", i + 1),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        out.prepare_cached(&format!("INSERT INTO {} VALUES ({})", schema::quote_ident(table), params))
+            .map_err(error::io)?
+            .execute(rusqlite::params_from_iter(row.into_iter().map(|(v, _)| v)))
+            .map_err(error::io)?;
+    }
+
+    Ok(rowids.len())
+}
+
+pub fn make ( db_old : &Connection, tables : &OldTables, path : &path::Path )
+    -> Result<Fixture, MigrationError>
+{
+    if path.exists()
+    {
+        return Err(MigrationError::Io(format!(
+            "{:?} already exists, not overwriting it with a fixture", path)));
+    }
+
+    let names       = schema::table_names(db_old)?;
+    let has_history = names.contains(&tables.history);
+    let history     = schema::quote_ident(&tables.history);
+    let activities  = schema::quote_ident(&tables.activities);
+
+    /*
+     * the history sample: boundary cases first, then evenly spaced rows
+     * (first and last included) until it's full
+     */
+
+    let mut sample = BTreeSet::new();
+    let mut history_of = 0;
+    if has_history
+    {
+        let all = rowids(db_old, &format!("SELECT rowid FROM {} ORDER BY rowid", history))?;
+        history_of = all.len();
+
+        sample.extend(rowids(db_old, &format!("SELECT rowid FROM {} WHERE {} ORDER BY rowid LIMIT {}",
+                                              history, boundary_cases(db_old, tables)?, EDGES))?);
+
+        let rest : Vec<i64> = all.into_iter().filter(|id| !sample.contains(id)).collect();
+        let room = SAMPLE - sample.len();
+        match rest.len() <= room
+        {
+            true  => sample.extend(rest),
+            false => sample.extend((0..room).map(|i| rest[i * (rest.len() - 1) / (room - 1).max(1)])),
+        }
+    }
+
+    let mut kept = BTreeSet::new();
+    if has_history && !sample.is_empty()
+    {
+        let ids = sample.iter().map(i64::to_string).collect::<Vec<_>>().join(", ");
+        kept.extend(rowids(db_old, &format!(
+            "SELECT rowid FROM {} WHERE id IN (SELECT id_activity FROM {} WHERE rowid IN ({}))",
+            activities, history, ids))?);
+    }
+    let ghosts = match has_history
+    {
+        true  => format!("WHERE id NOT IN (SELECT id_activity FROM {})", history),
+        false => String::new(),
+    };
+    kept.extend(rowids(db_old, &format!("SELECT rowid FROM {} {} ORDER BY rowid LIMIT {}",
+                                        activities, ghosts, GHOSTS))?);
+
+    /*
+     * tables first, their rows, then indexes, views and triggers; the
+     * latter would fire on the copied rows otherwise
+     */
+
+    let mut stmt = db_old
+        .prepare("SELECT type, name, sql FROM sqlite_master \
+                  WHERE sql IS NOT NULL AND name NOT LIKE 'sqlite_%' ORDER BY rowid")
+        .map_err(error::schema)?;
+    let objects : Vec<(String, String, String)> = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .map_err(error::schema)?
+        .collect::<Result<_, _>>()
+        .map_err(error::schema)?;
+
+    let out = Connection::open(path).map_err(error::io)?;
+
+    /*
+     * a half written fixture would only be in the way of the next try
+     */
+
+    let written = (|| -> Result<Fixture, MigrationError> {
+        out.execute_batch("BEGIN").map_err(error::io)?;
+
+        for (_, _, sql) in objects.iter().filter(|(kind, _, _)| kind == "table")
+        {
+            out.execute_batch(sql).map_err(error::io)?;
+        }
+
+        let name = schema::column_names(db_old, &tables.activities)?.iter().position(|c| c == "name");
+        let mut fixture = Fixture {
+            activities : copy_rows(db_old, &out, &tables.activities, &kept, name)?,
+            history    : 0,
+            history_of,
+            tables     : objects.iter()
+                .filter(|(kind, t, _)| kind == "table" && *t != tables.activities && *t != tables.history)
+                .count(),
+        };
+        if has_history
+        {
+            fixture.history = copy_rows(db_old, &out, &tables.history, &sample, None)?;
+        }
+
+        for (_, _, sql) in objects.iter().filter(|(kind, _, _)| kind != "table")
+        {
+            out.execute_batch(sql).map_err(error::io)?;
+        }
+
+        out.execute_batch("COMMIT").map_err(error::io)?;
+
+        Ok(fixture)
+    })();
+
+    if written.is_err()
+    {
+        drop(out);
+        let _ = fs::remove_file(path);
+    }
+
+    written
+}
@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+
+use serde_json::json;
+use serde_json::Map;
+use serde_json::Value;
+
+use crate::args::OutputFormat;
+use crate::old::OldActivity;
+use crate::old::OldHistory;
+use crate::transform::SourceDup;
+
+/*
+ * Read-only inspection of the old data (--list-activities, --stats)
+ *
+ * Both modes boil down to either a list of rows or a single record,
+ * which are printed in whatever --format asks for
+ */
+
+fn cell ( v : &Value ) -> String
+{
+    match v
+    {
+        Value::String(s) => s.clone(),
+        Value::Null      => String::new(),
+        _                => v.to_string(),
+    }
+}
+
+pub fn csv_field ( s : &str ) -> String
+{
+    if s.contains([',', '"', '\n'])
+    {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    }
+    else
+    {
+        s.to_string()
+    }
+}
+
+pub fn print_rows ( format : &OutputFormat, headers : &[&str], rows : &[Vec<Value>] )
+{
+    match format
+    {
+        OutputFormat::Table =>
+        {
+            let mut widths : Vec<usize> = headers.iter().map(|h| h.len()).collect();
+            for r in rows
+            {
+                for (i, v) in r.iter().enumerate()
+                {
+                    widths[i] = widths[i].max(cell(v).chars().count());
+                }
+            }
+
+            let line = |cells : Vec<String>| -> String {
+                cells.iter().enumerate()
+                    .map(|(i, c)| format!("{:<w$}", c, w = widths[i]))
+                    .collect::<Vec<String>>()
+                    .join("  ")
+                    .trim_end()
+                    .to_string()
+            };
+
+            println!("{}", line(headers.iter().map(|h| h.to_string()).collect()));
+            println!("{}", line(widths.iter().map(|w| "-".repeat(*w)).collect()));
+            for r in rows
+            {
+                println!("{}", line(r.iter().map(cell).collect()));
+            }
+        }
+        OutputFormat::Csv =>
+        {
+            println!("{}", headers.join(","));
+            for r in rows
+            {
+                let cells : Vec<String> = r.iter().map(|v| csv_field(&cell(v))).collect();
+                println!("{}", cells.join(","));
+            }
+        }
+        OutputFormat::Json =>
+        {
+            let list : Vec<Value> = rows.iter()
+                .map(|r| {
+                    let mut m = Map::new();
+                    for (h, v) in headers.iter().zip(r) { m.insert(h.to_string(), v.clone()); }
+                    Value::Object(m)
+                })
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&list).unwrap_or_default());
+        }
+    }
+}
+
+pub fn print_record ( format : &OutputFormat, fields : &[(&str, Value)] )
+{
+    match format
+    {
+        OutputFormat::Json =>
+        {
+            let mut m = Map::new();
+            for (k, v) in fields { m.insert(k.to_string(), v.clone()); }
+            println!("{}", serde_json::to_string_pretty(&Value::Object(m)).unwrap_or_default());
+        }
+        _ =>
+        {
+            let rows : Vec<Vec<Value>> = fields.iter()
+                .map(|(k, v)| vec![json!(k), v.clone()])
+                .collect();
+            print_rows(format, &["stat", "value"], &rows);
+        }
+    }
+}
+
+pub fn list_activities ( format : &OutputFormat, oldact : &[OldActivity] )
+{
+    let rows : Vec<Vec<Value>> = oldact.iter()
+        .map(|e| vec![
+            json!(e.id),
+            json!(e.name),
+            json!(e.added_when),
+            json!(e.is_activated != 0),
+            json!(e.hours_total),
+        ])
+        .collect();
+
+    print_rows(format, &["id", "name", "added", "active", "hours_total"], &rows);
+}
+
+pub fn stats ( format : &OutputFormat,
+               oldact : &[OldActivity],
+               oldhis : &[OldHistory] )
+{
+    let active      = oldact.iter().filter(|e| e.is_activated != 0).count();
+    let hours_total : f64 = oldact.iter().map(|e| e.hours_total).sum();
+    let hours_his   : f64 = oldhis.iter().map(|e| e.hours_on_day).sum();
+    let first       = oldhis.iter().map(|e| &e.date).min();
+    let last        = oldhis.iter().map(|e| &e.date).max();
+
+    print_record(format, &[
+        ("activities",         json!(oldact.len())),
+        ("activities_active",  json!(active)),
+        ("history_rows",       json!(oldhis.len())),
+        ("hours_total",        json!(crate::round6(hours_total))),
+        ("hours_history",      json!(crate::round6(hours_his))),
+        ("first_date",         json!(first)),
+        ("last_date",          json!(last)),
+    ]);
+}
+
+/*
+ * --report-source-dups, history rows of an activity on the same date;
+ * likely entered twice by accident, --merge-source-dups sums them up
+ */
+
+pub fn source_dups ( format : &OutputFormat,
+                     oldact : &[OldActivity],
+                     dups   : &[SourceDup] )
+{
+    let rows : Vec<Vec<Value>> = dups.iter()
+        .map(|d| vec![
+            json!(d.id),
+            json!(oldact.iter().find(|e| e.id == d.id).map(|e| e.name.as_str())),
+            json!(d.date),
+            json!(d.hours.len()),
+            json!(d.hours.iter().map(|h| h.to_string()).collect::<Vec<_>>().join(" + ")),
+            json!(crate::round6(d.hours.iter().sum())),
+        ])
+        .collect();
+
+    print_rows(format, &["id", "name", "date", "rows", "hours", "sum"], &rows);
+}
+
+/*
+ * --dedup-activities-by-name report, every activity whose name is used
+ * more than once, with the one its merge would go into
+ */
+
+pub fn duplicate_names ( format : &OutputFormat,
+                         oldact : &[OldActivity],
+                         oldhis : &[OldHistory],
+                         groups : &[(String, Vec<i32>)] )
+{
+    let mut n_his : HashMap<i32, usize> = HashMap::new();
+    for e in oldhis
+    {
+        *n_his.entry(e.id_activity).or_insert(0) += 1;
+    }
+
+    let rows : Vec<Vec<Value>> = groups.iter()
+        .flat_map(|(name, ids)| ids.iter().map(move |id| (name, id, ids[0])))
+        .map(|(name, id, into)| {
+            let e = oldact.iter().find(|e| e.id == *id);
+            vec![
+                json!(name),
+                json!(id),
+                json!(e.map(|e| e.name.as_str())),
+                json!(e.map(|e| crate::round6(e.hours_total))),
+                json!(n_his.get(id).copied().unwrap_or(0)),
+                json!(into),
+            ]
+        })
+        .collect();
+
+    print_rows(format, &["name", "id", "as written", "hours_total", "history_rows", "merged_into"], &rows);
+}
+
+/*
+ * --name-encoding-report, names are looked at as raw bytes; only names
+ * with problems are listed
+ */
+
+pub fn name_encoding_report ( format : &OutputFormat, names : &[(i32, Vec<u8>)] )
+{
+    let mut rows : Vec<Vec<Value>> = Vec::new();
+    let mut n_bad = 0;
+
+    for (id, name) in names
+    {
+        let problems = crate::old::name_problems(name);
+        if !problems.is_empty() { n_bad += 1; }
+
+        for p in problems
+        {
+            rows.push(vec![
+                json!(id),
+                json!(format!("{:?}", String::from_utf8_lossy(name))),
+                json!(p),
+            ]);
+        }
+    }
+
+    print_rows(format, &["id", "name", "problem"], &rows);
+
+    if let OutputFormat::Table = format
+    {
+        println!();
+        println!("{} of {} activity names have problems", n_bad, names.len());
+    }
+}
@@ -0,0 +1,36 @@
+use std::fs;
+
+use serde::Deserialize;
+
+use crate::old::OldActivity;
+use crate::old::OldHistory;
+use crate::error::MigrationError;
+
+/*
+ * Json dump of the old db, same field names as the old tables
+ *
+ * {
+ *   "activities" : [ { "id", "group_id", "name", "added_when",
+ *                      "is_activated", "hours_total" }, ... ],
+ *   "history"    : [ { "id_activity", "year", "month", "day",
+ *                      "weeknumber", "hours_on_day", "date" }, ... ]
+ * }
+ */
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OldDump {
+    activities : Vec<OldActivity>,
+    history    : Vec<OldHistory>,
+}
+
+pub fn read_old_json ( path : &str )
+    -> Result<(Vec<OldActivity>, Vec<OldHistory>), MigrationError>
+{
+    let text = fs::read_to_string(path)
+        .map_err(|e| MigrationError::Io(format!("{:?}: {}", path, e)))?;
+    let dump : OldDump = serde_json::from_str(&text)
+        .map_err(|e| MigrationError::Schema(format!("{:?}: {}", path, e)))?;
+
+    Ok((dump.activities, dump.history))
+}
@@ -0,0 +1,37 @@
+//! Reading side of the transition tool, for other programs that want to
+//! parse old timetracker databases (archived C++ version) themselves, and
+//! the migration itself as [`migrate()`], set up by [`MigrationOptions`].
+//!
+//! ```no_run
+//! use rusqlite::Connection;
+//! use timetracker_transitiontool::{read_old_activities, read_old_history};
+//!
+//! let db = Connection::open("productivity.db")?;
+//! for a in read_old_activities(&db)? {
+//!     println!("{}: {} hours", a.name, a.hours_total);
+//! }
+//! println!("{} history rows", read_old_history(&db)?.len());
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+pub mod error;
+pub mod migrate;
+pub mod new;
+pub mod old;
+pub mod options;
+pub mod round;
+pub mod schema;
+pub mod transform;
+pub mod warn;
+
+pub use error::MigrationError;
+pub use migrate::migrate;
+pub use migrate::migrate_in;
+pub use old::read_old_activities;
+pub use old::read_old_activities_in;
+pub use old::read_old_history;
+pub use old::read_old_history_in;
+pub use old::OldActivity;
+pub use old::OldHistory;
+pub use old::OldTables;
+pub use options::MigrationOptions;
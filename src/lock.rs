@@ -0,0 +1,62 @@
+use std::fs;
+use std::io::Read;
+use std::io::Seek;
+use std::io::Write;
+use std::path;
+
+use fs2::FileExt;
+
+use crate::error::MigrationError;
+
+/*
+ * one run writing to a new db at a time: <db>.lock next to it, locked
+ * exclusively for as long as the run writes and holding the pid and start
+ * time of that run, for the message of the one refused
+ *
+ * the os drops the lock with the process, so a lock file left behind by a
+ * crash doesn't block anything; removed again on drop
+ */
+
+pub struct RunLock {
+    file : fs::File,
+    path : path::PathBuf,
+}
+
+impl RunLock
+{
+    pub fn acquire ( dbpath : &path::Path, started : &str ) -> Result<RunLock, MigrationError>
+    {
+        let mut p = dbpath.as_os_str().to_owned();
+        p.push(".lock");
+        let path = path::PathBuf::from(p);
+
+        let mut file = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false)
+            .open(&path)
+            .map_err(|e| MigrationError::Io(format!("can't open lock file {:?}: {}", path, e)))?;
+
+        if file.try_lock_exclusive().is_err()
+        {
+            let mut holder = String::new();
+            let _ = file.read_to_string(&mut holder);
+            return Err(MigrationError::Aborted(format!(
+                "another run is writing to {:?} ({}), see {:?}", dbpath,
+                match holder.trim() { "" => "unknown pid", h => h }, path)));
+        }
+
+        file.set_len(0)?;
+        file.rewind()?;
+        write!(file, "pid {}, started {}", std::process::id(), started)?;
+        file.flush()?;
+
+        Ok(RunLock { file, path })
+    }
+}
+
+impl Drop for RunLock
+{
+    fn drop ( &mut self )
+    {
+        let _ = fs::remove_file(&self.path);
+        let _ = FileExt::unlock(&self.file);
+    }
+}
@@ -1,277 +1,2327 @@
+mod appschema;
+mod args;
+mod atomic;
+mod audit;
+mod backup;
+mod bench;
+mod checksum;
+mod diff;
+mod doctor;
+mod fixture;
+mod inspect;
+mod json;
+mod lock;
+mod netfs;
+mod progress;
+mod quarantine;
+mod report;
+mod stats;
+mod transform_config;
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::env;
 use std::fs;
 use std::io;
+use std::io::IsTerminal;
 use std::io::Write;
 use std::path;
+use std::process::ExitCode;
+use std::thread;
+use std::time;
+
+use chrono::NaiveDate;
+use directories::BaseDirs;
+use directories::ProjectDirs;
+use rusqlite::Connection;
+use serde_json::json;
+use serde_json::Value;
+
+use timetracker_transitiontool::error;
+use timetracker_transitiontool::migrate::reshape;
+use timetracker_transitiontool::migrate::select;
+use timetracker_transitiontool::new;
+use timetracker_transitiontool::old;
+use timetracker_transitiontool::options::AddedFallback;
+use timetracker_transitiontool::round;
+use timetracker_transitiontool::schema;
+use timetracker_transitiontool::transform;
+use timetracker_transitiontool::warn;
+
+use args::ActivityDedup;
+use args::Args;
+use args::DuplicateName;
+use args::InputFormat;
+use atomic::AtomicFile;
+use audit::AuditLog;
+use checksum::Checksum;
+use diff::Diff;
+use lock::RunLock;
+use new::NewDb;
+use progress::Progress;
+use quarantine::Quarantine;
+use stats::MigrationStats;
+use transform_config::TransformConfig;
+use warn::Warnings;
+use error::MigrationError;
+use old::OldActivity;
+use old::OldHistory;
+use round::round6;
+
+/*
+ * decide whether to buffer the old history or to stream it; the old
+ * db's file size stands in for the memory buffering it would take
+ */
+
+fn old_file_size ( path : &str ) -> Result<u64, MigrationError>
+{
+    let file = if old::is_uri(path) { old::uri_file_path(path)? } else { path.to_string() };
+
+    Ok(fs::metadata(file)?.len())
+}
+
+fn stream_history ( args : &Args, path : &str ) -> Result<bool, MigrationError>
+{
+    let size  = old_file_size(path)?;
+    let limit = args.max_memory.saturating_mul(1024 * 1024);
+
+    if size <= limit
+    {
+        return Ok(false);
+    }
+    let needs_all = if args.options.compact_history { Some("--compact-history") }
+        else if args.options.prune_empty_activities { Some("--prune-empty-activities") }
+        else if args.options.dedup_history { Some("--dedup-history") }
+        else if args.options.merge_source_dups { Some("--merge-source-dups") }
+        else if args.activity_checksums { Some("--activity-checksums") }
+        else if args.zero_fill_gaps { Some("--zero-fill-gaps") }
+        else if args.options.sample.is_some() { Some("--sample") }
+        else { None };
+
+    if let Some(flag) = needs_all
+    {
+        println!("Old db is {} MiB (above --max-memory {} MiB), but {} \
+                  needs the whole history, buffering anyway",
+                 size / 1024 / 1024, args.max_memory, flag);
+        return Ok(false);
+    }
+
+    println!("Old db is {} MiB (above --max-memory {} MiB), streaming history",
+             size / 1024 / 1024, args.max_memory);
+    Ok(true)
+}
+
+fn check_foreign_keys ( args : &Args, db_new : &NewDb ) -> Result<(), MigrationError>
+{
+    let violations = db_new.foreign_key_violations()?;
+
+    if violations.is_empty()
+    {
+        println!("Foreign key check: ok");
+        return Ok(());
+    }
+
+    println!("Foreign key check: {} violations", violations.len());
+    for (table, rowid, parent) in violations.iter().take(20)
+    {
+        println!("    {} rowid {} references a missing row of {}", table, rowid, parent);
+    }
+    if violations.len() > 20
+    {
+        println!("    ... and {} more", violations.len() - 20);
+    }
+
+    if args.force
+    {
+        println!("--force is set, continuing anyway");
+        return Ok(());
+    }
+
+    Err(MigrationError::Partial(format!(
+        "{} foreign key violations in the new db (rows are written)", violations.len())))
+}
+
+/*
+ * expected hashes were taken while inserting, compare them against what
+ * actually is in the new db now
+ */
+
+fn verify_checksums ( args     : &Args,
+                      db_new   : &NewDb,
+                      dbpath   : &path::Path,
+                      ids      : &HashSet<i32>,
+                      expected : (&Checksum, &Checksum),
+                      ranges   : Option<&checksum::Ranges> ) -> Result<(), MigrationError>
+{
+    let mut checks : Vec<(String, &Checksum, Checksum)> = Vec::new();
+
+    match ranges
+    {
+        None =>
+        {
+            let (act, his) = checksum::of_new_db(&db_new.conn, ids)?;
+            checks.push(("tt_activities".to_string(), expected.0, act));
+            checks.push(("tt_history".to_string(), expected.1, his));
+        }
+        Some(r) =>
+        {
+            println!("Verifying in {} threads", r.bounds.len());
+            for (i, (act, his)) in r.of_new_db(dbpath, args.out_key.as_deref(), ids)?.into_iter().enumerate()
+            {
+                let (lo, hi) = r.bounds[i];
+                checks.push((format!("tt_activities ids {}..={}", lo, hi), &r.act[i], act));
+                checks.push((format!("tt_history ids {}..={}", lo, hi), &r.his[i], his));
+            }
+        }
+    }
+
+    let mut failed = Vec::new();
+
+    for (table, want, got) in &checks
+    {
+        if want.value() == got.value() && want.rows == got.rows
+        {
+            println!("Checksum {}: ok, {} over {} rows", table, got.value(), got.rows);
+        }
+        else
+        {
+            println!("Checksum {}: MISMATCH, expected {} over {} rows, found {} over {} rows",
+                     table, want.value(), want.rows, got.value(), got.rows);
+            failed.push(table.as_str());
+        }
+    }
+
+    /*
+     * with all rows (read back in order) matching, the new db's hashes
+     * over whole tables are the expected ones
+     */
+
+    if failed.is_empty()
+    {
+        db_new.set_meta("checksum_activities", &expected.0.value())?;
+        db_new.set_meta("checksum_history", &expected.1.value())?;
+        return Ok(());
+    }
+
+    if args.force
+    {
+        println!("--force is set, continuing anyway");
+        return Ok(());
+    }
+
+    Err(MigrationError::Partial(format!(
+        "checksum mismatch in {} (rows are written)", failed.join(", "))))
+}
+
+/*
+ * with --continue-on-insert-error a failing row is recorded instead of
+ * ending the run; returns the error of a row that didn't make it in
+ */
+
+fn insert_failure ( args     : &Args,
+                    failures : &mut Vec<(String, String)>,
+                    row      : impl FnOnce() -> String,
+                    result   : Result<(), MigrationError> ) -> Result<Option<String>, MigrationError>
+{
+    match result
+    {
+        Ok(())                                   => Ok(None),
+        Err(e) if args.continue_on_insert_error  =>
+        {
+            failures.push((row(), e.to_string()));
+            Ok(Some(e.to_string()))
+        }
+        Err(e)                                   => Err(e),
+    }
+}
+
+fn history_row ( e : &OldHistory ) -> String
+{
+    format!("history of activity {} on {}", e.id_activity, e.date)
+}
+
+fn report_dropped ( audit   : &mut AuditLog,
+                    what    : &str,
+                    dropped : &[OldActivity],
+                    n_his   : usize,
+                    stream  : bool ) -> Result<(), MigrationError>
+{
+    if stream
+    {
+        println!("{}: {} activities (their history is skipped while streaming)",
+                 what, dropped.len());
+    }
+    else
+    {
+        println!("{}: {} activities, {} history rows", what, dropped.len(), n_his);
+    }
+
+    for e in dropped
+    {
+        audit.record("skipped", json!({ "reason" : what, "activity" : e.id, "name" : e.name }))?;
+    }
+    if !stream && n_his > 0
+    {
+        audit.record("skipped", json!({ "reason" : what, "history_rows" : n_his }))?;
+    }
+
+    Ok(())
+}
+
+fn report_date_check ( args : &Args, n_mismatch : usize )
+{
+    if n_mismatch > 0
+    {
+        println!("Date check: {} history rows with disagreeing date fields{}", n_mismatch,
+                 if args.options.trust_dates.is_none() { ", migrated as-is" } else { "" });
+    }
+}
+
+/*
+ * (folder, file) of the new db; --db-path or the app's own location
+ */
+
+fn new_db_path ( args : &Args ) -> Result<(path::PathBuf, path::PathBuf), MigrationError>
+{
+    if let Some(p) = &args.db_path
+    {
+        let dbpath = path::PathBuf::from(p);
+        let dcpath = match dbpath.parent()
+        {
+            Some(d) if !d.as_os_str().is_empty() => d.to_path_buf(),
+            _                                    => path::PathBuf::from("."),
+        };
+        if dbpath.file_name().is_none() || dbpath.is_dir()
+        {
+            return Err(MigrationError::Io(format!(
+                "--db-path {:?} doesn't name a file", dbpath)));
+        }
+        check_folder(&dcpath)?;
+        return Ok((dcpath, dbpath));
+    }
+
+    let projdir = ProjectDirs::from("dev", "sintheta", "timetracker");
+
+    let dcpath : path::PathBuf = if let Some(d) = projdir
+    {
+        d.config_dir().to_path_buf()
+    }
+    else 
+    {
+        return Err(MigrationError::Io(
+            "could not retrieve OS specific configuration folder".to_string()));
+    };
+    let dbpath : path::PathBuf = dcpath.join(new::DBNAME);
+    check_folder(&dcpath)?;
+
+    Ok((dcpath, dbpath))
+}
+
+/*
+ * a file where the new db's folder (or one above it) should be would
+ * only fail later, as "not a directory" without saying which
+ */
+
+fn check_folder ( dir : &path::Path ) -> Result<(), MigrationError>
+{
+    match dir.ancestors().find(|p| p.exists())
+    {
+        Some(p) if !p.is_dir() => Err(MigrationError::Io(format!(
+            "{:?} is in the way of the new db's folder {:?}, it's a file and not a folder; \
+             move it away or pick another --db-path", p, dir))),
+        _                      => Ok(()),
+    }
+}
+
+/*
+ * a folder that merely exists isn't enough for --db-path, sqlite also
+ * needs to create its journal next to the db
+ */
+
+fn check_writable ( dir : &path::Path ) -> Result<(), MigrationError>
+{
+    let probe = dir.join(format!(".tt_transition_probe_{}", std::process::id()));
+
+    match fs::File::create(&probe)
+    {
+        Ok(_)  => fs::remove_file(&probe).map_err(MigrationError::from),
+        Err(e) => Err(MigrationError::Io(format!("folder {:?} isn't writable: {}", dir, e))),
+    }
+}
+
+/*
+ * the new db ends up about as large as the old data, refuse to start a
+ * migration that would run out of disk space halfway
+ */
+
+fn check_free_space ( old_paths : &[&str], dir : &path::Path ) -> Result<(), MigrationError>
+{
+    let needed    = old_paths.iter().map(|p| old_file_size(p)).sum::<Result<u64, _>>()?;
+    let available = fs2::available_space(dir)?;
+
+    if available < needed
+    {
+        return Err(MigrationError::Io(format!(
+            "not enough free space in {:?}: about {} KiB needed, {} KiB available \
+             (--skip-space-check to try anyway)",
+            dir, needed.div_ceil(1024), available / 1024)));
+    }
+
+    Ok(())
+}
+
+/*
+ * --dry-run, everything up to the writes; the history to compare is
+ * read from the old db again when streaming, with the same filters
+ */
+
+fn dry_run ( args          : &Args,
+             db_old        : &Option<Connection>,
+             (oldact, oldhis) : (&[OldActivity], &[OldHistory]),
+             stream        : bool,
+             dbpath        : &path::Path ) -> Result<(), MigrationError>
+{
+    if !dbpath.exists()
+    {
+        let n_his = match (stream, db_old)
+        {
+            (true, Some(db)) =>
+            {
+                let ids : HashSet<i32> = oldact.iter().map(|e| e.id).collect();
+                let mut n = 0;
+                old::for_each_history_paged(db, &args.old_tables, args.page_size, |e| {
+                    if ids.contains(&e.id_activity) && args.options.limit.is_none_or(|l| n < l)
+                    {
+                        n += 1;
+                    }
+                    Ok(true)
+                })?;
+                n
+            }
+            _ => oldhis.len(),
+        };
+
+        println!();
+        println!("Dry run, nothing is written; the new db would get {} activities \
+                  and {} history rows", oldact.len(), n_his);
+        return Ok(());
+    }
+
+    let db_new = new::open_keyed(dbpath, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY, args.out_key.as_deref())?;
+    let mut diff = Diff::read(&db_new)?;
+
+    match (stream, db_old)
+    {
+        (true, Some(db)) =>
+        {
+            let ids : HashSet<i32> = oldact.iter().map(|e| e.id).collect();
+            let mut n = 0;
+            old::for_each_history_paged(db, &args.old_tables, args.page_size, |e| {
+                if ids.contains(&e.id_activity) && args.options.limit.is_none_or(|l| n < l)
+                {
+                    diff.history_row(&e);
+                    n += 1;
+                }
+                Ok(true)
+            })?;
+        }
+        _ => oldhis.iter().for_each(|e| diff.history_row(e)),
+    }
+
+    diff.print(oldact);
+
+    Ok(())
+}
+
+/*
+ * start time of the run; --seed-for-tests pins it (in utc) to the seed's
+ * seconds after 2000-01-01, so output is the same on every run and
+ * machine; there's nothing random in a migration beyond that
+ */
+
+fn run_clock ( args : &Args ) -> chrono::DateTime<chrono::FixedOffset>
+{
+    match args.seed_for_tests
+    {
+        Some(seed) => chrono::DateTime::from_timestamp(946_684_800 + seed as i64, 0)
+            .unwrap_or_default()
+            .fixed_offset(),
+        None       => chrono::Local::now().fixed_offset(),
+    }
+}
+
+/*
+ * an empty in-memory new db with the tables a run with these args writes
+ */
+
+fn written_schema ( args : &Args ) -> Result<NewDb, MigrationError>
+{
+    let mut db = NewDb::open(path::Path::new(":memory:"), None)?;
+    db.dedup_history = args.options.dedup_history;
+    db.create_tables()?;
+    db.set_meta("schema_version", new::SCHEMA_VERSION)?;
+
+    if !args.extra_columns.is_empty()
+    {
+        let old_path = args.old_path.as_ref().ok_or_else(|| MigrationError::Schema(
+            "--extra-column takes the column types from the old db, give its path".to_string()))?;
+        let db_old = old::open(old_path, &args.old_tables)?;
+        let types  = schema::columns(&db_old, &args.old_tables.activities)?;
+        let columns : Vec<(String, String)> = args.extra_columns.iter()
+            .map(|(old, new)| {
+                let t = types.iter().find(|(n, _)| n == old).map(|(_, t)| t.clone());
+                (new.clone(), t.unwrap_or_default())
+            })
+            .collect();
+        db.add_extra_columns(&columns)?;
+    }
+    if args.store_minutes
+    {
+        db.add_minute_columns()?;
+    }
+    if args.stamp_migrated
+    {
+        db.add_stamp_columns()?;
+    }
+
+    Ok(db)
+}
+
+/*
+ * --output-sql-schema-only, the new db's tables as a run with the same
+ * flags would create them, made in memory and written out; returns the
+ * number of statements
+ */
+
+fn schema_only ( args : &Args, file : &str ) -> Result<usize, MigrationError>
+{
+    let db = written_schema(args)?;
+
+    let statements = db.schema_sql()?;
+    let mut script = format!("-- schema of a new db (version {}), written by timetracker_transitiontool {}\n",
+                             new::SCHEMA_VERSION, new::tool_version());
+    for sql in &statements
+    {
+        script.push_str(&format!("\n{};\n", sql));
+    }
+    fs::write(file, script).map_err(|e| MigrationError::Io(format!(
+        "can't write schema {:?}: {}", file, e)))?;
+
+    Ok(statements.len())
+}
+
+/*
+ * --report, on the new db as left by an earlier run
+ */
+
+fn run_report ( args : &Args, report : &args::Report ) -> Result<(), MigrationError>
+{
+    let (_, dbpath) = new_db_path(args)?;
+
+    if !dbpath.exists()
+    {
+        return Err(MigrationError::Io(format!(
+            "{:?} doesn't exist, nothing to report on", dbpath)));
+    }
+
+    let db_new = new::open_keyed(&dbpath, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY, args.out_key.as_deref())?;
+
+    report::print(&args.format, report, &db_new)
+}
+
+/*
+ * --confirm-totals, (activity totals, history hours) of the old data as
+ * read and of what is about to be written; differences up to `tolerance`
+ * hours count as equal
+ */
+
+fn confirm_totals ( old : (f64, f64), new : (f64, f64), tolerance : f64, warnings : &Warnings )
+    -> Result<(), MigrationError>
+{
+    let differs = |a : f64, b : f64| (a - b).abs() > tolerance;
+    let mark    = |a : f64, b : f64| if differs(a, b) { "  <-- differs" } else { "" };
+
+    println!("Totals check (hours):");
+    println!("                      old data        to be written");
+    println!("    activity totals   {:>14}  {:>14}{}",
+             round6(old.0), round6(new.0), mark(old.0, new.0));
+    println!("    history hours     {:>14}  {:>14}{}",
+             round6(old.1), round6(new.1), mark(old.1, new.1));
+
+    let mut answer = String::new();
+
+    if differs(old.0, new.0) || differs(old.1, new.1)
+    {
+        warnings.warn("the totals to be written differ from the old data \
+                       (expected if filters or limits are in use)")?;
+        print!  ("       Type yes to write them anyway : ");
+        io::stdout().flush()?;
+        io::stdin().read_line(&mut answer)?;
+
+        if answer.trim() != "yes"
+        {
+            return Err(MigrationError::Aborted("totals not confirmed".to_string()));
+        }
+    }
+    else
+    {
+        print!  ("       Continue? [Y/n]     : ");
+        io::stdout().flush()?;
+        io::stdin().read_line(&mut answer)?;
+
+        if answer.trim().to_lowercase().starts_with('n')
+        {
+            return Err(MigrationError::Aborted("totals not confirmed".to_string()));
+        }
+    }
+
+    Ok(())
+}
+
+/*
+ * a path as typed or pasted at the prompt: surrounding quotes dropped, a
+ * leading ~ for the home folder, no trailing slash, no . or doubled
+ * slashes; sqlite uris stay as they are
+ */
+
+fn clean_path ( input : &str ) -> String
+{
+    let mut p = input.trim();
+    for q in ['"', '\'']
+    {
+        if p.len() >= 2 && p.starts_with(q) && p.ends_with(q)
+        {
+            p = p[1..p.len() - 1].trim();
+        }
+    }
+
+    if p.is_empty() || old::is_uri(p)
+    {
+        return p.to_string();
+    }
+
+    let mut full = path::PathBuf::new();
+    match (p.strip_prefix('~'), BaseDirs::new())
+    {
+        (Some(rest), Some(dirs)) if rest.is_empty() || rest.starts_with(path::MAIN_SEPARATOR) =>
+        {
+            full.push(dirs.home_dir());
+            full.push(rest.trim_start_matches(path::MAIN_SEPARATOR));
+        }
+        _ => full.push(p),
+    }
+
+    full.components().collect::<path::PathBuf>().to_string_lossy().into_owned()
+}
+
+fn prompt_old_path ( args : &Args ) -> Result<String, MigrationError>
+{
+    let mut path : String = Default::default();
+
+    match args.input_format
+    {
+        InputFormat::Sqlite =>
+            println!("Enter your full db path, eg: /home/user/foo/bar/productivity.db"),
+        InputFormat::Json   =>
+            println!("Enter your full json path, eg: /home/user/foo/bar/productivity.json"),
+        InputFormat::Sql    =>
+            println!("Enter your full sql dump path, eg: /home/user/foo/bar/productivity.sql"),
+    }
+    print!  ("       Your entry          : ");
+    io::stdout().flush()?;
+    io::stdin().read_line(&mut path)?;
+    path = clean_path(&path);
+
+    if path.is_empty()
+    {
+        return Err(MigrationError::Aborted("no path entered".to_string()));
+    }
+
+    Ok(path)
+}
+
+/*
+ * started without any arguments from a terminal: offer the main modes
+ * as numbered menu instead of going straight into a migration
+ */
+
+fn menu () -> Result<Option<Args>, MigrationError>
+{
+    loop
+    {
+        println!("timetracker transition tool, what do you want to do?");
+        println!("    (1) migrate the old db");
+        println!("    (2) inspect the old db (activities and totals)");
+        println!("    (3) show where the new db goes");
+        println!("    (4) verify the old db (doctor)");
+        println!("    (5) quit");
+        print!  ("       Your choice         : ");
+        io::stdout().flush()?;
+
+        let mut choice = String::new();
+        if io::stdin().read_line(&mut choice)? == 0
+        {
+            return Ok(None);
+        }
+        println!();
+
+        let mut args : Args = Default::default();
+        match choice.trim()
+        {
+            "1" => {}
+            "2" => { args.list_activities = true; args.stats = true; }
+            "3" =>
+            {
+                let (_, dbpath) = new_db_path(&args)?;
+                println!("The new db is {:?}{}", dbpath,
+                         if dbpath.exists() { " (exists already)" } else { "" });
+                println!();
+                continue;
+            }
+            "4" => args.doctor = true,
+            "5" | "q" => return Ok(None),
+            c =>
+            {
+                println!("Unknown choice {:?}", c);
+                println!();
+                continue;
+            }
+        }
+
+        return Ok(Some(args));
+    }
+}
+
+fn main() -> ExitCode
+{
+    let args = if env::args().len() == 1 && io::stdin().is_terminal()
+    {
+        match menu()
+        {
+            Ok(Some(args)) => args,
+            Ok(None)       => return ExitCode::from(error::EXIT_OK),
+            Err(e)         =>
+            {
+                eprintln!("Error, {}", e);
+                return ExitCode::from(e.exit_code());
+            }
+        }
+    }
+    else
+    {
+        Args::parse()
+    };
+
+    round::set_rounding(args.options.precision, args.options.round_mode);
+
+    match run(&args)
+    {
+        Ok(()) => ExitCode::from(error::EXIT_OK),
+        Err(e) =>
+        {
+            eprintln!("Error, {}", e);
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+/*
+ * --print-config, what a run with the same arguments would work with;
+ * nothing gets opened, the old db doesn't even have to exist. there are
+ * no config files, a value comes from a flag, the environment (the config
+ * folder) or its default, or another flag implied it (eg --watch)
+ */
+
+fn print_config ( args : &Args )
+{
+    let mut rows : Vec<Vec<Value>> = Vec::new();
+    let given = |flags : &str| -> Vec<String> {
+        flags.split(", ").filter(|f| args.given.iter().any(|g| g == f)).map(str::to_string).collect()
+    };
+
+    let old_source = match (&args.old_path, given("--from-sql").is_empty())
+    {
+        (None, _)        => "asked for when run".to_string(),
+        (Some(_), false) => "flag (--from-sql)".to_string(),
+        (Some(_), true) if args.old_activities_db.is_some() => "flag (--old-activities-db)".to_string(),
+        (Some(_), true) if args.old_path_from_env => "env (TT_OLD_DB)".to_string(),
+        (Some(_), true)  => "argument".to_string(),
+    };
+    rows.push(vec![json!("old db"), json!(args.old_path.as_deref().unwrap_or("-")), json!(old_source)]);
+
+    match new_db_path(args)
+    {
+        Ok((dcpath, dbpath)) =>
+        {
+            let source = match given("--db-path, --merge-into")
+            {
+                g if !g.is_empty() => format!("flag ({})", g.join(", ")),
+                _ if cfg!(target_os = "linux") && env::var_os("XDG_CONFIG_HOME")
+                    .is_some_and(|d| path::Path::new(&d).is_absolute()) => "env (XDG_CONFIG_HOME)".to_string(),
+                _                  => "default (config folder of the os)".to_string(),
+            };
+            let lock = format!("{}.lock", dbpath.display());
+            rows.push(vec![json!("new db folder"), json!(dcpath.display().to_string()), json!(source)]);
+            rows.push(vec![json!("new db"), json!(dbpath.display().to_string()), json!(source)]);
+            rows.push(vec![json!("lock file"), json!(lock), json!(source)]);
+        }
+        Err(e) => rows.push(vec![json!("new db"), json!(e.to_string()), json!("-")]),
+    }
+
+    let defaults = Args::default().settings();
+    for ((flags, value), (_, default)) in args.settings().into_iter().zip(defaults)
+    {
+        let source = match given(flags)
+        {
+            g if !g.is_empty()   => format!("flag ({})", g.join(", ")),
+            _ if value != default => "implied".to_string(),
+            _                    => "default".to_string(),
+        };
+        rows.push(vec![json!(flags.split(", ").next()), json!(value), json!(source)]);
+    }
+
+    inspect::print_rows(&args.format, &["setting", "value", "source"], &rows);
+}
+
+/*
+ * with --audit-log, the outcome of every run is its last line
+ */
+
+fn run ( args : &Args ) -> Result<(), MigrationError>
+{
+    if args.print_config
+    {
+        print_config(args);
+        return Ok(());
+    }
+
+    let mut audit = AuditLog::open(&args.audit_log)?;
+    audit.record("started", json!({ "arguments" : env::args().skip(1).collect::<Vec<String>>() }))?;
+
+    let result = match args.watch
+    {
+        true  => watch(args, &mut audit),
+        false => migrate(args, &mut audit).map(|_| ()),
+    };
+
+    match &result
+    {
+        Ok(())  => audit.record("finished", json!({}))?,
+        Err(e)  => audit.record("failed", json!({ "error" : e.to_string(),
+                                                  "exit_code" : e.exit_code() }))?,
+    }
+
+    result
+}
+
+/*
+ * --watch, a --since-last-run migration every --interval seconds until
+ * interrupted; both dbs are closed in between, so the old app can keep
+ * using its db. A cycle failing on a locked or busy db is retried the
+ * next time, one that can't ever work (schema, aborted) ends the watch
+ */
+
+fn watch ( args : &Args, audit : &mut AuditLog ) -> Result<(), MigrationError>
+{
+    let interval = time::Duration::from_secs(args.interval);
+    let mut cycle = 0;
+
+    loop
+    {
+        cycle += 1;
+        let at = chrono::Local::now().format("%Y-%m-%d %H:%M:%S");
+
+        match migrate(args, audit)
+        {
+            Ok(stats) =>
+            {
+                println!("Watch cycle {} at {}: {} activities, {} history rows migrated",
+                         cycle, at, stats.activities, stats.history_rows);
+                audit.record("watch_cycle", json!({ "cycle" : cycle, "activities" : stats.activities,
+                                                    "history_rows" : stats.history_rows }))?;
+            }
+            Err(e @ (MigrationError::Schema(_) | MigrationError::Aborted(_))) => return Err(e),
+            Err(e) =>
+            {
+                println!("Watch cycle {} at {} failed, trying again next cycle: {}", cycle, at, e);
+                audit.record("watch_cycle", json!({ "cycle" : cycle, "error" : e.to_string() }))?;
+            }
+        }
+
+        println!("Next cycle in {} seconds, Ctrl-C to stop", args.interval);
+        println!();
+        thread::sleep(interval);
+    }
+}
+
+/*
+ * printed once a migration went through: where the new app finds its db,
+ * how to look at what got migrated and how to get back to before
+ */
+
+fn next_steps ( dbpath : &path::Path, existed : bool, old_path : &str )
+    -> Result<(), MigrationError>
+{
+    let restore = match backup::find(dbpath)?.first()
+    {
+        Some(b)           => format!("copy {:?} over it (--list-backups lists all backups)", b.path),
+        None if !existed  => "delete it and run again".to_string(),
+        None              => "migrate again into a fresh db, it existed before and has no \
+                              <db>.bak-<time> backup next to it".to_string(),
+    };
+
+    println!();
+    println!("Next steps:");
+    println!("    1. point the new app at its db: {:?}", dbpath);
+    println!("    2. check the hours per month:   timetracker_transitiontool --report monthly --db-path {:?}",
+             dbpath);
+    println!("    3. if something looks wrong:    {}", restore);
+    println!("       {:?} was only read, it's left as it was", old_path);
+
+    Ok(())
+}
+
+fn migrate ( args : &Args, audit : &mut AuditLog ) -> Result<MigrationStats, MigrationError>
+{
+    let started  = run_clock(args);
+    let warnings = Warnings::new(args.strict);
+
+    if let Some((a, b)) = &args.diff_only
+    {
+        return diff::old_dbs(&args.format, (a, b), &args.old_tables).map(|_| Default::default());
+    }
+    if let Some(report) = &args.report
+    {
+        return run_report(args, report).map(|_| Default::default());
+    }
+    if let Some(file) = &args.schema_only
+    {
+        let n = schema_only(args, file)?;
+        println!("Schema: {} statements written to {:?}", n, file);
+        return Ok(Default::default());
+    }
+    if let Some(file) = &args.validate_app
+    {
+        let diffs = appschema::differences(&written_schema(args)?.conn, &appschema::open_expected(file)?)?;
+        if diffs.is_empty()
+        {
+            println!("App schema: the tables written match {:?}", file);
+            return Ok(Default::default());
+        }
+        println!("App schema: {} differences between the tables written and {:?}", diffs.len(), file);
+        appschema::print(&args.format, &diffs);
+        return Err(MigrationError::Schema(format!(
+            "the new db wouldn't match the app's schema in {:?}, see above", file)));
+    }
+    if args.list_backups
+    {
+        let (_, dbpath) = new_db_path(args)?;
+        return backup::list(&args.format, &dbpath).map(|_| Default::default());
+    }
+
+    /*
+     * Explanation Primer
+     * (not for inspection modes, their output may be piped elsewhere)
+     */
+
+    if !args.inspecting()
+    {
+        println!("Small tool to transition from old timetracker to new version");
+        println!("Written for self use");
+        println!("Note: ");
+        println!("  a) certain values hard-coded (db names etc)");
+        println!("    (won't keep this tool up to date if breaking changes occur)");
+        println!("  b) no graceful error recovery here");
+        println!("    (first error ends the run, exit code tells which kind)");
+    }
+    
+    /*
+     * a broken --transform-config ends the run before anything is read
+     */
+
+    let transforms = args.transform_config.as_deref().map(TransformConfig::read).transpose()?;
+
+    /*
+     * Retrieve full db path of old db
+     */
+
+    let path = match &args.old_path
+    {
+        Some(p) =>
+        {
+            if args.old_path_from_env && !args.quiet
+            {
+                println!("Reading old db path from TT_OLD_DB: {:?}", p);
+            }
+            p.clone()
+        }
+        None    => prompt_old_path(args)?,
+    };
+    let mut phases = bench::Phases::new();
+
+    /*
+     * Read old data, either from the old db or a json dump of it;
+     * history of a large old db isn't buffered but streamed into the new
+     */
+
+    let mut stream = args.input_format == InputFormat::Sqlite
+        && !args.inspecting()
+        && stream_history(args, args.old_tables.history_db.as_deref().unwrap_or(&path))?;
+
+    let local = match args.input_format
+    {
+        InputFormat::Sqlite if args.copy_local =>
+        {
+            let copy = netfs::LocalCopy::new(&path)?;
+            if !args.inspecting()
+            {
+                println!("Reading from a local copy: {:?}", copy.path);
+            }
+            Some(copy)
+        }
+        InputFormat::Sqlite =>
+        {
+            if let Some(fstype) = netfs::network_fs(path::Path::new(&path))
+            {
+                warnings.warn(&format!(
+                    "{:?} is on a network filesystem ({}), sqlite's locking is unreliable \
+                     there; copy it to a local disk first, or use --copy-local", path, fstype))?;
+            }
+            None
+        }
+        _ => None,
+    };
+
+    let mut db_old : Option<Connection> = None;
+
+    let (mut oldact, mut oldhis) = match args.input_format
+    {
+        InputFormat::Sqlite | InputFormat::Sql =>
+        {
+            let db = match (&args.input_format, &local)
+            {
+                (InputFormat::Sql, _) => old::open_sql_dump(&path, &args.old_tables)?,
+                (_, Some(copy))       => old::open(&copy.path.to_string_lossy(), &args.old_tables)?,
+                (_, None)             => old::open(&path, &args.old_tables).map_err(|e| match e
+                {
+                    MigrationError::Io(m) if !old::is_uri(&path) && path::Path::new(&path).exists() =>
+                        MigrationError::Io(format!(
+                            "{} (on a read-only or network mount, --copy-local may help)", m)),
+                    e => e,
+                })?,
+            };
+            let has_history = schema::table_names(&db)?.contains(&args.old_tables.history);
+            if !has_history
+            {
+                if args.require_history || args.doctor
+                {
+                    return Err(MigrationError::Schema(format!(
+                        "old db has no table '{}'", args.old_tables.history)));
+                }
+                warnings.warn(&format!("old db has no table '{}', migrating the activities \
+                                        without any history", args.old_tables.history))?;
+                stream = false;
+            }
+            let combined = schema::old_history_layout(&db, &args.old_tables)?
+                == schema::HistoryLayout::Combined;
+            if args.doctor
+            {
+                if combined
+                {
+                    return Err(MigrationError::Schema(
+                        "doctor only knows the old history layout with year/month/day \
+                         columns, not the one with a single datetime column".to_string()));
+                }
+                return doctor::run(&args.format, &db, &args.old_tables).map(|_| Default::default());
+            }
+            if let Some(p) = &args.make_fixture
+            {
+                let f = fixture::make(&db, &args.old_tables, path::Path::new(p))?;
+                println!("Fixture: {} activities (names scrubbed) and {} of {} history rows \
+                          written to {:?}", f.activities, f.history, f.history_of, p);
+                if f.tables > 0
+                {
+                    println!("    {} other tables with their schema only", f.tables);
+                }
+                return Ok(Default::default());
+            }
+            if combined && !args.inspecting()
+            {
+                println!("Old history has a single datetime column, \
+                          deriving year/month/day/week from it");
+            }
+
+            /*
+             * loosely typed columns get converted while reading, unless
+             * --strict-types (inspecting just reads them)
+             */
+
+            let mismatches = match args.inspecting()
+            {
+                true  => Vec::new(),
+                false => old::type_mismatches(&db, &args.old_tables)?,
+            };
+            for m in &mismatches
+            {
+                let msg = format!("{}.{} holds {} values of type {} instead of {} (e.g. {})",
+                                  m.table, m.column, m.count, m.found, m.expected, m.sample);
+                match args.strict_types
+                {
+                    true  => eprintln!("{}", msg),
+                    false => warnings.warn(&format!("{}, reading them as {}", msg, m.expected))?,
+                }
+            }
+            if args.strict_types && !mismatches.is_empty()
+            {
+                return Err(MigrationError::Schema(format!(
+                    "{} columns hold values of another type, stopping as --strict-types is set",
+                    mismatches.len())));
+            }
+            if let (Some(zone), false) = (args.old_tables.source_timezone, args.inspecting())
+            {
+                match combined
+                {
+                    false => println!("NOTE: the old history has no times (year/month/day \
+                                       columns), {} keeps its days",
+                                      match args.convert_timezone
+                                      {
+                                          true  => "--convert-timezone",
+                                          false => "--normalize-timezone-dates",
+                                      }),
+                    true  =>
+                    {
+                        let shifts = old::timezone_shifts(&db, &args.old_tables)?;
+                        let from = args.old_tables.datetime_timezone.unwrap_or(old::SourceZone::UTC);
+                        println!("Taking the days of the datetimes (written in {}) in {}: {} \
+                                  history rows move to another day", from, zone, shifts.len());
+                        for (id, datetime, before, after) in &shifts
+                        {
+                            println!("    activity {:>5}  {:?}  {} -> {}", id, datetime, before, after);
+                            audit.record("date_shifted", json!({
+                                "activity" : id, "datetime" : datetime,
+                                "before"   : before.to_string(), "after" : after.to_string() }))?;
+                        }
+                    }
+                }
+            }
+            if args.name_encoding_report
+            {
+                let names = old::read_activity_names(&db, &args.old_tables)?;
+                inspect::name_encoding_report(&args.format, &names);
+                return Ok(Default::default());
+            }
+            let mut oldact = old::read_old_activities_in(&db, &args.old_tables)?;
+            let oldhis = if stream || !has_history { Vec::new() }
+                         else { old::read_old_history_in(&db, &args.old_tables)? };
+
+            if !args.extra_columns.is_empty()
+            {
+                let names : Vec<String> = args.extra_columns.iter()
+                    .map(|(old, _)| old.clone()).collect();
+                let mut extra = old::read_extra_columns(&db, &args.old_tables, &names)?;
+                for e in oldact.iter_mut()
+                {
+                    e.extra = extra.remove(&e.id).unwrap_or_default();
+                }
+            }
+
+            db_old = Some(db);
+            (oldact, oldhis)
+        }
+        InputFormat::Json   =>
+        {
+            if args.doctor
+            {
+                return Err(MigrationError::Schema(
+                    "doctor only works with an old sqlite db".to_string()));
+            }
+            if !args.extra_columns.is_empty()
+            {
+                return Err(MigrationError::Schema(
+                    "--extra-column only works with an old sqlite db".to_string()));
+            }
+            let (mut oldact, oldhis) = json::read_old_json(&path)?;
+            if args.old_tables.ignore_hours_total
+            {
+                for e in oldact.iter_mut() { e.hours_total = 0.; }
+            }
+            (oldact, oldhis)
+        }
+    };
+
+    if args.list_activities
+    {
+        inspect::list_activities(&args.format, &oldact);
+    }
+    if args.stats
+    {
+        inspect::stats(&args.format, &oldact, &oldhis);
+    }
+    if args.name_encoding_report
+    {
+        let names : Vec<(i32, Vec<u8>)> = oldact.iter()
+            .map(|e| (e.id, e.name.clone().into_bytes())).collect();
+        inspect::name_encoding_report(&args.format, &names);
+    }
+    if args.report_source_dups
+    {
+        inspect::source_dups(&args.format, &oldact, &transform::source_dups(&oldhis));
+    }
+    if args.dedup_activities == Some(ActivityDedup::Report)
+    {
+        inspect::duplicate_names(&args.format, &oldact, &oldhis, &transform::duplicate_names(&oldact));
+    }
+    if args.inspecting()
+    {
+        return Ok(Default::default());
+    }
+
+    let old_hours_total : f64 = oldact.iter().map(|e| e.hours_total).sum();
+    let old_hours_his   : f64 = oldhis.iter().map(|e| e.hours_on_day).sum();
+
+    if stream
+    {
+        println!("Read {} activities from {:?}, history is streamed while inserting",
+                 oldact.len(), path);
+    }
+    else
+    {
+        println!("Read {} activities and {} history rows from {:?}",
+                 oldact.len(), oldhis.len(), path);
+    }
+    phases.mark("read");
+    audit.record("read", json!({
+        "path"         : path,
+        "activities"   : oldact.len(),
+        "history_rows" : if stream { json!("streamed") } else { json!(oldhis.len()) },
+    }))?;
+
+    /*
+     * tt_activities.name is NOT NULL, and a blank name is no use either
+     */
+
+    let unnamed : Vec<i32> = oldact.iter()
+        .filter(|e| e.name.trim().is_empty()).map(|e| e.id).collect();
+    if !unnamed.is_empty() && args.strict_names
+    {
+        return Err(MigrationError::Aborted(format!(
+            "activities {:?} have no name (--strict-names is set)", unnamed)));
+    }
+    for id in old::fill_empty_names(&mut oldact)
+    {
+        warnings.warn(&format!("activity {} has no name, migrating it as {:?}", id, old::unnamed(id)))?;
+        audit.record("name_substituted", json!({ "activity" : id, "after" : old::unnamed(id) }))?;
+    }
+    if let Some(max) = args.max_name_length
+    {
+        for (id, len) in transform::long_names(&mut oldact, max, args.truncate_names)
+        {
+            warnings.warn(&match args.truncate_names
+            {
+                true  => format!("activity {} has a name of {} characters, \
+                                  truncated to {}", id, len, max),
+                false => format!("activity {} has a name of {} characters \
+                                  (above --max-name-length {})", id, len, max),
+            })?;
+            audit.record("long_name", json!({ "activity" : id, "length" : len,
+                                              "truncated" : args.truncate_names }))?;
+        }
+    }
+
+    /*
+     * Determine path for the new db
+     */
+
+    println!();
+
+    let (dcpath, dbpath) = match args.bench
+    {
+        true  => (path::PathBuf::new(), path::PathBuf::from(":memory:")),
+        false => new_db_path(args)?,
+    };
+
+    /*
+     * create folder and db file if needed
+     */
 
-use chrono::Datelike;
-use chrono::TimeZone;
-use directories::ProjectDirs;
-use rusqlite::Connection;
-use rusqlite::OpenFlags;
+    let writes = !args.dry_run && args.emit_sql.is_none() && !args.bench;
 
-/*
- * Hardcoded stuff from the new db layout
- * copied from version 0.1.0
- */
+    if args.no_create && !dbpath.exists()
+    {
+        return Err(MigrationError::Io(format!(
+            "{:?} doesn't exist and --no-create is set, \
+             run the timetracker once to create it", dbpath)));
+    }
 
-const DBNAME : &str = "productivity.db";
-
-pub const SQL_CREATE_ACT : &str =
-"CREATE TABLE tt_activities (
-    id INTEGER PRIMARY KEY,
-    name TEXT NOT NULL, 
-    added TEXT NOT NULL, 
-    hourstotal NUMERIC NOT NULL DEFAULT 0.0
-    )";
-
-pub const SQL_CREATE_HIS : &str = 
-"CREATE TABLE tt_history (
-    id INTEGER NOT NULL, 
-    year INTEGER NOT NULL, 
-    month INTEGER NOT NULL, 
-    day INTEGER NOT NULL, 
-    isoweek INTEGER NOT NULL, 
-    isoweekyear INTEGER NOT NULL,
-    hoursonday NUMERIC NOT NULL DEFAULT 0.0, 
-    date TEXT NOT NULL,
-    FOREIGN KEY (id) REFERENCES tt_activities(id)
-    )";
-
-
-fn round6 ( val : f64) -> f64
-{
-    (val * 1_000_000.).round() / 1_000_000.
-}
+    if writes
+    {
+        if !dcpath.exists()
+        {
+            println!("folder  doesn't exist, creating: {:?}", dcpath);
+            fs::create_dir_all(&dcpath)?;
+        }
+        if args.db_path.is_some()
+        {
+            check_writable(&dcpath)?;
+        }
+    }
 
-struct DBOldRowActivities {
-    id			: i32,
-    group_id	: i32,		// disregarded for new db
-    name		: String,
-    added_when	: String,
-    is_activated : i32,		// not needed anymore as well
-    hours_total : f64,
-}
+    /*
+     * one run writing to the new db at a time, until this one returns
+     * (--dry-run, --emit-sql and --bench don't write to it); taken before
+     * anything looks at the new db, so that's the state this run writes over
+     */
 
-struct DBOldRowHistory {
-    id_activity  : i32,
-    year		 : i32,
-    month		 : i32,
-    day			 : i32,
-    weeknumber   : i32,
-    hours_on_day : f64,
-    date	     : String,
-}
+    let _lock = match writes
+    {
+        true  => Some(RunLock::acquire(&dbpath, &started.to_rfc3339())?),
+        false => None,
+    };
+
+    if let Some(fstype) = netfs::network_fs(&dcpath)
+    {
+        warnings.warn(&format!(
+            "{:?} is on a network filesystem ({}), a new db written there may end up \
+             corrupted; better migrate to a local --db-path and copy the result over",
+            dcpath, fstype))?;
+    }
+
+    let dbpath_exists: bool = dbpath.exists();
 
-fn main()
-{
     /*
-     * Explanation Primer
+     * --since-last-run, only history from the day of the last one on (that
+     * day again, its rows may have grown since)
      */
 
-    println!("Small tool to transition from old timetracker to new version");
-    println!("Written for self use");
-    println!("Note: ");
-    println!("  a) certain values hard-coded (db names etc)");
-    println!("    (won't keep this tool up to date if breaking changes occur)");
-    println!("  b) no graceful error checking here");
-    println!("    (expect panics as soon as something doesn't work)");
-    
+    let (since, last_sums) = match (args.since_last_run, dbpath_exists)
+    {
+        (true, true) =>
+        {
+            let db = NewDb::open(&dbpath, args.out_key.as_deref())?;
+            (db.meta("last_run")?, db.meta("activity_checksums")?)
+        }
+        _            => (None, None),
+    };
+
+    if writes && args.check_space
+    {
+        let old_paths : Vec<&str> = [Some(path.as_str()), args.old_tables.history_db.as_deref()]
+            .into_iter().flatten().collect();
+        check_free_space(&old_paths, &dcpath)?;
+    }
+    if !dbpath_exists
+    {
+        match (args.dry_run, &args.emit_sql)
+        {
+            _ if args.bench  => println!("Bench: migrating into an in-memory db, nothing gets written"),
+            (true, _)        => println!("db file doesn't exist, would be created: {:?}", dbpath),
+            (false, Some(_)) => println!("db file doesn't exist, the sql script creates the tables"),
+            (false, None)    => println!("db file doesn't exist, creating: {:?}", dbpath),
+        }
+    }
     /*
-     * Retrieve full db path of old db
+     * if db already exists warn user!
+     */
+    else
+    {
+        println!("db already exists at {:?}", dbpath);
+        println!("NOTE: we'll update the new db w/ values from the old!");
+        println!("MAKE SURE YOUR DB IS PROPERLY BACKED UP BEFORE THIS!");
+        println!("(seriously just do it...");
+    }
+
+    /*
+     * --activity-checksums, hashed before anything changes the old data;
+     * with hashes of the last run only what differs from it is migrated
      */
 
-    let mut path   : String = Default::default();
-    let mut db_old : Connection;
+    let sums = match args.activity_checksums
+    {
+        true  => checksum::per_activity(&oldact, &oldhis),
+        false => Default::default(),
+    };
+    let last_sums : Option<BTreeMap<i32, String>> = match last_sums.filter(|_| args.activity_checksums)
+    {
+        Some(json) => Some(serde_json::from_str(&json).map_err(|e| MigrationError::Schema(format!(
+            "tt_migration_meta.activity_checksums isn't valid: {}", e)))?),
+        None       => None,
+    };
+    let unchanged : HashSet<i32> = match &last_sums
+    {
+        Some(last) => sums.iter().filter(|(id, sum)| last.get(id) == Some(sum)).map(|(id, _)| *id).collect(),
+        None       => HashSet::new(),
+    };
+
+    match &since
+    {
+        Some(_) if last_sums.is_some() =>
+        {
+            let (dropped, n_his) = transform::drop_activities(
+                &mut oldact, &mut oldhis, |e| unchanged.contains(&e.id));
 
-    println!("Enter your full db path, eg: /home/user/foo/bar/productivity.db");
-    print!  ("       Your entry          : ");
-    io::stdout().flush().unwrap();
-    io::stdin().read_line(&mut path).expect("Failed to read line");
-    path = path.trim().to_string();
+            println!("Activity checksums: {} activities changed since the last run, \
+                      migrating all of their history", oldact.len());
+            for e in &oldact
+            {
+                println!("    {:>5}  {}", e.id, e.name);
+                audit.record("activity_changed", json!({ "activity" : e.id, "name" : e.name }))?;
+            }
+            report_dropped(audit, "Unchanged since the last run, skipping", &dropped, n_his, stream)?;
+        }
+        Some(day) =>
+        {
+            let n = oldhis.len();
+            oldhis.retain(|e| e.date >= *day);
+            println!("Since last run on {}: skipping {} older history rows", day, n - oldhis.len());
+        }
+        None if args.since_last_run =>
+            println!("No earlier run recorded in the new db, migrating all of the history"),
+        None => {}
+    }
 
     /*
-     * Open for reading
+     * drop deactivated activities, those not selected by name, all but
+     * --activity, along with their history
      */
 
-    db_old = Connection::open_with_flags(
-        &path, OpenFlags::SQLITE_OPEN_READ_ONLY
-        ).unwrap();
+    let selected = select(&mut oldact, &mut oldhis, &args.options)?;
 
-    db_old.execute("PRAGMA foreign_keys=OFF;", rusqlite::params![]).unwrap();
+    if let Some((dropped, n_his)) = &selected.inactive
+    {
+        report_dropped(audit, "Skipping inactive", dropped, *n_his, stream)?;
+    }
+    if let Some((dropped, n_his)) = &selected.by_name
+    {
+        report_dropped(audit, "Name filter, skipping", dropped, *n_his, stream)?;
+
+        // as the filter left them, before --activity picked one
+        let left : Vec<&OldActivity> = oldact.iter()
+            .chain(selected.activity.iter().flat_map(|(_, (d, _))| d))
+            .collect();
+        println!("Name filter: migrating {} activities:", left.len());
+        for e in left
+        {
+            println!("    {:>5}  {}", e.id, e.name);
+        }
+    }
+    if let Some((keep, (dropped, n_his))) = &selected.activity
+    {
+        audit.record("skipped", json!({ "reason" : "--activity", "activities" : dropped.len(),
+                                        "history_rows" : n_his }))?;
 
-    println!("Opened {:?} read-only", path);
+        if stream
+        {
+            println!("Activity: migrating only {} ({}), other history is skipped while streaming",
+                     oldact[0].name, keep);
+        }
+        else
+        {
+            println!("Activity: migrating only {} ({}), skipping {} other history rows",
+                     oldact[0].name, keep, n_his);
+        }
+    }
 
     /*
-     * Determine path for the new db
+     * days that can't be migrated, before anything is written; streamed
+     * ones get read for it once more
      */
 
-    let projdir = ProjectDirs::from("dev", "sintheta", "timetracker");
-    let dcpath : path::PathBuf;
-    let dbpath : path::PathBuf;
+    let invalid = |e : &OldHistory| transform::date_problem(e, args.options.years, &args.options.trust_dates);
+    let mut n_invalid = 0;
+    let mut report_invalid = |e : &OldHistory, why : String| -> Result<(), MigrationError> {
+        if n_invalid == 0
+        {
+            println!("History rows with an invalid date:");
+        }
+        n_invalid += 1;
+        println!("    activity {:>5}  {:?}  {}", e.id_activity, e.date, why);
+        audit.record("invalid_date", json!({
+            "activity" : e.id_activity, "date" : e.date, "year" : e.year,
+            "month" : e.month, "day" : e.day, "problem" : why }))
+    };
+    match (stream, &db_old)
+    {
+        (true, Some(db)) => old::for_each_history_paged(db, &args.old_tables, args.page_size, |e| {
+            if let Some(why) = invalid(&e) { report_invalid(&e, why)?; }
+            Ok(true)
+        })?,
+        _ => for e in &oldhis
+        {
+            if let Some(why) = invalid(e) { report_invalid(e, why)?; }
+        },
+    }
+    if n_invalid > 0
+    {
+        match args.options.skip_invalid_dates
+        {
+            false => return Err(MigrationError::Schema(format!(
+                "{} history rows have an invalid date (listed above), \
+                 --invalid-date-policy skip leaves them out", n_invalid))),
+            true  =>
+            {
+                println!("Invalid dates: skipping the {} history rows above", n_invalid);
+                oldhis.retain(|e| invalid(e).is_none());
+            }
+        }
+    }
 
-    let dcpath_exists: bool;
-    let dbpath_exists: bool;
+    /*
+     * date text and numeric date fields have to agree
+     */
 
-    println!();
+    let n_mismatch = transform::check_dates(&mut oldhis, &args.options.trust_dates, &warnings)?;
+    report_date_check(args, n_mismatch);
+    if n_mismatch > 0
+    {
+        audit.record("date_check", json!({ "mismatches" : n_mismatch }))?;
+    }
+
+    /*
+     * one history row per activity and day
+     */
+
+    let mut altered : HashSet<i32> = HashSet::new();
 
-    if let Some(d) = projdir
+    if let Some(config) = &transforms
     {
-        dcpath = d.config_dir().to_path_buf();
-        dbpath = dcpath.join(DBNAME);
+        let mut n_changed = 0;
+        for e in oldhis.iter_mut()
+        {
+            if config.history(e, args.options.round_mode)
+            {
+                altered.insert(e.id_activity);
+                n_changed += 1;
+            }
+        }
+
+        if config.changes_history() && stream
+        {
+            warnings.warn("the history is streamed, its hours get transformed as they're \
+                           written but the activity totals aren't recomputed from them")?;
+        }
+        else if config.changes_history()
+        {
+            println!("Transform config: hours of {} history rows changed", n_changed);
+            audit.record("transformed", json!({ "field" : "history.hours_on_day", "history_rows" : n_changed }))?;
+        }
     }
-    else 
+    /*
+     * merge, compact, dedup the history; totals have to match it again
+     * after that, then only a slice of the old data if asked for (for
+     * trying things out) and no activities left without history
+     */
+
+    let reshaped = reshape(&mut oldact, &mut oldhis, altered, &args.options);
+
+    if let Some(dups) = &reshaped.source_dups
+    {
+        println!("Merging source duplicates: {} dates with more than one history row", dups.len());
+        for d in dups
+        {
+            let listed : Vec<String> = d.hours.iter().map(f64::to_string).collect();
+            println!("    {:>5}  {}  {} -> {} hours", d.id, d.date, listed.join(" + "), round6(d.hours.iter().sum()));
+            audit.record("source_dups_merged", json!({
+                "activity" : d.id, "date" : d.date, "hours" : d.hours }))?;
+        }
+    }
+    if let Some(n_merged) = reshaped.compacted
+    {
+        println!("Compacting history: {} rows merged into same-day entries", n_merged);
+        audit.record("history_compacted", json!({ "rows_merged" : n_merged }))?;
+    }
+    if let Some(n_dropped) = reshaped.deduped
     {
-        panic!("Could not retrieve OS specific configuration folder!");
+        println!("Dedup: {} history rows skipped, their activity already has a row \
+                  on that date", n_dropped);
+        audit.record("skipped", json!({ "reason" : "--dedup-history", "history_rows" : n_dropped }))?;
+    }
+    if let Some(changed) = &reshaped.totals
+    {
+        println!("Reconciling totals: {} activities changed", changed.len());
+        for (id, name, before, after) in changed
+        {
+            println!("    {:>5}  {}  {} -> {} hours", id, name, before, after);
+            audit.record("total_recomputed", json!({
+                "activity" : id, "name" : name, "before" : before, "after" : after }))?;
+        }
+    }
+    if let Some((dropped, n_his)) = &reshaped.limit_activities
+    {
+        report_dropped(audit, "Limit, skipping", dropped, *n_his, stream)?;
+    }
+    if let Some((n, of)) = reshaped.limit
+    {
+        println!("Limit: migrating only the first {} of {} history rows", n, of);
+        audit.record("skipped", json!({ "reason" : "--limit", "history_rows" : of - n }))?;
+    }
+    if let Some(sampled) = &reshaped.sample
+    {
+        println!("Sample: migrating {} random history rows of {} (seed {}), \
+                  of {} activities", sampled.history_rows, sampled.of, args.options.sample_seed, sampled.activities);
+        audit.record("skipped", json!({ "reason" : "--sample",
+                                        "history_rows" : sampled.of - sampled.history_rows,
+                                        "activities" : sampled.dropped.len() }))?;
+    }
+    if let Some(dropped) = &reshaped.pruned
+    {
+        println!("Pruning {} activities without history:", dropped.len());
+        for e in dropped
+        {
+            println!("    {:>5}  {}  ({} hours total)", e.id, e.name, e.hours_total);
+            audit.record("skipped", json!({ "reason" : "no history", "activity" : e.id, "name" : e.name }))?;
+        }
     }
 
     /*
-     * create folder and db file if needed
+     * new names, only the name changes (history stays linked by id)
      */
 
-    dcpath_exists = dcpath.exists();
-    dbpath_exists = dbpath.exists();
-
-    if !dcpath_exists
+    if !args.rename_activity.is_empty()
     {
-        println!("folder  doesn't exist, creating: {:?}", dcpath);
-        fs::create_dir_all(&dcpath).unwrap();
+        let (applied, unmatched) = transform::rename_activities(&mut oldact, &args.rename_activity);
+
+        println!("Renaming {} activities:", applied.len());
+        for (id, from, to) in &applied
+        {
+            println!("    {:>5}  {} -> {}", id, from, to);
+            audit.record("renamed", json!({ "activity" : id, "before" : from, "after" : to }))?;
+        }
+        for name in &unmatched
+        {
+            warnings.warn(&format!("--rename-activity {:?} matches no activity being migrated", name))?;
+        }
     }
-    if !dbpath_exists
+
+    /*
+     * --transform-config on the activities, after the renames (they match
+     * the names as they were)
+     */
+
+    if let Some(config) = &transforms
     {
-        println!("db file doesn't exist, creating: {:?}", dbpath);
+        let (renamed, blank, n_totals) = config.activities(&mut oldact, args.options.round_mode);
+
+        println!("Transform config: {} names and {} totals changed", renamed.len(), n_totals);
+        for (id, from, to) in &renamed
+        {
+            println!("    {:>5}  {:?} -> {:?}", id, from, to);
+            audit.record("transformed", json!({ "field" : "activities.name", "activity" : id,
+                                                "before" : from, "after" : to }))?;
+        }
+        if n_totals > 0
+        {
+            audit.record("transformed", json!({ "field" : "activities.hours_total", "activities" : n_totals }))?;
+        }
+        for id in &blank
+        {
+            warnings.warn(&format!("--transform-config leaves the name of activity {} blank, keeping it", id))?;
+        }
     }
+
     /*
-     * if db already exists warn user!
+     * names ending in a suffix (eg the group, as some old dbs have it)
      */
-    else
+
+    if let Some(pattern) = &args.strip_suffix
     {
-        println!("db already exists at {:?}", dbpath);
-        println!("NOTE: we'll update the new db w/ values from the old!");
-        println!("MAKE SURE YOUR DB IS PROPERLY BACKED UP BEFORE THIS!");
-        println!("(seriously just do it...");
+        let stripped = transform::strip_suffixes(&mut oldact, pattern);
+
+        println!("Stripping suffixes matching {:?} from {} activity names:", pattern, stripped.len());
+        for (id, from, to, suffix) in &stripped
+        {
+            println!("    {:>5}  {:?} -> {:?}", id, from, to);
+            audit.record("suffix_stripped", json!({
+                "activity" : id, "before" : from, "after" : to, "suffix" : suffix }))?;
+        }
     }
 
     /*
-     * iterate over old db data; activities
+     * --merge-by-name, old activities named alike; `folded` maps the ones
+     * merged into another onto it, for their history
      */
 
-    let mut stmt = db_old
-        .prepare(&format!("SELECT * FROM activities"))
-        .unwrap();
+    let mut folded : HashMap<i32, i32> = HashMap::new();
+
+    let duplicates = match args.merge_by_name || args.dedup_activities.is_some()
+    {
+        true  => transform::duplicate_names(&oldact),
+        false => Vec::new(),
+    };
+    if duplicates.is_empty() && args.dedup_activities.is_some()
+    {
+        println!("Dedup activities: no activity names are used more than once");
+    }
+    if !duplicates.is_empty()
+    {
+        let policy = args.on_duplicate_name;
+        let names : HashMap<i32, String> = oldact.iter().map(|e| (e.id, e.name.clone())).collect();
+
+        println!("{} activity names are used more than once:", duplicates.len());
+        for (name, ids) in &duplicates
+        {
+            let listed : Vec<String> = ids.iter().map(|id| format!("{} ({})", id, names[id])).collect();
+            println!("    {:?}  {}", name, listed.join(", "));
+        }
+
+        let renamed = match policy
+        {
+            DuplicateName::Error  => return Err(MigrationError::Aborted(
+                "activities named alike can't be told apart by name, \
+                 pick --on-duplicate-name merge or suffix".to_string())),
+            DuplicateName::Merge  => { folded = transform::merge_duplicates(&mut oldact, &duplicates); Vec::new() }
+            DuplicateName::Suffix => transform::suffix_duplicates(&mut oldact, &duplicates),
+        };
+
+        for (name, ids) in &duplicates
+        {
+            match policy
+            {
+                DuplicateName::Merge => println!("    {:?}  merged into {}", name, ids[0]),
+                _                    =>
+                    for (id, from, to) in renamed.iter().filter(|(id, _, _)| ids.contains(id))
+                    {
+                        println!("    {:?}  {} renamed {:?} -> {:?}", name, id, from, to);
+                    },
+            }
+            audit.record("duplicate_name", json!({
+                "name" : name, "activities" : ids, "resolution" : policy.name(),
+                "kept" : ids[0],
+                "renamed" : renamed.iter().filter(|(id, _, _)| ids.contains(id))
+                                   .map(|(id, _, to)| json!({ "activity" : id, "name" : to }))
+                                   .collect::<Vec<_>>() }))?;
+        }
+    }
 
-    let iter = stmt.query_map([], |row| {
-        Ok(DBOldRowActivities {
-            id			: row.get(0)?,
-            group_id	: row.get(1)?,
-            name		: row.get(2)?,
-            added_when	: row.get(3)?,
-            is_activated: row.get(4)?,
-            hours_total : row.get(5)?,
+    /*
+     * days without history, once it's known which activities end up as one
+     */
 
-        })
-    }).unwrap();
+    if args.zero_fill_gaps
+    {
+        let filled = transform::zero_fill_gaps(&mut oldhis, &folded);
 
-    let mut oldact : Vec<DBOldRowActivities> = Vec::new();
-    for e in iter { oldact.push(e.unwrap()); }
+        println!("Zero-filling gaps: {} days without history of {} activities get a 0 hours row",
+                 filled.values().sum::<usize>(), filled.len());
+        for (id, n) in &filled
+        {
+            audit.record("gaps_filled", json!({ "activity" : id, "history_rows" : n }))?;
+        }
+    }
 
     /*
-     * iterate over old db data; history
+     * activities without a usable added date
      */
 
+    if !args.options.added_fallback.is_empty()
+    {
+        let mut first_days : HashMap<i32, NaiveDate> = HashMap::new();
+        let mut first_day = |e : &OldHistory| {
+            if let Some(d) = old::parse_datetime(&e.date)
+            {
+                let f = first_days.entry(e.id_activity).or_insert(d);
+                *f = (*f).min(d);
+            }
+        };
+
+        if args.options.added_fallback.iter().any(|f| matches!(f, AddedFallback::EarliestHistory))
+        {
+            match (stream, &db_old)
+            {
+                (true, Some(db)) =>
+                    old::for_each_history_paged(db, &args.old_tables, args.page_size, |e| { first_day(&e); Ok(true) })?,
+                _ => oldhis.iter().for_each(first_day),
+            }
+        }
+
+        let changed = transform::fill_added(
+            &mut oldact, &first_days, &args.options.added_fallback, started.date_naive());
+
+        if !changed.is_empty()
+        {
+            println!("Added date fallback: {} activities without a usable added date", changed.len());
+        }
+        for c in &changed
+        {
+            match &c.after
+            {
+                Some((day, how)) =>
+                    println!("    {:>5}  {}  {:?} -> {} ({})", c.id, c.name, c.before, day, how),
+                None =>
+                    println!("    {:>5}  {}  {:?} kept, no fallback applies", c.id, c.name, c.before),
+            }
+            audit.record("added_substituted", json!({
+                "activity" : c.id, "name" : c.name, "before" : c.before,
+                "after"    : c.after.as_ref().map(|(day, _)| day),
+                "fallback" : c.after.as_ref().map(|(_, how)| how),
+            }))?;
+        }
+    }
+
     /*
-    let mut stmt = db_old
-        .prepare(&format!("SELECT * FROM history WHERE date >= '2024-01-22'"))
-        .unwrap();
+     * let the user eyeball the numbers before anything gets written
      */
 
-    let mut stmt = db_old
-        .prepare(&format!("SELECT * FROM history"))
-        .unwrap();
+    if args.confirm_totals
+    {
+        let new_hours_total : f64 = oldact.iter().map(|e| e.hours_total).sum();
+        let (old_his, new_his) = match (stream, &db_old)
+        {
+            (true, Some(db)) =>
+            {
+                let ids : HashSet<i32> = oldact.iter().map(|e| e.id).collect();
+                let (mut all, mut kept, mut n) = (0., 0., 0);
+                old::for_each_history_paged(db, &args.old_tables, args.page_size, |e| {
+                    all += e.hours_on_day;
+                    if ids.contains(&e.id_activity) && args.options.limit.is_none_or(|l| n < l)
+                    {
+                        kept += e.hours_on_day;
+                        n += 1;
+                    }
+                    Ok(true)
+                })?;
+                (all, kept)
+            }
+            _ => (old_hours_his, oldhis.iter().map(|e| e.hours_on_day).sum()),
+        };
 
+        confirm_totals((old_hours_total, old_his), (new_hours_total, new_his),
+                       args.options.tolerance, &warnings)?;
+    }
 
-    let iter = stmt.query_map([], |row| {
-        Ok(DBOldRowHistory {
-            id_activity : row.get(0)?, 
-            year		: row.get(1)?, 
-            month		: row.get(2)?, 
-            day			: row.get(3)?, 
-            weeknumber	: row.get(4)?, 
-            hours_on_day: row.get(5)?, 
-            date		: row.get(6)?, 
-        })
-    }).unwrap();
+    if args.dry_run
+    {
+        return dry_run(args, &db_old, (&oldact, &oldhis), stream, &dbpath).map(|_| Default::default());
+    }
 
-    let mut oldhis : Vec<DBOldRowHistory> = Vec::new();
-    for e in iter { oldhis.push(e.unwrap()); }
+    phases.mark("transform");
 
     /*
      * open new db for read/write
      */
 
-    let db_new = Connection::open(dbpath).unwrap();
-    db_new.execute("PRAGMA foreign_keys=OFF;", rusqlite::params![]).unwrap();
+    /*
+     * --atomic-file, from here on everything goes to the temp file; it's
+     * removed again on any early return
+     */
+
+    let atomic = match args.atomic_file
+    {
+        true  => Some(AtomicFile::new(&dbpath, dbpath_exists)?),
+        false => None,
+    };
+    let writepath = atomic.as_ref().map_or(dbpath.clone(), |a| a.temp.clone());
+    if let Some(a) = &atomic
+    {
+        println!("Writing to {:?}, renamed over the new db once done", a.temp);
+    }
+
+    let mut db_new = match &args.emit_sql
+    {
+        Some(p) => NewDb::emitting(&dbpath, dbpath_exists, path::Path::new(p), args.out_key.as_deref())?,
+        None    => NewDb::open(&writepath, args.out_key.as_deref())?,
+    };
+    db_new.verbose_sql = args.verbose_sql;
+    db_new.set_synchronous(args.synchronous)?;
+    if args.synchronous != "FULL"
+    {
+        println!("NOTE: synchronous={}, a crash during this run may leave a broken db",
+                 args.synchronous);
+    }
+
+    /*
+     * --strict writes everything in one transaction, a warning while
+     * writing leaves the new db as it was; otherwise every row commits on
+     * its own, which is also what leaves --continue-on-insert-error with
+     * the rows that did go in
+     */
+
+    if warnings.strict() || db_new.emitting_sql()
+    {
+        db_new.begin()?;
+    }
 
     /*
      * create tables in db (if db is new)
      */
 
+    db_new.dedup_history   = args.options.dedup_history;
+    db_new.refresh_history = args.since_last_run;
     if !dbpath_exists
     {
-        db_new.execute(SQL_CREATE_ACT, ()).unwrap();
-        db_new.execute(SQL_CREATE_HIS, ()).unwrap();
+        db_new.create_tables()?;
+    }
+    else if args.options.dedup_history
+    {
+        db_new.add_history_unique_index()?;
+    }
+
+    /*
+     * only add to dbs whose rows were written in the same layout
+     */
+
+    match db_new.meta("schema_version")?
+    {
+        Some(v) if v != new::SCHEMA_VERSION =>
+        {
+            println!("Existing db was written with schema version {}, \
+                      this tool writes {}", v, new::SCHEMA_VERSION);
+            if !args.force
+            {
+                return Err(MigrationError::Schema(format!(
+                    "new db has schema version {}, expected {} (--force to mix them anyway)",
+                    v, new::SCHEMA_VERSION)));
+            }
+            println!("--force is set, continuing anyway");
+        }
+        Some(_) => {}
+        None if dbpath_exists =>
+            println!("Existing db has no schema version recorded, assuming {}",
+                     new::SCHEMA_VERSION),
+        None => {}
+    }
+    db_new.set_meta("schema_version", new::SCHEMA_VERSION)?;
+    db_new.set_meta("tool_version", &new::tool_version())?;
+
+    if let (false, Some(db)) = (args.extra_columns.is_empty(), &db_old)
+    {
+        let types = schema::columns(db, &args.old_tables.activities)?;
+        let columns : Vec<(String, String)> = args.extra_columns.iter()
+            .map(|(old, new)| {
+                let t = types.iter().find(|(n, _)| n == old).map(|(_, t)| t.clone());
+                (new.clone(), t.unwrap_or_default())
+            })
+            .collect();
+
+        db_new.add_extra_columns(&columns)?;
+        db_new.extra_columns = args.extra_columns.iter().map(|(_, new)| new.clone()).collect();
+    }
+    if args.store_minutes
+    {
+        db_new.add_minute_columns()?;
+        db_new.store_minutes = true;
+    }
+    if args.stamp_migrated
+    {
+        db_new.add_stamp_columns()?;
+        db_new.migrated_at = Some(started.format("%Y-%m-%dT%H:%M:%S%:z").to_string());
     }
 
     /*
      * enter activities into new db
      */
 
-    for e in oldact
+    let mut stats : MigrationStats = Default::default();
+    let mut sum_act = Checksum::new();
+    let mut sum_his = Checksum::new();
+
+    let mut failures   : Vec<(String, String)> = Vec::new();
+    let mut failed_ids : HashSet<i32> = HashSet::new();
+
+    let mut quarantine : Quarantine = Default::default();
+
+    let mut lost_minutes : Vec<String> = Vec::new();
+    let mut minute_check = |what : &dyn Fn() -> String, hours : f64| {
+        if args.store_minutes && new::loses_minutes(hours)
+        {
+            lost_minutes.push(format!("{}: {} hours -> {} minutes",
+                                      what(), round6(hours), new::minutes(hours)));
+        }
+    };
+
+    /*
+     * --prefer-existing, activities already in the new db are merged
+     * instead of inserted: their name and added date stay, the total is
+     * the better one of both; these values then count as migrated
+     *
+     * with --merge-by-name the one of the same name is found instead of
+     * the one of the same id; `merged` maps old ids to the existing ones
+     */
+
+    let mut merged : HashMap<i32, i32> = HashMap::new();
+
+    if (args.prefer_existing || args.merge_by_name) && dbpath_exists
+    {
+        let by_name : HashMap<String, i32> = match args.merge_by_name
+        {
+            true  => db_new.activity_names()?.into_iter().rev()
+                         .map(|(id, name)| (transform::normalized_name(&name), id)).collect(),
+            false => HashMap::new(),
+        };
+
+        println!("Activities already in the new db:");
+        for e in oldact.iter_mut()
+        {
+            let id = match by_name.get(&transform::normalized_name(&e.name))
+            {
+                Some(id)                     => *id,
+                None if args.merge_by_name   => continue,
+                None                         => e.id,
+            };
+            let Some((name, added, hours)) = db_new.activity(id)? else { continue };
+
+            let keep = transform::keep_existing_total(hours, e.hours_total);
+            if !keep
+            {
+                db_new.update_total(id, e.hours_total)?;
+            }
+            let by = match id == e.id
+            {
+                true  => String::new(),
+                false => format!(" (by name, old id {})", e.id),
+            };
+            println!("    {:>5}  {}  exists{}, total {} (old {}, new db {})", id, name, by,
+                     if keep { "kept" } else { "updated" }, round6(e.hours_total), hours);
+            audit.record("merged", json!({
+                "activity" : id, "old_activity" : e.id, "old_total" : round6(e.hours_total),
+                "existing_total" : hours, "kept_existing" : keep }))?;
+
+            if keep { e.hours_total = hours; }
+            e.name       = name;
+            e.added_when = added;
+            merged.insert(e.id, id);
+        }
+        println!("Merged {} activities into existing ones", merged.len());
+    }
+
+    db_new.remap_ids = args.remap_ids;
+    let remapping = args.remap_ids || args.merge_by_name;
+    let mut remap : HashMap<i32, i32> = merged.clone();
+
+    for e in oldact.iter().filter(|e| !merged.contains_key(&e.id))
+    {
+        let mut new_id = e.id;
+        match insert_failure(args, &mut failures, || format!("activity {} ({})", e.id, e.name),
+                             db_new.insert_activity(e).map(|id| new_id = id))?
+        {
+            None      =>
+            {
+                stats.activities += 1;
+                minute_check(&|| format!("activity {} ({})", e.id, e.name), e.hours_total);
+                remap.insert(e.id, new_id);
+            }
+            Some(why) =>
+            {
+                failed_ids.insert(e.id);
+                quarantine.activity(e, &why);
+            }
+        }
+    }
+
+    /*
+     * --remap-ids (and --merge-by-name): from here on the activities go by
+     * their new ids, the history rows get moved over to them as they are
+     * inserted; rows of activities that failed to insert have nowhere to go
+     */
+
+    let history_id = |id : i32| -> Option<i32> {
+        let id = folded.get(&id).copied().unwrap_or(id);
+        match remapping
+        {
+            true  => remap.get(&id).copied(),
+            false => (!failed_ids.contains(&id)).then_some(id),
+        }
+    };
+
+    if remapping
+    {
+        let moved : Vec<&old::OldActivity> = oldact.iter()
+            .filter(|e| remap.get(&e.id).is_some_and(|n| *n != e.id)).collect();
+        if args.remap_ids || !moved.is_empty()
+        {
+            println!("Remapped activity ids:");
+        }
+        for e in moved
+        {
+            println!("    {:>5} -> {:>5}  {}", e.id, remap[&e.id], e.name);
+            audit.record("id_remapped", json!({ "before" : e.id, "after" : remap[&e.id] }))?;
+        }
+        oldact.retain(|e| remap.contains_key(&e.id));
+        for e in oldact.iter_mut() { e.id = remap[&e.id]; }
+    }
+    let migrated_ids : HashSet<i32> = oldact.iter()
+        .map(|e| e.id)
+        .filter(|id| remapping || !failed_ids.contains(id))
+        .collect();
+
+    let mut ranges = match (args.verify_checksums, args.parallel_verify)
     {
-        db_new.execute(
-                "INSERT INTO tt_activities 
-                (id, name, added, hourstotal) 
-                VALUES (?1, ?2, ?3, ?4)",
-                rusqlite::params![
-                	e.id,
-                    e.name,
-                    e.added_when,
-                    round6(e.hours_total),
-                ]).unwrap();
+        (true, Some(n)) if !db_new.emitting_sql() && !args.bench =>
+        {
+            let ids : Vec<i32> = migrated_ids.iter().copied().collect();
+            Some(checksum::Ranges::new(&ids, n))
+        }
+        _ => None,
+    };
+
+    if args.verify_checksums
+    {
+        let mut sorted : Vec<&old::OldActivity> = oldact.iter()
+            .filter(|e| migrated_ids.contains(&e.id)).collect();
+        sorted.sort_by_key(|e| e.id);
+        for e in sorted
+        {
+            sum_act.add_activity(e);
+            if let Some(r) = &mut ranges { r.add_activity(e); }
+        }
     }
 
     /*
      * enter all history into new db
      */
 
-    for e in oldhis
+    if let (true, Some(db)) = (stream, &db_old)
+    {
+        let mut n_inserted = 0;
+        let mut n_skipped  = 0;
+        let mut n_mismatch = 0;
+        let mut progress   = Progress::new(None, args.quiet);
+
+        old::for_each_history_paged(db, &args.old_tables, args.page_size, |mut e : OldHistory| {
+            if args.options.limit.is_some_and(|n| n_inserted >= n)
+            {
+                return Ok(false);
+            }
+            if transform::date_problem(&e, args.options.years, &args.options.trust_dates).is_some()
+            {
+                return Ok(true);
+            }
+            if let Some(config) = &transforms
+            {
+                config.history(&mut e, args.options.round_mode);
+            }
+            match history_id(e.id_activity).filter(|id| migrated_ids.contains(id))
+            {
+                Some(id) => e.id_activity = id,
+                None     =>
+                {
+                    n_skipped += 1;
+                    return Ok(true);
+                }
+            }
+            n_mismatch += transform::check_dates(std::slice::from_mut(&mut e), &args.options.trust_dates, &warnings)?;
+            match insert_failure(args, &mut failures, || history_row(&e), db_new.insert_history(&e))?
+            {
+                None      =>
+                {
+                    if args.verify_checksums { sum_his.add_history(&e); }
+                    if let Some(r) = &mut ranges { r.add_history(&e); }
+                    minute_check(&|| history_row(&e), e.hours_on_day);
+                    n_inserted += 1;
+                }
+                Some(why) => quarantine.history(&e, &why),
+            }
+            progress.inc();
+            Ok(true)
+        })?;
+        progress.finish();
+
+        report_date_check(args, n_mismatch);
+        if args.options.limit.is_some_and(|n| n_inserted >= n)
+        {
+            println!("Limit: stopped after {} history rows", n_inserted);
+        }
+        println!("Streamed {} history rows, skipped {} of activities not migrated",
+                 n_inserted, n_skipped);
+        stats.history_rows = n_inserted;
+    }
+    else
+    {
+        let mut progress = Progress::new(Some(oldhis.len() as u64), args.quiet);
+
+        let mut n_orphaned = 0;
+
+        for e in oldhis.iter_mut()
+        {
+            match history_id(e.id_activity)
+            {
+                None     =>
+                {
+                    n_orphaned += 1;
+                    quarantine.history(e, "its activity failed to insert");
+                }
+                Some(id) =>
+                {
+                    e.id_activity = id;
+                    match insert_failure(args, &mut failures, || history_row(e), db_new.insert_history(e))?
+                    {
+                        None      =>
+                        {
+                            if args.verify_checksums { sum_his.add_history(e); }
+                            if let Some(r) = &mut ranges { r.add_history(e); }
+                            minute_check(&|| history_row(e), e.hours_on_day);
+                            stats.history_rows += 1;
+                        }
+                        Some(why) => quarantine.history(e, &why),
+                    }
+                }
+            }
+            progress.inc();
+        }
+        progress.finish();
+
+        if n_orphaned > 0
+        {
+            println!("Skipped {} history rows of activities that failed to insert", n_orphaned);
+        }
+    }
+
+    stats.read_date_range(&db_new.conn)?;
+    stats.read_years(&db_new.conn)?;
+
+    if db_new.ignored.get() > 0
+    {
+        println!("Dedup: {} history rows skipped, the new db has a row of their \
+                  activity on that date already", db_new.ignored.get());
+        stats.history_rows -= db_new.ignored.get();
+        audit.record("skipped", json!({ "reason" : "already in the new db",
+                                        "history_rows" : db_new.ignored.get() }))?;
+    }
+
+    if !lost_minutes.is_empty()
+    {
+        println!();
+        warnings.warn(&format!("{} rows lose time below a minute in the minute columns \
+                                (hours are stored unchanged):\n    {}",
+                               lost_minutes.len(), lost_minutes.join("\n    ")))?;
+    }
+
+    /*
+     * --only-recompute, the totals the activities were written without
+     */
+
+    let mut recomputed = Vec::new();
+    if args.only_recompute
+    {
+        let mut ids : Vec<i32> = migrated_ids.iter().copied().collect();
+        ids.sort();
+        recomputed = db_new.recompute_totals(&ids)?;
+        for (id, name, hours) in &recomputed
+        {
+            audit.record("total_from_history", json!({ "activity" : id, "name" : name, "hours" : hours }))?;
+        }
+    }
+
+    if args.since_last_run
+    {
+        db_new.set_meta("last_run", &started.format("%Y-%m-%d").to_string())?;
+    }
+
+    /*
+     * hashes of what's in the new db now: the activities migrated, and
+     * those skipped as unchanged; after failed inserts the last ones stay,
+     * what failed then still differs from them next time
+     */
+
+    if args.activity_checksums && failures.is_empty()
+    {
+        let kept : BTreeMap<&i32, &String> = sums.iter()
+            .filter(|(id, _)| migrated_ids.contains(id) || unchanged.contains(id))
+            .collect();
+        db_new.set_meta("activity_checksums", &serde_json::to_string(&kept).unwrap_or_default())?;
+    }
+    db_new.commit()?;
+    phases.mark("insert");
+
+    /*
+     * audit references of the new db
+     */
+
+    if args.check_foreign_keys
+    {
+        check_foreign_keys(args, &db_new)?;
+    }
+    if args.verify_checksums
+    {
+        verify_checksums(args, &db_new, &writepath, &migrated_ids, (&sum_act, &sum_his), ranges.as_ref())?;
+    }
+    phases.mark("verify");
+
+    let emitted = db_new.emitted();
+    let weekly  = match &args.export_weekly
     {
-        let dtlocal = chrono::Local
-            .with_ymd_and_hms(
-                e.year, 
-                e.month.try_into().unwrap(), 
-                e.day.try_into().unwrap(), 
-                0, 0, 0)
-            .unwrap();
+        Some(p) => Some((p, report::export_weekly(&db_new.conn, path::Path::new(p))?)),
+        None    => None,
+    };
+
+    db_new.close()?;
 
-        db_new.execute(
-            "INSERT INTO tt_history 
-            (id, year, month, day, isoweek, isoweekyear, hoursonday, date) 
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
-            rusqlite::params![
-                e.id_activity,
-                e.year,
-                e.month,
-                e.day,
-                e.weeknumber,
-                dtlocal.iso_week().year(),
-                round6(e.hours_on_day),
-                e.date,
-            ]).unwrap();
+    for (row, e) in &failures
+    {
+        audit.record("insert_failed", json!({ "row" : row, "error" : e }))?;
     }
+    audit.record("inserted", json!({
+        "new_db"       : dbpath,
+        "activities"   : stats.activities,
+        "history_rows" : stats.history_rows,
+    }))?;
 
+    match args.json
+    {
+        true  => stats.print_json(&warnings.messages()),
+        false => stats.print(),
+    }
+    println!();
+    if args.only_recompute && !args.json
+    {
+        println!("Totals from history: {} activities", recomputed.len());
+        for (id, name, hours) in &recomputed
+        {
+            println!("    {:>5}  {}  {} hours", id, name, hours);
+        }
+        println!();
+    }
+    if let Some(p) = &args.quarantine
+    {
+        quarantine.write(path::Path::new(p))?;
+        println!("Quarantine: {} rows written to {:?}", quarantine.len(), p);
+    }
+    if let Some(p) = &args.emit_sql
+    {
+        println!("SQL script: {} statements written to {:?}, nothing was written to {:?}",
+                 emitted, p, dbpath);
+    }
+    if let Some((p, n)) = weekly
+    {
+        println!("Weekly summary: {} rows written to {:?}", n, p);
+    }
+    if args.bench
+    {
+        phases.print(&args.format, stats.history_rows);
+    }
 
-    db_old.execute("PRAGMA foreign_keys=ON;", rusqlite::params![]).unwrap();
-    db_new.execute("PRAGMA foreign_keys=ON;", rusqlite::params![]).unwrap();
+    if !failures.is_empty()
+    {
+        eprintln!("Insert errors: {} rows could not be migrated", failures.len());
+        for (row, e) in &failures
+        {
+            eprintln!("    {}: {}", row, e);
+        }
+        return Err(match atomic
+        {
+            Some(_) => MigrationError::Partial(format!(
+                "{} rows failed to insert, --atomic-file leaves {:?} as it was",
+                failures.len(), dbpath)),
+            None    => MigrationError::Partial(format!(
+                "{} rows failed to insert, everything else is written", failures.len())),
+        });
+    }
+    if let Some(a) = atomic
+    {
+        a.commit()?;
+        println!("Atomic: renamed the written db over {:?}", dbpath);
+    }
 
+    if warnings.count() > 0
+    {
+        eprintln!("{} warnings, see above", warnings.count());
+    }
     println!("Done, if the program ran this far it worked");
+
+    if !args.quiet && !args.json && !args.bench && args.emit_sql.is_none()
+    {
+        next_steps(&dbpath, dbpath_exists, &path)?;
+    }
+
+    Ok(stats)
 }
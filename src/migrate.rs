@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use chrono::Local;
+use chrono::NaiveDate;
+use rusqlite::Connection;
+
+use crate::error::MigrationError;
+use crate::new::NewDb;
+use crate::old;
+use crate::old::OldActivity;
+use crate::old::OldHistory;
+use crate::old::OldTables;
+use crate::options::MigrationOptions;
+use crate::round;
+use crate::schema;
+use crate::transform;
+use crate::transform::AddedFill;
+use crate::transform::SourceDup;
+use crate::warn::Warnings;
+
+/*
+ * The migration as one call: read the old db, select and reshape its
+ * rows as MigrationOptions say, write them to the new db. The command
+ * line tool runs select and reshape the same way, in between reports on
+ * every step and does what only its flags ask for (streaming, renames,
+ * merging into an existing db, ...)
+ */
+
+/// Activities one step left out, and the number of history rows that
+/// went with them.
+pub type Dropped = (Vec<OldActivity>, usize);
+
+/// What [`select`] left out, per option (`None` where it isn't set).
+#[derive(Debug, Default)]
+pub struct Selected {
+    /// `skip_inactive`.
+    pub inactive : Option<Dropped>,
+    /// `include_name` and `exclude_name`.
+    pub by_name  : Option<Dropped>,
+    /// `activity`: the id of the one kept, and all the others.
+    pub activity : Option<(i32, Dropped)>,
+}
+
+/// What [`reshape`] did, per option (`None` where it isn't set or, for
+/// `limit`, didn't cut anything).
+#[derive(Debug, Default)]
+pub struct Reshaped {
+    /// `merge_source_dups`: every date that had more than one row.
+    pub source_dups      : Option<Vec<SourceDup>>,
+    /// `compact_history`: rows merged into another on the same day.
+    pub compacted        : Option<usize>,
+    /// `dedup_history`: rows left out, their activity has one on the date.
+    pub deduped          : Option<usize>,
+    /// `recompute_totals`: (id, name, old total, new total) of every
+    /// activity whose total changed.
+    pub totals           : Option<Vec<(i32, String, f64, f64)>>,
+    /// `limit_activities`.
+    pub limit_activities : Option<Dropped>,
+    /// `limit`: rows kept, of how many.
+    pub limit            : Option<(usize, usize)>,
+    /// `sample`.
+    pub sample           : Option<Sampled>,
+    /// `prune_empty_activities`.
+    pub pruned           : Option<Vec<OldActivity>>,
+}
+
+/// The pick of `sample`.
+#[derive(Debug)]
+pub struct Sampled {
+    /// History rows kept, of how many.
+    pub history_rows : usize,
+    pub of           : usize,
+    /// Activities left, and those left out as none of their rows got picked.
+    pub activities   : usize,
+    pub dropped      : Vec<OldActivity>,
+}
+
+/// The outcome of [`migrate`].
+#[derive(Debug)]
+pub struct Migrated {
+    /// Rows written to the new db.
+    pub activities    : usize,
+    pub history_rows  : usize,
+    pub selected      : Selected,
+    /// History rows with an invalid date, left out (`skip_invalid_dates`).
+    pub invalid_dates : usize,
+    /// History rows whose date text and fields disagree (rewritten with
+    /// `trust_dates`).
+    pub date_mismatches : usize,
+    pub reshaped      : Reshaped,
+    /// `added_fallback`: every activity without a usable added date.
+    pub added         : Vec<AddedFill>,
+    /// The warnings of the run, as printed to stderr.
+    pub warnings      : Vec<String>,
+}
+
+/// Leaves out the activities the options don't select, along with their
+/// history: inactive ones, by name, all but `activity`. Fails if
+/// `activity` doesn't match exactly one activity.
+pub fn select ( oldact : &mut Vec<OldActivity>,
+                oldhis : &mut Vec<OldHistory>,
+                opts   : &MigrationOptions )
+    -> Result<Selected, MigrationError>
+{
+    let mut selected = Selected::default();
+
+    if opts.skip_inactive
+    {
+        selected.inactive = Some(transform::drop_activities(oldact, oldhis, |e| e.is_activated == 0));
+    }
+    if !opts.include_name.is_empty() || !opts.exclude_name.is_empty()
+    {
+        selected.by_name = Some(transform::drop_activities(oldact, oldhis,
+            |e| !transform::name_selected(&opts.include_name, &opts.exclude_name, &e.name)));
+    }
+    if let Some(name) = &opts.activity
+    {
+        let candidates = transform::activity_candidates(oldact, name);
+
+        if candidates.len() != 1
+        {
+            let listed : Vec<String> = match candidates.is_empty()
+            {
+                true  => oldact.iter().map(|e| format!("\n    {:>5}  {}", e.id, e.name)).collect(),
+                false => candidates.iter().map(|e| format!("\n    {:>5}  {}", e.id, e.name)).collect(),
+            };
+            return Err(MigrationError::Aborted(format!(
+                "--activity {:?} matches {} activities, it has to match exactly one; candidates:{}",
+                name, candidates.len(), listed.concat())));
+        }
+
+        let keep = candidates[0].id;
+        selected.activity = Some((keep, transform::drop_activities(oldact, oldhis, |e| e.id != keep)));
+    }
+
+    Ok(selected)
+}
+
+/// Merges, compacts and deduplicates the history, sets the totals of the
+/// activities it changed for (or `altered` before) to its sum again,
+/// then takes the slice `limit`, `limit_activities` or `sample` ask for
+/// and prunes activities left without history.
+pub fn reshape ( oldact  : &mut Vec<OldActivity>,
+                 oldhis  : &mut Vec<OldHistory>,
+                 altered : HashSet<i32>,
+                 opts    : &MigrationOptions )
+    -> Reshaped
+{
+    let mut reshaped = Reshaped::default();
+    let mut altered  = altered;
+
+    if opts.merge_source_dups
+    {
+        let (merged, dups, touched) = transform::merge_source_dups(std::mem::take(oldhis));
+        *oldhis = merged;
+        altered.extend(touched);
+        reshaped.source_dups = Some(dups);
+    }
+    if opts.compact_history
+    {
+        let (compacted, n_merged, touched) = transform::compact_history(std::mem::take(oldhis));
+        *oldhis = compacted;
+        altered.extend(touched);
+        reshaped.compacted = Some(n_merged);
+    }
+    if opts.dedup_history
+    {
+        let (deduped, n_dropped, touched) = transform::dedup_history(std::mem::take(oldhis));
+        *oldhis = deduped;
+        altered.extend(touched);
+        reshaped.deduped = Some(n_dropped);
+    }
+
+    if opts.recompute_totals && !altered.is_empty()
+    {
+        reshaped.totals = Some(transform::reconcile_totals(oldact, oldhis, &altered));
+    }
+
+    if let Some(n) = opts.limit_activities
+    {
+        let keep : HashSet<i32> = oldact.iter().take(n).map(|e| e.id).collect();
+        reshaped.limit_activities = Some(transform::drop_activities(oldact, oldhis, |e| !keep.contains(&e.id)));
+    }
+    if let Some(n) = opts.limit.filter(|n| oldhis.len() > *n)
+    {
+        reshaped.limit = Some((n, oldhis.len()));
+        oldhis.truncate(n);
+    }
+    if let Some(n) = opts.sample
+    {
+        let of = oldhis.len();
+        transform::sample_history(oldhis, n, opts.sample_seed);
+
+        let used : HashSet<i32> = oldhis.iter().map(|e| e.id_activity).collect();
+        let (dropped, _) = transform::drop_activities(oldact, oldhis, |e| !used.contains(&e.id));
+        reshaped.sample = Some(Sampled { history_rows : oldhis.len(), of, activities : oldact.len(), dropped });
+    }
+
+    if opts.prune_empty_activities
+    {
+        let used : HashSet<i32> = oldhis.iter().map(|e| e.id_activity).collect();
+        let (dropped, _) = transform::drop_activities(oldact, oldhis, |e| !used.contains(&e.id));
+        reshaped.pruned = Some(dropped);
+    }
+
+    reshaped
+}
+
+/// Migrates the old db `old` into `new` as `opts` say, creating the new
+/// tables if they don't exist yet; all rows are written in one
+/// transaction. `new` is written as configured (see its fields).
+///
+/// ```
+/// use rusqlite::Connection;
+/// use timetracker_transitiontool::{migrate, MigrationOptions};
+/// use timetracker_transitiontool::new::NewDb;
+///
+/// let old = Connection::open_in_memory()?;
+/// old.execute_batch(
+///     "CREATE TABLE activities (id, group_id, name, added_when, is_activated, hours_total);
+///      CREATE TABLE history (id_activity, year, month, day, weeknumber, hours_on_day, date);
+///      INSERT INTO activities VALUES (1, 0, 'Reading', '2020-01-01', 1, 2.5),
+///                                    (2, 0, 'Chess', '2020-01-01', 0, 1.0);
+///      INSERT INTO history VALUES (1, 2024, 1, 1, 1, 1.25, '2024-01-01'),
+///                                 (1, 2024, 1, 1, 1, 1.25, '2024-01-01'),
+///                                 (2, 2024, 1, 2, 1, 1.0, '2024-01-02');")?;
+///
+/// let new  = NewDb::open(std::path::Path::new(":memory:"), None)?;
+/// let opts = MigrationOptions { skip_inactive : true, compact_history : true, ..Default::default() };
+/// let done = migrate(&old, &new, &opts)?;
+///
+/// assert_eq!((done.activities, done.history_rows), (1, 1));
+/// assert_eq!(done.reshaped.compacted, Some(1));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn migrate ( old : &Connection, new : &NewDb, opts : &MigrationOptions ) -> Result<Migrated, MigrationError>
+{
+    migrate_in(old, &OldTables::default(), new, opts)
+}
+
+/// Like [`migrate`], with the old tables named as in `tables`.
+pub fn migrate_in ( old    : &Connection,
+                    tables : &OldTables,
+                    new    : &NewDb,
+                    opts   : &MigrationOptions )
+    -> Result<Migrated, MigrationError>
+{
+    round::set_rounding(opts.precision, opts.round_mode);
+
+    let mut oldact = old::read_old_activities_in(old, tables)?;
+    let mut oldhis = old::read_old_history_in(old, tables)?;
+
+    let selected = select(&mut oldact, &mut oldhis, opts)?;
+
+    let n_his = oldhis.len();
+    oldhis.retain(|e| transform::date_problem(e, opts.years, &opts.trust_dates).is_none());
+    let invalid_dates = n_his - oldhis.len();
+    if invalid_dates > 0 && !opts.skip_invalid_dates
+    {
+        return Err(MigrationError::Schema(format!(
+            "{} history rows have an invalid date (skip_invalid_dates leaves them out)", invalid_dates)));
+    }
+
+    let warnings = Warnings::new(false);
+    let date_mismatches = transform::check_dates(&mut oldhis, &opts.trust_dates, &warnings)?;
+
+    let reshaped = reshape(&mut oldact, &mut oldhis, HashSet::new(), opts);
+
+    let mut first_days : HashMap<i32, NaiveDate> = HashMap::new();
+    for e in &oldhis
+    {
+        if let Some(d) = old::parse_datetime(&e.date)
+        {
+            let f = first_days.entry(e.id_activity).or_insert(d);
+            *f = (*f).min(d);
+        }
+    }
+    let added = transform::fill_added(&mut oldact, &first_days, &opts.added_fallback, Local::now().date_naive());
+
+    if !schema::table_names(&new.conn)?.iter().any(|t| t == "tt_activities")
+    {
+        new.create_tables()?;
+    }
+
+    /*
+     * the new db may pick other ids (remap_ids), the history follows them
+     */
+
+    new.begin()?;
+    let mut ids : HashMap<i32, i32> = HashMap::new();
+    for e in &oldact
+    {
+        ids.insert(e.id, new.insert_activity(e)?);
+    }
+    for e in &oldhis
+    {
+        match ids.get(&e.id_activity).filter(|id| **id != e.id_activity)
+        {
+            Some(id) => new.insert_history(&OldHistory { id_activity : *id, ..e.clone() })?,
+            None     => new.insert_history(e)?,
+        }
+    }
+    new.commit()?;
+
+    Ok(Migrated {
+        activities    : oldact.len(),
+        history_rows  : oldhis.len(),
+        selected,
+        invalid_dates,
+        date_mismatches,
+        reshaped,
+        added,
+        warnings      : warnings.messages(),
+    })
+}
@@ -0,0 +1,122 @@
+use std::fs;
+use std::path;
+use std::thread;
+use std::time;
+
+use crate::error::MigrationError;
+use crate::old;
+
+/*
+ * sqlite's locking is unreliable on network filesystems (nfs, smb, ...),
+ * the old db is best read from a local copy (--copy-local) and the new
+ * one written locally
+ */
+
+const NETWORK_FS : [&str; 12] = [
+    "nfs", "nfs4", "cifs", "smb", "smbfs", "smb3", "fuse.sshfs",
+    "9p", "afs", "ceph", "glusterfs", "davfs",
+];
+
+/*
+ * best effort: the filesystem type of the mount the path (or its nearest
+ * existing parent) lives on, if that's a network one; only linux tells
+ * the type in /proc/mounts, elsewhere nothing is detected
+ */
+
+pub fn network_fs ( p : &path::Path ) -> Option<String>
+{
+    let existing = p.ancestors().find(|a| a.exists())?;
+    let full     = fs::canonicalize(existing).ok()?;
+    let mounts   = fs::read_to_string("/proc/mounts").ok()?;
+
+    let mut best : Option<(usize, String)> = None;
+    for line in mounts.lines()
+    {
+        let mut fields = line.split(' ');
+        let (Some(_), Some(point), Some(fstype)) = (fields.next(), fields.next(), fields.next())
+            else { continue };
+        let point = point.replace("\\040", " ");
+
+        if full.starts_with(&point) && best.as_ref().is_none_or(|(len, _)| point.len() > *len)
+        {
+            best = Some((point.len(), fstype.to_string()));
+        }
+    }
+
+    best.map(|(_, t)| t).filter(|t| NETWORK_FS.contains(&t.as_str()))
+}
+
+/*
+ * --copy-local, the old db (and its -wal file, if any) copied to the temp
+ * folder; removed again when dropped, along with what sqlite left next to it
+ *
+ * a flaky mount can fail a read halfway or hand over a short file, a copy
+ * is tried COPY_TRIES times and only taken if it's as long as the original
+ */
+
+const COPY_TRIES : u32 = 3;
+
+fn copy_checked ( src : &path::Path, dst : &path::Path ) -> Result<(), MigrationError>
+{
+    let mut last = String::new();
+
+    for attempt in 1..=COPY_TRIES
+    {
+        if attempt > 1
+        {
+            thread::sleep(time::Duration::from_millis(500 * u64::from(attempt - 1)));
+        }
+        match fs::copy(src, dst).and_then(|n| Ok((n, fs::metadata(src)?.len())))
+        {
+            Ok((n, len)) if n == len => return Ok(()),
+            Ok((n, len))             => last = format!("copied {} of {} bytes", n, len),
+            Err(e)                   => last = e.to_string(),
+        }
+    }
+
+    let _ = fs::remove_file(dst);
+    Err(MigrationError::Io(format!("can't copy {:?} to {:?} ({} tries): {}",
+                                   src, dst, COPY_TRIES, last)))
+}
+
+pub struct LocalCopy {
+    pub path : path::PathBuf,
+}
+
+impl LocalCopy
+{
+    pub fn new ( src : &str ) -> Result<LocalCopy, MigrationError>
+    {
+        let dst = std::env::temp_dir()
+            .join(format!("tt_transition_old_{}.db", std::process::id()));
+        let src = if old::is_uri(src) { old::uri_file_path(src)? } else { src.to_string() };
+
+        copy_checked(path::Path::new(&src), &dst)?;
+
+        let copy = LocalCopy { path : dst };
+        let wal  = format!("{}-wal", src);
+        if path::Path::new(&wal).exists()
+        {
+            copy_checked(path::Path::new(&wal), &copy.sidecar("-wal"))?;
+        }
+
+        Ok(copy)
+    }
+
+    fn sidecar ( &self, suffix : &str ) -> path::PathBuf
+    {
+        let mut p = self.path.clone().into_os_string();
+        p.push(suffix);
+        p.into()
+    }
+}
+
+impl Drop for LocalCopy
+{
+    fn drop ( &mut self )
+    {
+        let _ = fs::remove_file(self.sidecar("-wal"));
+        let _ = fs::remove_file(self.sidecar("-shm"));
+        let _ = fs::remove_file(&self.path);
+    }
+}
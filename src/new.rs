@@ -0,0 +1,676 @@
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path;
+
+use chrono::Datelike;
+use chrono::TimeZone;
+use rusqlite::Connection;
+use rusqlite::OpenFlags;
+use rusqlite::OptionalExtension;
+use rusqlite::types::ToSqlOutput;
+use rusqlite::types::Value;
+use rusqlite::ToSql;
+
+use crate::error;
+use crate::error::MigrationError;
+use crate::round::round6;
+use crate::schema;
+use crate::old::OldActivity;
+use crate::old::OldHistory;
+
+/*
+ * Hardcoded stuff from the new db layout
+ * copied from version 0.1.0
+ */
+
+pub const DBNAME : &str = "productivity.db";
+
+pub const SQL_CREATE_ACT : &str =
+"CREATE TABLE tt_activities (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL, 
+    added TEXT NOT NULL, 
+    hourstotal NUMERIC NOT NULL DEFAULT 0.0
+    )";
+
+pub const SQL_CREATE_HIS : &str = 
+"CREATE TABLE tt_history (
+    id INTEGER NOT NULL, 
+    year INTEGER NOT NULL, 
+    month INTEGER NOT NULL, 
+    day INTEGER NOT NULL, 
+    isoweek INTEGER NOT NULL, 
+    isoweekyear INTEGER NOT NULL,
+    hoursonday NUMERIC NOT NULL DEFAULT 0.0, 
+    date TEXT NOT NULL,
+    FOREIGN KEY (id) REFERENCES tt_activities(id)
+    )";
+
+/*
+ * Bookkeeping of this tool inside the new db, not used by the app itself
+ *
+ * schema_version names the layout above; dbs written by a tool producing
+ * a different layout must not be mixed with this one's rows
+ */
+
+pub const SCHEMA_VERSION : &str = "0.1.0";
+
+/*
+ * version of this tool (and the commit it was built from, see build.rs),
+ * shown by --version and recorded as tool_version in every migrated db
+ */
+
+pub const TOOL_VERSION : &str = env!("CARGO_PKG_VERSION");
+pub const TOOL_COMMIT  : Option<&str> = option_env!("TT_GIT_COMMIT");
+
+pub fn tool_version () -> String
+{
+    match TOOL_COMMIT
+    {
+        Some(c) => format!("{} ({})", TOOL_VERSION, c),
+        None    => TOOL_VERSION.to_string(),
+    }
+}
+
+pub const SQL_CREATE_META : &str =
+"CREATE TABLE IF NOT EXISTS tt_migration_meta (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+    )";
+
+
+/*
+ * --out-key, the new db encrypted with sqlcipher (only in builds with the
+ * sqlcipher feature, args refuses the flag otherwise); sqlcipher only
+ * finds out about a wrong key, or a db that isn't encrypted, at the first
+ * read, so that happens right away
+ */
+
+pub fn open_keyed ( path : &path::Path, flags : OpenFlags, key : Option<&str> )
+    -> Result<Connection, MigrationError>
+{
+    let conn = Connection::open_with_flags(path, flags).map_err(error::io)?;
+
+    if let Some(key) = key
+    {
+        conn.pragma_update(None, "key", key).map_err(error::io)?;
+        conn.query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(()))
+            .map_err(|e| MigrationError::Io(format!(
+                "can't open {:?} with --out-key (wrong key, or not encrypted?): {}", path, e)))?;
+    }
+
+    Ok(conn)
+}
+
+/*
+ * The new db being written to, plus everything that changes how rows
+ * get written
+ */
+
+pub struct NewDb {
+    pub conn        : Connection,
+    pub migrated_at : Option<String>,	// --stamp-migrated, run start time
+    pub verbose_sql : bool,				// --verbose-sql
+    pub extra_columns : Vec<String>,	// --extra-column, values in OldActivity::extra
+    pub store_minutes : bool,			// --store-minutes
+    pub dedup_history : bool,			// --dedup-history, UNIQUE(id, date)
+    pub refresh_history : bool,			// --since-last-run, update rows' hours
+    pub remap_ids     : bool,			// --remap-ids, sqlite picks activity ids
+    pub ignored       : Cell<usize>,	// history rows the constraint refused
+    emit              : Option<RefCell<io::BufWriter<fs::File>>>,	// --emit-sql
+    emitted           : Cell<usize>,
+}
+
+/*
+ * bound parameters as they'd appear in sql, for --verbose-sql; blobs
+ * only by their size there, written out for --emit-sql
+ */
+
+fn sql_value ( p : &dyn ToSql, blobs : bool ) -> String
+{
+    let v = match p.to_sql()
+    {
+        Ok(ToSqlOutput::Borrowed(v)) => v.into(),
+        Ok(ToSqlOutput::Owned(v))    => v,
+        _                            => return "?".to_string(),
+    };
+
+    match v
+    {
+        Value::Null       => "NULL".to_string(),
+        Value::Integer(i) => i.to_string(),
+        Value::Real(f)    => f.to_string(),
+        Value::Text(t)    => format!("'{}'", t.replace('\'', "''")),
+        Value::Blob(b) if blobs =>
+            format!("X'{}'", b.iter().map(|x| format!("{:02X}", x)).collect::<String>()),
+        Value::Blob(b)    => format!("<{} bytes>", b.len()),
+    }
+}
+
+/*
+ * a statement on one line with its ?N parameters written in as literals,
+ * for --emit-sql; a ? within quotes (an identifier or literal) is left alone
+ */
+
+fn inline_params ( sql : &str, params : &[&dyn ToSql] ) -> String
+{
+    let sql = sql.split_whitespace().collect::<Vec<&str>>().join(" ");
+
+    let mut out   = String::new();
+    let mut quote : Option<char> = None;
+    let mut chars = sql.chars().peekable();
+
+    while let Some(c) = chars.next()
+    {
+        match (quote, c)
+        {
+            (Some(q), _) if c == q => quote = None,
+            (None, '\'' | '"')     => quote = Some(c),
+            (None, '?')            =>
+            {
+                let mut n = String::new();
+                while let Some(d) = chars.next_if(char::is_ascii_digit) { n.push(d); }
+
+                let p = n.parse::<usize>().ok()
+                    .and_then(|i| i.checked_sub(1))
+                    .and_then(|i| params.get(i));
+                match p
+                {
+                    Some(p) => out.push_str(&sql_value(*p, true)),
+                    None    => { out.push('?'); out.push_str(&n); }
+                }
+                continue;
+            }
+            _ => {}
+        }
+        out.push(c);
+    }
+
+    out
+}
+
+/*
+ * hours as whole minutes for --store-minutes, and whether that drops
+ * anything below a minute (of the hours as they get stored)
+ */
+
+pub fn minutes ( hours : f64 ) -> i64
+{
+    (round6(hours) * 60.).round() as i64
+}
+
+pub fn loses_minutes ( hours : f64 ) -> bool
+{
+    (round6(hours) * 60. - minutes(hours) as f64).abs() > 1e-6
+}
+
+impl NewDb
+{
+    pub fn open ( path : &path::Path, key : Option<&str> ) -> Result<NewDb, MigrationError>
+    {
+        let conn = open_keyed(path, OpenFlags::default(), key)?;
+        conn.execute("PRAGMA foreign_keys=OFF;", rusqlite::params![])
+            .map_err(error::io)?;
+
+        Ok(NewDb {
+            conn,
+            migrated_at   : None,
+            verbose_sql   : false,
+            extra_columns : Vec::new(),
+            store_minutes : false,
+            dedup_history : false,
+            refresh_history : false,
+            remap_ids     : false,
+            ignored       : Cell::new(0),
+            emit          : None,
+            emitted       : Cell::new(0),
+        })
+    }
+
+    /*
+     * --emit-sql: every statement is run against the new db as usual (or
+     * an empty one in memory if there's none yet), so everything reading
+     * it back still works, and written to the script; in a transaction
+     * that gets rolled back at close, leaving the new db as it was
+     */
+
+    pub fn emitting ( path : &path::Path, exists : bool, script : &path::Path, key : Option<&str> )
+        -> Result<NewDb, MigrationError>
+    {
+        let mut db = match exists
+        {
+            true  => NewDb::open(path, key)?,
+            false => NewDb::open(path::Path::new(":memory:"), None)?,
+        };
+
+        let file = fs::File::create(script).map_err(|e| MigrationError::Io(format!(
+            "can't create sql script {:?}: {}", script, e)))?;
+        let mut out = io::BufWriter::new(file);
+        writeln!(out, "-- migration of {:?}, written by timetracker_transitiontool {}",
+                 path, tool_version())?;
+        writeln!(out, "PRAGMA foreign_keys=OFF;")?;
+        db.emit = Some(RefCell::new(out));
+
+        Ok(db)
+    }
+
+    pub fn emitting_sql ( &self ) -> bool
+    {
+        self.emit.is_some()
+    }
+
+    /// Statements written to the --emit-sql script so far.
+    pub fn emitted ( &self ) -> usize
+    {
+        self.emitted.get()
+    }
+
+    fn emit_line ( &self, line : &str ) -> Result<(), MigrationError>
+    {
+        if let Some(out) = &self.emit
+        {
+            writeln!(out.borrow_mut(), "{}", line)?;
+        }
+        Ok(())
+    }
+
+    /*
+     * every statement writing to the new db goes through here
+     */
+
+    fn exec ( &self, sql : &str, params : &[&dyn ToSql] ) -> Result<(), MigrationError>
+    {
+        if self.verbose_sql
+        {
+            let sql : Vec<&str> = sql.split_whitespace().collect();
+            let values : Vec<String> = params.iter().map(|p| sql_value(*p, false)).collect();
+            eprintln!("debug: {} -- [{}]", sql.join(" "), values.join(", "));
+        }
+
+        self.conn.execute(sql, params).map_err(error::partial)?;
+
+        if self.emit.is_some()
+        {
+            self.emit_line(&format!("{};", inline_params(sql, params)))?;
+            self.emitted.set(self.emitted.get() + 1);
+        }
+
+        Ok(())
+    }
+
+    /*
+     * durability vs speed of the writes, one of OFF, NORMAL, FULL
+     */
+
+    pub fn set_synchronous ( &self, mode : &str ) -> Result<(), MigrationError>
+    {
+        self.exec(&format!("PRAGMA synchronous={}", mode), &[])
+    }
+
+    pub fn create_tables ( &self ) -> Result<(), MigrationError>
+    {
+        self.exec(SQL_CREATE_ACT, &[])?;
+        match self.dedup_history
+        {
+            true  => self.exec(&SQL_CREATE_HIS.replace(
+                         "FOREIGN KEY", "UNIQUE (id, date),\n    FOREIGN KEY"), &[])?,
+            false => self.exec(SQL_CREATE_HIS, &[])?,
+        }
+
+        Ok(())
+    }
+
+    /*
+     * the same for a db created without the constraint, as a unique index;
+     * fails if it already holds more than one row of an activity on a date
+     */
+
+    pub fn add_history_unique_index ( &self ) -> Result<(), MigrationError>
+    {
+        self.exec("CREATE UNIQUE INDEX IF NOT EXISTS tt_history_id_date \
+                   ON tt_history (id, date)", &[])
+            .map_err(|e| MigrationError::Schema(format!(
+                "can't make (id, date) unique in the existing tt_history, \
+                 it has duplicates already ({})", e)))
+    }
+
+    /*
+     * migrated_at isn't part of the app's layout, only added on request
+     * (to new and existing dbs alike)
+     */
+
+    pub fn add_stamp_columns ( &self ) -> Result<(), MigrationError>
+    {
+        for table in ["tt_activities", "tt_history"]
+        {
+            if !schema::column_names(&self.conn, table)?.iter().any(|c| c == "migrated_at")
+            {
+                println!("Adding column migrated_at to {}", table);
+                self.exec(&format!("ALTER TABLE {} ADD COLUMN migrated_at TEXT", table), &[])?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /*
+     * --store-minutes, integer minutes next to the float hours (which the
+     * app keeps using); added if missing like the stamp columns
+     */
+
+    pub fn add_minute_columns ( &self ) -> Result<(), MigrationError>
+    {
+        for (table, column) in [("tt_activities", "minutestotal"), ("tt_history", "minutesonday")]
+        {
+            if !schema::column_names(&self.conn, table)?.iter().any(|c| c == column)
+            {
+                println!("Adding column {} to {}", column, table);
+                self.exec(&format!("ALTER TABLE {} ADD COLUMN {} INTEGER", table, column), &[])?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /*
+     * columns carried over from a forked old layout (added if missing,
+     * with the type declared in the old table)
+     */
+
+    pub fn add_extra_columns ( &self, columns : &[(String, String)] ) -> Result<(), MigrationError>
+    {
+        let existing = schema::column_names(&self.conn, "tt_activities")?;
+
+        for (name, decltype) in columns
+        {
+            if !existing.contains(name)
+            {
+                println!("Adding column {} to tt_activities", name);
+                self.exec(&format!("ALTER TABLE tt_activities ADD COLUMN {} {}",
+                                   schema::quote_ident(name), decltype), &[])?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /*
+     * returns the id the activity got, its old one unless remapping
+     */
+
+    pub fn insert_activity ( &self, e : &OldActivity ) -> Result<i32, MigrationError>
+    {
+        let hours = round6(e.hours_total);
+        let mut cols   : Vec<String> = ["id", "name", "added", "hourstotal"]
+            .iter().map(|c| c.to_string()).collect();
+        let mut params : Vec<&dyn ToSql> = vec![&e.id, &e.name, &e.added_when, &hours];
+
+        if self.remap_ids
+        {
+            cols.remove(0);
+            params.remove(0);
+        }
+
+        if let Some(ts) = &self.migrated_at
+        {
+            cols.push("migrated_at".to_string());
+            params.push(ts);
+        }
+        let in_minutes = minutes(e.hours_total);
+        if self.store_minutes
+        {
+            cols.push("minutestotal".to_string());
+            params.push(&in_minutes);
+        }
+        for (c, v) in self.extra_columns.iter().zip(&e.extra)
+        {
+            cols.push(schema::quote_ident(c));
+            params.push(v);
+        }
+
+        let marks : Vec<String> = (1..=params.len()).map(|i| format!("?{}", i)).collect();
+        let sql = format!("INSERT INTO tt_activities 
+            ({}) 
+            VALUES ({})", cols.join(", "), marks.join(", "));
+
+        self.exec(&sql, &params)?;
+
+        match self.remap_ids
+        {
+            true  => i32::try_from(self.conn.last_insert_rowid()).map_err(|_| MigrationError::Partial(
+                         format!("new id of activity {} doesn't fit an i32", e.id))),
+            false => Ok(e.id),
+        }
+    }
+
+    pub fn insert_history ( &self, e : &OldHistory ) -> Result<(), MigrationError>
+    {
+        let dtlocal = chrono::Local
+            .with_ymd_and_hms(
+                e.year, 
+                e.month.try_into().unwrap_or(0), 
+                e.day.try_into().unwrap_or(0), 
+                0, 0, 0)
+            .earliest()
+            .ok_or_else(|| MigrationError::Partial(format!(
+                "invalid date {}-{}-{} in old history", e.year, e.month, e.day)))?;
+
+        let isoweekyear = dtlocal.iso_week().year();
+        let hours       = round6(e.hours_on_day);
+        let mut params : Vec<&dyn ToSql> = vec![
+            &e.id_activity,
+            &e.year,
+            &e.month,
+            &e.day,
+            &e.weeknumber,
+            &isoweekyear,
+            &hours,
+            &e.date,
+        ];
+
+        let mut cols = vec!["id", "year", "month", "day", "isoweek", "isoweekyear",
+                            "hoursonday", "date"];
+
+        if let Some(ts) = &self.migrated_at
+        {
+            cols.push("migrated_at");
+            params.push(ts);
+        }
+        let in_minutes = minutes(e.hours_on_day);
+        if self.store_minutes
+        {
+            cols.push("minutesonday");
+            params.push(&in_minutes);
+        }
+
+        /*
+         * a row already there (by UNIQUE(id, date)) either stays as it is,
+         * or, refreshing, gets the hours of this one; both count as ignored
+         * if nothing changed
+         */
+
+        let marks : Vec<String> = (1..=params.len()).map(|i| format!("?{}", i)).collect();
+        let sql = match (self.dedup_history, self.refresh_history)
+        {
+            (_, true) => format!("INSERT INTO tt_history ({}) VALUES ({})
+                ON CONFLICT (id, date) DO UPDATE SET {}
+                WHERE hoursonday != excluded.hoursonday",
+                cols.join(", "), marks.join(", "),
+                if self.store_minutes { "hoursonday = excluded.hoursonday, \
+                                         minutesonday = excluded.minutesonday" }
+                else { "hoursonday = excluded.hoursonday" }),
+            (true, _) => format!("INSERT OR IGNORE INTO tt_history ({}) VALUES ({})",
+                cols.join(", "), marks.join(", ")),
+            _         => format!("INSERT INTO tt_history ({}) VALUES ({})",
+                cols.join(", "), marks.join(", ")),
+        };
+
+        self.exec(&sql, &params)?;
+        if self.dedup_history && self.conn.changes() == 0
+        {
+            self.ignored.set(self.ignored.get() + 1);
+        }
+
+        Ok(())
+    }
+
+    /*
+     * (name, added, hourstotal) of an activity already in the new db
+     */
+
+    pub fn activity ( &self, id : i32 ) -> Result<Option<(String, String, f64)>, MigrationError>
+    {
+        self.conn
+            .query_row("SELECT name, added, hourstotal FROM tt_activities WHERE id = ?1", [id],
+                       |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .optional()
+            .map_err(error::partial)
+    }
+
+    pub fn activity_names ( &self ) -> Result<Vec<(i32, String)>, MigrationError>
+    {
+        let mut stmt = self.conn
+            .prepare("SELECT id, name FROM tt_activities ORDER BY id")
+            .map_err(error::partial)?;
+        let names = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(error::partial)?
+            .collect::<Result<_, _>>()
+            .map_err(error::partial)?;
+
+        Ok(names)
+    }
+
+    pub fn update_total ( &self, id : i32, hours : f64 ) -> Result<(), MigrationError>
+    {
+        let hours = round6(hours);
+        self.exec("UPDATE tt_activities SET hourstotal = ?1 WHERE id = ?2", &[&hours, &id])?;
+        if self.store_minutes
+        {
+            self.exec("UPDATE tt_activities SET minutestotal = ?1 WHERE id = ?2",
+                      &[&minutes(hours), &id])?;
+        }
+
+        Ok(())
+    }
+
+    /*
+     * --only-recompute, the totals of these activities set to the sum of
+     * their history as written (with what the new db had of them before);
+     * returns (id, name, hours) of each
+     */
+
+    pub fn recompute_totals ( &self, ids : &[i32] ) -> Result<Vec<(i32, String, f64)>, MigrationError>
+    {
+        let mut totals = Vec::new();
+        for &id in ids
+        {
+            let (name, hours) : (String, f64) = self.conn
+                .query_row("SELECT a.name, coalesce(sum(h.hoursonday), 0.0) \
+                            FROM tt_activities a LEFT JOIN tt_history h ON h.id = a.id \
+                            WHERE a.id = ?1 GROUP BY a.id", [id],
+                           |row| Ok((row.get(0)?, row.get(1)?)))
+                .map_err(error::partial)?;
+            self.update_total(id, hours)?;
+            totals.push((id, name, round6(hours)));
+        }
+
+        Ok(totals)
+    }
+
+    /*
+     * the statements recreating the db's schema as it is now (altered
+     * tables with their added columns), in the order they were created
+     */
+
+    pub fn schema_sql ( &self ) -> Result<Vec<String>, MigrationError>
+    {
+        let mut stmt = self.conn
+            .prepare("SELECT sql FROM sqlite_master WHERE sql IS NOT NULL ORDER BY rowid")
+            .map_err(error::io)?;
+        let sql = stmt.query_map([], |row| row.get(0)).map_err(error::io)?
+            .collect::<Result<_, _>>().map_err(error::io)?;
+        Ok(sql)
+    }
+
+    pub fn meta ( &self, key : &str ) -> Result<Option<String>, MigrationError>
+    {
+        if !schema::table_names(&self.conn)?.contains("tt_migration_meta")
+        {
+            return Ok(None);
+        }
+
+        self.conn
+            .query_row("SELECT value FROM tt_migration_meta WHERE key = ?1", [key],
+                       |row| row.get(0))
+            .optional()
+            .map_err(error::partial)
+    }
+
+    pub fn set_meta ( &self, key : &str, value : &str ) -> Result<(), MigrationError>
+    {
+        self.exec(SQL_CREATE_META, &[])?;
+        self.exec("INSERT OR REPLACE INTO tt_migration_meta (key, value) VALUES (?1, ?2)",
+                  &[&key, &value])
+    }
+
+    /*
+     * (table, rowid, referenced table) of every row whose foreign key
+     * points nowhere
+     */
+
+    pub fn foreign_key_violations ( &self ) -> Result<Vec<(String, i64, String)>, MigrationError>
+    {
+        let mut stmt = self.conn
+            .prepare("PRAGMA foreign_key_check")
+            .map_err(error::partial)?;
+
+        let iter = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .map_err(error::partial)?;
+
+        let mut violations = Vec::new();
+        for e in iter { violations.push(e.map_err(error::partial)?); }
+
+        Ok(violations)
+    }
+
+    /*
+     * --strict (and --emit-sql): one transaction for the whole run,
+     * committed once nothing can warn anymore; dropped uncommitted (on an
+     * error) it's rolled back
+     */
+
+    pub fn begin ( &self ) -> Result<(), MigrationError>
+    {
+        self.exec("BEGIN", &[])
+    }
+
+    pub fn commit ( &self ) -> Result<(), MigrationError>
+    {
+        match self.conn.is_autocommit() || self.emit.is_some()
+        {
+            true  => Ok(()),
+            false => self.exec("COMMIT", &[]),
+        }
+    }
+
+    pub fn close ( self ) -> Result<(), MigrationError>
+    {
+        if let Some(out) = &self.emit
+        {
+            self.emit_line("COMMIT;")?;
+            self.emit_line("PRAGMA foreign_keys=ON;")?;
+            out.borrow_mut().flush()?;
+            self.conn.execute("ROLLBACK", rusqlite::params![])
+                .map_err(error::partial)?;
+        }
+
+        self.conn.execute("PRAGMA foreign_keys=ON;", rusqlite::params![])
+            .map_err(error::partial)?;
+
+        Ok(())
+    }
+}
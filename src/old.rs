@@ -0,0 +1,1073 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use chrono::DateTime;
+use chrono::Datelike;
+use chrono::FixedOffset;
+use chrono::Local;
+use chrono::NaiveDate;
+use chrono::NaiveDateTime;
+use chrono::TimeZone;
+use chrono::Utc;
+use rusqlite::Connection;
+use rusqlite::types::FromSql;
+use rusqlite::types::Value;
+use rusqlite::types::ValueRef;
+use rusqlite::OpenFlags;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::error;
+use crate::error::MigrationError;
+use crate::schema;
+
+/*
+ * Rows of the old db layout (archived C++ version)
+ */
+
+/// One row of the old `activities` table.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OldActivity {
+    /// Primary key, referenced by [`OldHistory::id_activity`].
+    pub id			 : i32,
+    /// Group of the activity; the new layout has no groups.
+    pub group_id	 : i32,
+    /// Empty if the old row has NULL, see [`fill_empty_names`].
+    #[serde(deserialize_with = "null_as_empty")]
+    pub name		 : String,
+    /// When the activity was created, kept as the text the old app wrote.
+    pub added_when	 : String,
+    /// 0 for deactivated activities.
+    pub is_activated : i32,
+    /// Hours over all history, as stored by the old app.
+    pub hours_total  : f64,
+    /// Values of additional columns some forks added, only filled in by
+    /// [`read_extra_columns`].
+    #[serde(skip)]
+    pub extra        : Vec<Value>,
+}
+
+fn null_as_empty<'de, D> ( d : D ) -> Result<String, D::Error>
+    where D : serde::Deserializer<'de>
+{
+    Ok(Option::<String>::deserialize(d)?.unwrap_or_default())
+}
+
+/// One row of the old `history` table, the hours of an activity on a day.
+///
+/// The day is stored twice, as numeric `year`/`month`/`day` and as `date`
+/// text (`YYYY-MM-DD`); both normally agree.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OldHistory {
+    /// The [`OldActivity::id`] these hours belong to.
+    pub id_activity  : i32,
+    pub year		 : i32,
+    pub month		 : i32,
+    pub day			 : i32,
+    /// Week of the year as the old app computed it.
+    pub weeknumber   : i32,
+    pub hours_on_day : f64,
+    pub date	     : String,
+}
+
+/// Names of the old tables and of history columns, some forks of the old
+/// app renamed them.
+#[derive(Debug, Clone)]
+pub struct OldTables {
+    pub activities   : String,
+    pub history      : String,
+    /// Columns of `history` holding the hours and the date text.
+    pub hours_column : String,
+    pub date_column  : String,
+    /// How history stores the day, detected when `None`; see
+    /// [`schema::OLD_VERSIONS`].
+    pub layout       : Option<schema::HistoryLayout>,
+    /// Zone to take the days of datetime histories in, instead of the
+    /// day as written; see [`parse_datetime_in`].
+    pub source_timezone : Option<SourceZone>,
+    /// Zone the datetimes without an offset were written in, UTC if None;
+    /// see [`parse_datetime_between`].
+    pub datetime_timezone : Option<SourceZone>,
+    /// Db file holding `history`, if it's not the one with `activities`;
+    /// [`open`] attaches it to the old db's connection.
+    pub history_db : Option<String>,
+    /// Don't read `hours_total` at all, every activity comes with 0 hours;
+    /// for a total column known to hold garbage.
+    pub ignore_hours_total : bool,
+}
+
+impl Default for OldTables
+{
+    fn default() -> Self
+    {
+        OldTables {
+            activities   : "activities".to_string(),
+            history      : "history".to_string(),
+            hours_column : "hours_on_day".to_string(),
+            date_column  : "date".to_string(),
+            layout       : None,
+            source_timezone : None,
+            datetime_timezone : None,
+            history_db : None,
+            ignore_hours_total : false,
+        }
+    }
+}
+
+impl OldTables
+{
+    /// Whether history columns got renamed, i.e. history can't simply be
+    /// read by column position.
+    pub fn renamed_columns ( &self ) -> bool
+    {
+        let default = OldTables::default();
+
+        self.hours_column != default.hours_column || self.date_column != default.date_column
+    }
+}
+
+/*
+ * Open for reading
+ */
+
+/*
+ * the old db may also be given as sqlite uri, to pass open parameters:
+ *
+ *   file:/abs/path.db?immutable=1
+ *   file:///abs/path.db?immutable=1&nolock=1
+ *   file://localhost/abs/path.db?vfs=unix-none
+ *   file:relative/path.db?mode=ro
+ *
+ * accepted parameters are vfs, mode (ro only, the old db is never written),
+ * cache, psow, nolock and immutable
+ */
+
+const URI_PARAMS : [&str; 6] = ["vfs", "mode", "cache", "psow", "nolock", "immutable"];
+
+/// Whether `path` is meant as SQLite `file:` URI rather than a plain path.
+pub fn is_uri ( path : &str ) -> bool
+{
+    path.starts_with("file:")
+}
+
+/// Checks a `file:` URI and returns the path of the db file it points to.
+pub fn uri_file_path ( uri : &str ) -> Result<String, MigrationError>
+{
+    let bad = |why : &str| MigrationError::Io(format!("invalid db uri {:?}: {}", uri, why));
+
+    let rest = uri.strip_prefix("file:").ok_or_else(|| bad("doesn't start with file:"))?;
+    let (path, query) = match rest.split_once('?')
+    {
+        Some((p, q)) => (p, q),
+        None         => (rest, ""),
+    };
+    let path = match path.strip_prefix("//")
+    {
+        Some(p) if p.starts_with('/')          => p,
+        Some(p) if p.starts_with("localhost/") => &p["localhost".len()..],
+        Some(_) => return Err(bad("only local files (no host, or localhost) are supported")),
+        None    => path,
+    };
+
+    if path.is_empty()
+    {
+        return Err(bad("no file path"));
+    }
+
+    for param in query.split('&').filter(|p| !p.is_empty())
+    {
+        let (key, val) = param.split_once('=').unwrap_or((param, ""));
+
+        if !URI_PARAMS.contains(&key)
+        {
+            return Err(bad(&format!("unsupported parameter '{}'", key)));
+        }
+        if key == "mode" && val != "ro"
+        {
+            return Err(bad("old db can only be opened with mode=ro"));
+        }
+    }
+
+    percent_decode(path).ok_or_else(|| bad("broken %-escape in path"))
+}
+
+fn percent_decode ( s : &str ) -> Option<String>
+{
+    let bytes = s.as_bytes();
+    let mut out : Vec<u8> = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len()
+    {
+        if bytes[i] == b'%'
+        {
+            let hex = s.get(i + 1..i + 3)?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        }
+        else
+        {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(out).ok()
+}
+
+/// Opens an old db read-only and checks it actually has the old layout.
+/// `path` may be a plain path or a `file:` URI, see [`uri_file_path`].
+pub fn open ( path : &str, tables : &OldTables ) -> Result<Connection, MigrationError>
+{
+    let mut flags = OpenFlags::SQLITE_OPEN_READ_ONLY;
+
+    if is_uri(path) || tables.history_db.is_some()
+    {
+        if is_uri(path)
+        {
+            uri_file_path(path)?;
+        }
+        flags |= OpenFlags::SQLITE_OPEN_URI;
+    }
+
+    let db_old = Connection::open_with_flags(path, flags).map_err(error::io)?;
+
+    db_old.execute("PRAGMA foreign_keys=OFF;", rusqlite::params![])
+        .map_err(error::io)?;
+
+    if let Some(history_db) = &tables.history_db
+    {
+        attach_history(&db_old, path, history_db, tables)?;
+    }
+
+    schema::check_old_layout(&db_old, tables)?;
+
+    Ok(db_old)
+}
+
+/*
+ * history read from a db of its own, attached read-only as old_history;
+ * unqualified names resolve to main first, so the activities db mustn't
+ * have a history table of its own
+ */
+
+fn attach_history ( db_old : &Connection, path : &str, history_db : &str, tables : &OldTables )
+    -> Result<(), MigrationError>
+{
+    if schema::table_names(db_old)?.contains(&tables.history)
+    {
+        return Err(MigrationError::Schema(format!(
+            "{:?} has a table '{}' too, which would be read instead of the one in {:?}",
+            path, tables.history, history_db)));
+    }
+
+    let uri = match is_uri(history_db)
+    {
+        true  => { uri_file_path(history_db)?; history_db.to_string() }
+        false => format!("file:{}?mode=ro",
+                         history_db.replace('%', "%25").replace('?', "%3f").replace('#', "%23")),
+    };
+    db_old.execute("ATTACH DATABASE ?1 AS old_history", [&uri])
+        .map_err(|e| MigrationError::Io(format!("can't open {:?}: {}", history_db, e)))?;
+
+    let found : bool = db_old.query_row(
+        "SELECT count(*) > 0 FROM old_history.sqlite_master WHERE type = 'table' AND name = ?1",
+        [&tables.history], |row| row.get(0))
+        .map_err(|e| MigrationError::Io(format!("can't read {:?}: {}", history_db, e)))?;
+    if !found
+    {
+        return Err(MigrationError::Schema(format!(
+            "{:?} has no table '{}'", history_db, tables.history)));
+    }
+
+    Ok(())
+}
+
+/// Loads a `.sql` text dump of an old db (its CREATE TABLE and INSERT
+/// statements, as written by `sqlite3 old.db .dump`) into an in-memory db,
+/// which reads like the old db itself.
+///
+/// ```
+/// use timetracker_transitiontool::old::open_sql_dump;
+/// use timetracker_transitiontool::{read_old_activities, read_old_history};
+///
+/// let dump = "BEGIN TRANSACTION;
+/// CREATE TABLE activities (id INTEGER PRIMARY KEY, group_id INTEGER, name TEXT,
+///     added_when TEXT, is_activated INTEGER, hours_total REAL);
+/// INSERT INTO activities VALUES(1,1,'Reading','2020-01-01',1,1.5);
+/// CREATE TABLE history (id_activity INTEGER, year INTEGER, month INTEGER, day INTEGER,
+///     weeknumber INTEGER, hours_on_day REAL, date TEXT);
+/// INSERT INTO history VALUES(1,2020,1,2,1,1.5,'2020-01-02');
+/// COMMIT;";
+/// let path = std::env::temp_dir().join("tt_doctest_dump.sql");
+/// std::fs::write(&path, dump)?;
+///
+/// let db = open_sql_dump(&path.to_string_lossy(), &Default::default())?;
+/// assert_eq!(read_old_activities(&db)?[0].name, "Reading");
+/// assert_eq!(read_old_history(&db)?.len(), 1);
+/// # std::fs::remove_file(&path)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn open_sql_dump ( path : &str, tables : &OldTables ) -> Result<Connection, MigrationError>
+{
+    let sql = std::fs::read_to_string(path)
+        .map_err(|e| MigrationError::Io(format!("{:?}: {}", path, e)))?;
+
+    let db_old = Connection::open_in_memory().map_err(error::io)?;
+    db_old.execute_batch(&sql)
+        .map_err(|e| MigrationError::Schema(format!("{:?} isn't a usable sql dump: {}", path, e)))?;
+
+    db_old.execute("PRAGMA foreign_keys=OFF;", rusqlite::params![])
+        .map_err(error::io)?;
+
+    schema::check_old_layout(&db_old, tables)?;
+
+    Ok(db_old)
+}
+
+/*
+ * sqlite columns take values of any type: numbers stored as text, whole
+ * numbers stored as reals and numbers in text columns are read as what
+ * they should have been; see type_mismatches for finding them up front
+ */
+
+trait Tolerant : FromSql
+{
+    fn coerce ( v : ValueRef ) -> Option<Self>;
+}
+
+impl Tolerant for i32
+{
+    fn coerce ( v : ValueRef ) -> Option<i32>
+    {
+        match v
+        {
+            ValueRef::Real(f) if f.fract() == 0.0 => i32::try_from(f as i64).ok(),
+            ValueRef::Text(t) => std::str::from_utf8(t).ok()?.trim().parse().ok(),
+            _                 => None,
+        }
+    }
+}
+
+impl Tolerant for f64
+{
+    fn coerce ( v : ValueRef ) -> Option<f64>
+    {
+        match v
+        {
+            ValueRef::Text(t) => std::str::from_utf8(t).ok()?.trim().parse().ok(),
+            _                 => None,
+        }
+    }
+}
+
+impl Tolerant for String
+{
+    fn coerce ( v : ValueRef ) -> Option<String>
+    {
+        match v
+        {
+            ValueRef::Integer(i) => Some(i.to_string()),
+            ValueRef::Real(f)    => Some(f.to_string()),
+            _                    => None,
+        }
+    }
+}
+
+impl Tolerant for Option<String>
+{
+    fn coerce ( v : ValueRef ) -> Option<Option<String>>
+    {
+        String::coerce(v).map(Some)
+    }
+}
+
+/*
+ * the value as T, or what coerce makes of it; otherwise the error of the
+ * plain get, naming the column
+ */
+
+fn get<T : Tolerant> ( row : &rusqlite::Row, i : usize ) -> rusqlite::Result<T>
+{
+    row.get(i).or_else(|e| row.get_ref(i).ok().and_then(T::coerce).ok_or(e))
+}
+
+/*
+ * iterate over old db data; activities
+ */
+
+/// Reads all rows of the old `activities` table.
+///
+/// ```
+/// use rusqlite::Connection;
+/// use timetracker_transitiontool::read_old_activities;
+///
+/// let db = Connection::open_in_memory()?;
+/// db.execute_batch(
+///     "CREATE TABLE activities (id, group_id, name, added_when, is_activated, hours_total);
+///      INSERT INTO activities VALUES (1, 1, 'Reading', '2020-01-01', 1, 3.5);")?;
+///
+/// let activities = read_old_activities(&db)?;
+/// assert_eq!(activities[0].name, "Reading");
+/// assert_eq!(activities[0].hours_total, 3.5);
+///
+/// // numbers stored as text are read as numbers
+/// db.execute_batch("INSERT INTO activities VALUES ('2', 1, 'Coding', '2020-01-01', 1, ' 1.5');")?;
+/// assert_eq!(read_old_activities(&db)?[1].hours_total, 1.5);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn read_old_activities ( db_old : &Connection )
+    -> Result<Vec<OldActivity>, MigrationError>
+{
+    read_old_activities_in(db_old, &OldTables::default())
+}
+
+/// Like [`read_old_activities`], for an old db with renamed tables.
+pub fn read_old_activities_in ( db_old : &Connection, tables : &OldTables )
+    -> Result<Vec<OldActivity>, MigrationError>
+{
+    schema::check_ident(&tables.activities)?;
+
+    let columns = match tables.ignore_hours_total
+    {
+        true  => schema::column_names(db_old, &tables.activities)?.iter().take(5)
+                     .map(|c| schema::quote_ident(c))
+                     .chain(["0.0".to_string()])
+                     .collect::<Vec<_>>().join(", "),
+        false => "*".to_string(),
+    };
+    let mut stmt = db_old
+        .prepare(&format!("SELECT {} FROM {}", columns, schema::quote_ident(&tables.activities)))
+        .map_err(error::schema)?;
+
+    let iter = stmt.query_map([], |row| {
+        Ok(OldActivity {
+            id			: get(row, 0)?,
+            group_id	: get(row, 1)?,
+            name		: get::<Option<String>>(row, 2)?.unwrap_or_default(),
+            added_when	: get::<Option<String>>(row, 3)?.unwrap_or_default(),
+            is_activated: get(row, 4)?,
+            hours_total : get(row, 5)?,
+            extra       : Vec::new(),
+        })
+    }).map_err(error::schema)?;
+
+    let mut oldact : Vec<OldActivity> = Vec::new();
+    for e in iter { oldact.push(e.map_err(error::schema)?); }
+
+    Ok(oldact)
+}
+
+/// A column of the old db holding values of another type than the one it
+/// is read as, see [`type_mismatches`].
+#[derive(Debug)]
+pub struct TypeMismatch {
+    pub table    : String,
+    pub column   : String,
+    /// `integer`, `real` or `text`.
+    pub expected : &'static str,
+    /// The other types found, comma separated.
+    pub found    : String,
+    /// Rows holding one of them.
+    pub count    : usize,
+    /// One of those values, quoted as SQL.
+    pub sample   : String,
+}
+
+/// The columns of the old tables whose values (NULLs aside) aren't all of
+/// the type they are read as; reading converts them where it can, numbers
+/// stored as text for instance.
+///
+/// ```
+/// use rusqlite::Connection;
+/// use timetracker_transitiontool::old::{type_mismatches, OldTables};
+///
+/// let db = Connection::open_in_memory()?;
+/// db.execute_batch(
+///     "CREATE TABLE activities (id, group_id, name, added_when, is_activated, hours_total);
+///      INSERT INTO activities VALUES (1, 1, 'Reading', '2020-01-01', 1, '3.5');")?;
+///
+/// let found = type_mismatches(&db, &OldTables::default())?;
+/// assert_eq!((found[0].column.as_str(), found[0].found.as_str()), ("hours_total", "text"));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn type_mismatches ( db_old : &Connection, tables : &OldTables )
+    -> Result<Vec<TypeMismatch>, MigrationError>
+{
+    const ACTIVITIES : [&str; 6] = ["integer", "integer", "text", "text", "integer", "real"];
+    const HISTORY    : [&str; 7] = ["integer", "integer", "integer", "integer", "integer", "real", "text"];
+
+    schema::check_ident(&tables.activities)?;
+
+    let mut columns : Vec<(&str, String, &str)> = schema::column_names(db_old, &tables.activities)?
+        .into_iter().zip(ACTIVITIES)
+        .take(if tables.ignore_hours_total { 5 } else { 6 })
+        .map(|(c, t)| (tables.activities.as_str(), c, t))
+        .collect();
+
+    if schema::table_names(db_old)?.contains(&tables.history)
+    {
+        let names = match schema::old_history_layout(db_old, tables)?
+        {
+            schema::HistoryLayout::Combined                       =>
+                vec!["id_activity".to_string(), "datetime".to_string(), tables.hours_column.clone()],
+            schema::HistoryLayout::Split if tables.renamed_columns() =>
+                ["id_activity", "year", "month", "day", "weeknumber"].iter().map(|c| c.to_string())
+                    .chain([tables.hours_column.clone(), tables.date_column.clone()])
+                    .collect(),
+            schema::HistoryLayout::Split                          =>
+                schema::column_names(db_old, &tables.history)?,
+        };
+        let types : &[&str] = match names.len()
+        {
+            3 => &["integer", "text", "real"],
+            _ => &HISTORY,
+        };
+        columns.extend(names.into_iter().zip(types).map(|(c, t)| (tables.history.as_str(), c, *t)));
+    }
+
+    let mut found = Vec::new();
+    for (table, column, expected) in columns
+    {
+        let allowed = match expected
+        {
+            "integer" => "'integer'",
+            "real"    => "'integer', 'real'",
+            _         => "'text'",
+        };
+        let c = schema::quote_ident(&column);
+        let (count, types, sample) : (usize, Option<String>, Option<String>) = db_old
+            .query_row(&format!("SELECT count(*), group_concat(DISTINCT typeof({c})), min(quote({c})) \
+                                 FROM {} WHERE typeof({c}) NOT IN ('null', {})",
+                                schema::quote_ident(table), allowed, c = c),
+                       [], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .map_err(error::schema)?;
+
+        if count > 0
+        {
+            found.push(TypeMismatch {
+                table    : table.to_string(),
+                column,
+                expected,
+                found    : types.unwrap_or_default(),
+                count,
+                sample   : sample.unwrap_or_default(),
+            });
+        }
+    }
+
+    Ok(found)
+}
+
+/// Reads the activity names as raw bytes by activity id, so names that
+/// aren't valid UTF-8 can still be looked at, see [`name_problems`].
+pub fn read_activity_names ( db_old : &Connection, tables : &OldTables )
+    -> Result<Vec<(i32, Vec<u8>)>, MigrationError>
+{
+    schema::check_ident(&tables.activities)?;
+
+    let mut stmt = db_old
+        .prepare(&format!("SELECT id, CAST(name AS BLOB) FROM {}",
+                          schema::quote_ident(&tables.activities)))
+        .map_err(error::schema)?;
+
+    let iter = stmt.query_map([], |row| {
+        Ok((row.get::<_, i32>(0)?, row.get::<_, Option<Vec<u8>>>(1)?.unwrap_or_default()))
+    }).map_err(error::schema)?;
+
+    let mut names = Vec::new();
+    for e in iter { names.push(e.map_err(error::schema)?); }
+
+    Ok(names)
+}
+
+/// The placeholder name of an activity whose old name is NULL or blank.
+pub fn unnamed ( id : i32 ) -> String
+{
+    format!("(unnamed #{})", id)
+}
+
+/// Gives every activity with a NULL (read as empty) or blank name the name
+/// [`unnamed`], as the new `tt_activities.name` can't be NULL and an empty
+/// name is useless in the app; returns the ids of the renamed activities.
+///
+/// ```
+/// use rusqlite::Connection;
+/// use timetracker_transitiontool::old::fill_empty_names;
+/// use timetracker_transitiontool::read_old_activities;
+///
+/// let db = Connection::open_in_memory()?;
+/// db.execute("CREATE TABLE activities (id, group_id, name, added_when, is_activated, hours_total)", [])?;
+/// db.execute("INSERT INTO activities VALUES (1, 1, NULL, '2020-01-01', 1, 0),
+///                                           (2, 1, '', '2020-01-01', 1, 0),
+///                                           (3, 1, '  ', '2020-01-01', 1, 0),
+///                                           (4, 1, 'Coding', '2020-01-01', 1, 0)", [])?;
+///
+/// let mut oldact = read_old_activities(&db)?;
+/// assert_eq!(oldact[0].name, "");
+///
+/// assert_eq!(fill_empty_names(&mut oldact), [1, 2, 3]);
+/// let names : Vec<&str> = oldact.iter().map(|e| e.name.as_str()).collect();
+/// assert_eq!(names, ["(unnamed #1)", "(unnamed #2)", "(unnamed #3)", "Coding"]);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn fill_empty_names ( oldact : &mut [OldActivity] ) -> Vec<i32>
+{
+    let mut filled = Vec::new();
+
+    for e in oldact.iter_mut().filter(|e| e.name.trim().is_empty())
+    {
+        e.name = unnamed(e.id);
+        filled.push(e.id);
+    }
+
+    filled
+}
+
+/// What about an activity name could break the new app: invalid UTF-8,
+/// control characters, or replacement characters left by an earlier
+/// broken conversion. Any other Unicode (emoji, combining marks, non-Latin
+/// scripts) is fine and comes through byte for byte.
+///
+/// ```
+/// use rusqlite::Connection;
+/// use timetracker_transitiontool::old::{name_problems, read_activity_names};
+/// use timetracker_transitiontool::read_old_activities;
+///
+/// let names = ["Lesen 📚", "Cafe\u{301}", "日本語", "Tab\there", "bad \u{fffd}"];
+///
+/// let db = Connection::open_in_memory()?;
+/// db.execute("CREATE TABLE activities (id, group_id, name, added_when, is_activated, hours_total)", [])?;
+/// for (i, n) in names.iter().enumerate() {
+///     db.execute("INSERT INTO activities VALUES (?1, 1, ?2, '2020-01-01', 1, 0)",
+///                rusqlite::params![i as i32, n])?;
+/// }
+///
+/// // multibyte and combining characters round-trip unchanged
+/// let read = read_old_activities(&db)?;
+/// for (e, n) in read.iter().zip(names) {
+///     assert_eq!(e.name.as_bytes(), n.as_bytes());
+/// }
+///
+/// let raw = read_activity_names(&db, &Default::default())?;
+/// assert!(name_problems(&raw[0].1).is_empty());
+/// assert!(name_problems(&raw[1].1).is_empty());
+/// assert!(name_problems(&raw[2].1).is_empty());
+/// assert_eq!(name_problems(&raw[3].1), ["control character U+0009 at byte 3"]);
+/// assert_eq!(name_problems(&raw[4].1), ["replacement character U+FFFD at byte 4"]);
+/// assert_eq!(name_problems(b"ab\xff"), ["invalid UTF-8 at byte 2"]);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn name_problems ( name : &[u8] ) -> Vec<String>
+{
+    let mut problems = Vec::new();
+
+    let text = match std::str::from_utf8(name)
+    {
+        Ok(t)  => t,
+        Err(e) =>
+        {
+            problems.push(format!("invalid UTF-8 at byte {}", e.valid_up_to()));
+            std::str::from_utf8(&name[..e.valid_up_to()]).unwrap_or_default()
+        }
+    };
+
+    for (i, c) in text.char_indices()
+    {
+        if c.is_control()
+        {
+            problems.push(format!("control character U+{:04X} at byte {}", c as u32, i));
+        }
+        else if c == '\u{fffd}'
+        {
+            problems.push(format!("replacement character U+FFFD at byte {}", i));
+        }
+    }
+
+    problems
+}
+
+/// Reads additional columns of the old activities table by activity id,
+/// values in the order of `columns`; errors if one of them doesn't exist.
+pub fn read_extra_columns ( db_old  : &Connection,
+                            tables  : &OldTables,
+                            columns : &[String] )
+    -> Result<HashMap<i32, Vec<Value>>, MigrationError>
+{
+    for c in columns { schema::check_ident(c)?; }
+
+    let existing = schema::column_names(db_old, &tables.activities)?;
+    for c in columns
+    {
+        if !existing.contains(c)
+        {
+            return Err(MigrationError::Schema(format!(
+                "old table {} has no column '{}'", tables.activities, c)));
+        }
+    }
+
+    let select : Vec<String> = columns.iter().map(|c| schema::quote_ident(c)).collect();
+    let mut stmt = db_old
+        .prepare(&format!("SELECT id, {} FROM {}",
+                          select.join(", "), schema::quote_ident(&tables.activities)))
+        .map_err(error::schema)?;
+
+    let iter = stmt.query_map([], |row| {
+        let mut values = Vec::new();
+        for i in 0..columns.len() { values.push(row.get::<_, Value>(i + 1)?); }
+        Ok((row.get::<_, i32>(0)?, values))
+    }).map_err(error::schema)?;
+
+    let mut extra : HashMap<i32, Vec<Value>> = HashMap::new();
+    for e in iter
+    {
+        let (id, values) = e.map_err(error::schema)?;
+        extra.insert(id, values);
+    }
+
+    Ok(extra)
+}
+
+/*
+ * iterate over old db data; history
+ * row by row, so huge histories don't have to be held in memory;
+ * optionally also read in pages (LIMIT/OFFSET in rowid order), so sqlite
+ * doesn't keep one huge result set open either
+ */
+
+fn for_each_row<T> ( db_old    : &Connection,
+                     sql       : &str,
+                     page_size : Option<usize>,
+                     map       : impl Fn(&rusqlite::Row) -> rusqlite::Result<T>,
+                     mut each  : impl FnMut(T) -> Result<bool, MigrationError> )
+    -> Result<(), MigrationError>
+{
+    let Some(n) = page_size else
+    {
+        let mut stmt = db_old.prepare(sql).map_err(error::schema)?;
+        let iter = stmt.query_map([], map).map_err(error::schema)?;
+
+        for e in iter
+        {
+            if !each(e.map_err(error::schema)?)? { break; }
+        }
+        return Ok(());
+    };
+
+    let mut stmt = db_old
+        .prepare(&format!("{} ORDER BY rowid LIMIT ?1 OFFSET ?2", sql))
+        .map_err(error::schema)?;
+    let mut offset = 0;
+
+    loop
+    {
+        let mut n_page = 0;
+        let iter = stmt.query_map([n as i64, offset as i64], &map).map_err(error::schema)?;
+
+        for e in iter
+        {
+            n_page += 1;
+            if !each(e.map_err(error::schema)?)? { return Ok(()); }
+        }
+        if n_page < n
+        {
+            return Ok(());
+        }
+        offset += n;
+    }
+}
+
+/// Calls `f` for every row of the old `history` table, without holding
+/// them all in memory; `f` returns whether to keep going.
+pub fn for_each_history ( db_old : &Connection,
+                          tables : &OldTables,
+                          f      : impl FnMut(OldHistory) -> Result<bool, MigrationError> )
+    -> Result<(), MigrationError>
+{
+    for_each_history_paged(db_old, tables, None, f)
+}
+
+/// Like [`for_each_history`], reading `page_size` rows per query if given.
+pub fn for_each_history_paged ( db_old    : &Connection,
+                                tables    : &OldTables,
+                                page_size : Option<usize>,
+                                f         : impl FnMut(OldHistory) -> Result<bool, MigrationError> )
+    -> Result<(), MigrationError>
+{
+    /*
+    let mut stmt = db_old
+        .prepare(&format!("SELECT * FROM history WHERE date >= '2024-01-22'"))
+        .unwrap();
+     */
+
+    schema::check_ident(&tables.history)?;
+
+    if schema::old_history_layout(db_old, tables)? == schema::HistoryLayout::Combined
+    {
+        return for_each_combined_history(db_old, tables, page_size, f);
+    }
+
+    schema::check_ident(&tables.hours_column)?;
+    schema::check_ident(&tables.date_column)?;
+
+    let sql = if tables.renamed_columns()
+    {
+        format!("SELECT id_activity, year, month, day, weeknumber, {}, {} FROM {}",
+                schema::quote_ident(&tables.hours_column),
+                schema::quote_ident(&tables.date_column),
+                schema::quote_ident(&tables.history))
+    }
+    else
+    {
+        format!("SELECT * FROM {}", schema::quote_ident(&tables.history))
+    };
+
+    for_each_row(db_old, &sql, page_size, |row| {
+        Ok(OldHistory {
+            id_activity : get(row, 0)?, 
+            year		: get(row, 1)?, 
+            month		: get(row, 2)?, 
+            day			: get(row, 3)?, 
+            weeknumber	: get(row, 4)?, 
+            hours_on_day: get(row, 5)?, 
+            date		: get(row, 6)?, 
+        })
+    }, f)
+}
+
+/*
+ * the variant with a single datetime column: the numeric fields,
+ * weeknumber (iso week) and date text get derived from it
+ */
+
+const DATETIME_FORMATS : [&str; 4] = [
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M",
+    "%Y-%m-%dT%H:%M",
+];
+
+/// The day of a date or datetime text as the old app variants wrote
+/// them (`YYYY-MM-DD`, optionally with a time, or RFC 3339).
+pub fn parse_datetime ( s : &str ) -> Option<NaiveDate>
+{
+    let s = s.trim();
+
+    DATETIME_FORMATS.iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(s, f).ok())
+        .map(|dt| dt.date())
+        .or_else(|| DateTime::parse_from_rfc3339(s).ok().map(|dt| dt.date_naive()))
+        .or_else(|| NaiveDate::parse_from_str(s, "%Y-%m-%d").ok())
+}
+
+/// Zone the old app recorded its datetimes in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SourceZone {
+    /// The zone of the machine running the migration, with its DST rules.
+    Local,
+    /// A fixed offset from UTC.
+    Fixed(FixedOffset),
+}
+
+impl SourceZone
+{
+    pub const UTC : SourceZone = SourceZone::Fixed(FixedOffset::east_opt(0).unwrap());
+
+    /// `UTC`, `local` or an offset like `+02:00`, `-0530`; no named zones,
+    /// their DST rules would need the tz database.
+    pub fn parse ( s : &str ) -> Option<SourceZone>
+    {
+        match s.to_ascii_lowercase().as_str()
+        {
+            "local"        => Some(SourceZone::Local),
+            "utc" | "z"    => Some(SourceZone::UTC),
+            _              => s.parse::<FixedOffset>().ok().map(SourceZone::Fixed),
+        }
+    }
+
+    /*
+     * a wall clock time of this zone as an instant; one in the gap of a
+     * local dst change is taken an hour later, one in the repeated hour at
+     * its first occurrence
+     */
+    fn instant ( &self, dt : NaiveDateTime ) -> DateTime<Utc>
+    {
+        match self
+        {
+            SourceZone::Local    => Local.from_local_datetime(&dt).earliest()
+                .or_else(|| Local.from_local_datetime(&(dt + chrono::Duration::hours(1))).earliest())
+                .map(|t| t.with_timezone(&Utc))
+                .unwrap_or_else(|| dt.and_utc()),
+            SourceZone::Fixed(o) => (dt - *o).and_utc(),
+        }
+    }
+}
+
+impl fmt::Display for SourceZone
+{
+    fn fmt ( &self, f : &mut fmt::Formatter ) -> fmt::Result
+    {
+        match self
+        {
+            SourceZone::Local    => write!(f, "local"),
+            SourceZone::Fixed(o) => write!(f, "{}", o),
+        }
+    }
+}
+
+/// The day of a date or datetime text in `zone`: datetimes without an
+/// offset are taken as UTC (what some variants of the old app wrote),
+/// those with one by it; date-only texts stay the day they are.
+///
+/// ```
+/// use timetracker_transitiontool::old::{parse_datetime_in, SourceZone};
+///
+/// let zone = SourceZone::parse("+02:00").unwrap();
+/// let day  = parse_datetime_in("2024-03-04 23:30:00", zone).unwrap();
+/// assert_eq!(day.to_string(), "2024-03-05");
+/// assert_eq!(parse_datetime_in("2024-03-04", zone).unwrap().to_string(), "2024-03-04");
+/// ```
+pub fn parse_datetime_in ( s : &str, zone : SourceZone ) -> Option<NaiveDate>
+{
+    parse_datetime_between(s, SourceZone::UTC, zone)
+}
+
+/// The day of a date or datetime text written in `from`, once moved to
+/// `to`: for a db recorded while travelling or before a move. Datetimes
+/// with an offset keep going by it; date-only texts stay the day they are,
+/// there's no time to move them by.
+///
+/// ```
+/// use timetracker_transitiontool::old::{parse_datetime_between, SourceZone};
+///
+/// let berlin = SourceZone::parse("+01:00").unwrap();
+/// let la     = SourceZone::parse("-08:00").unwrap();
+/// let day    = |s| parse_datetime_between(s, berlin, la).unwrap().to_string();
+///
+/// assert_eq!(day("2024-03-05 08:59:59"), "2024-03-04");
+/// assert_eq!(day("2024-03-05 09:00:00"), "2024-03-05");
+/// assert_eq!(day("2024-12-30T00:30"),    "2024-12-29");
+/// assert_eq!(day("2024-03-05T09:00:00+00:00"), "2024-03-05");
+/// assert_eq!(day("2024-03-05"),          "2024-03-05");
+/// assert_eq!(parse_datetime_between("2024-03-04 23:30", la, berlin).unwrap().to_string(), "2024-03-05");
+/// ```
+pub fn parse_datetime_between ( s : &str, from : SourceZone, to : SourceZone ) -> Option<NaiveDate>
+{
+    let s = s.trim();
+
+    let utc = DATETIME_FORMATS.iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(s, f).ok())
+        .map(|dt| from.instant(dt))
+        .or_else(|| DateTime::parse_from_rfc3339(s).ok().map(|dt| dt.with_timezone(&Utc)));
+
+    match (utc, to)
+    {
+        (Some(dt), SourceZone::Local)    => Some(dt.with_timezone(&Local).date_naive()),
+        (Some(dt), SourceZone::Fixed(o)) => Some(dt.with_timezone(&o).date_naive()),
+        (None, _)                        => NaiveDate::parse_from_str(s, "%Y-%m-%d").ok(),
+    }
+}
+
+/// History rows of the datetime variant whose day moves once read in
+/// `tables.source_timezone` (written in `tables.datetime_timezone`): (activity, datetime text, day as written,
+/// day in the zone). None for the year/month/day variant, it has no times.
+pub fn timezone_shifts ( db_old : &Connection, tables : &OldTables )
+    -> Result<Vec<(i32, String, NaiveDate, NaiveDate)>, MigrationError>
+{
+    let Some(zone) = tables.source_timezone else { return Ok(Vec::new()) };
+    let from = tables.datetime_timezone.unwrap_or(SourceZone::UTC);
+    if schema::old_history_layout(db_old, tables)? != schema::HistoryLayout::Combined
+    {
+        return Ok(Vec::new());
+    }
+
+    let mut stmt = db_old
+        .prepare(&format!("SELECT id_activity, datetime FROM {}", schema::quote_ident(&tables.history)))
+        .map_err(error::schema)?;
+    let iter = stmt
+        .query_map([], |row| Ok((row.get::<_, i32>(0)?, row.get::<_, String>(1)?)))
+        .map_err(error::schema)?;
+
+    let mut shifts = Vec::new();
+    for e in iter
+    {
+        let (id, datetime) = e.map_err(error::schema)?;
+        if let (Some(before), Some(after)) = (parse_datetime(&datetime), parse_datetime_between(&datetime, from, zone))
+        {
+            if before != after { shifts.push((id, datetime, before, after)); }
+        }
+    }
+
+    Ok(shifts)
+}
+
+fn for_each_combined_history ( db_old    : &Connection,
+                               tables    : &OldTables,
+                               page_size : Option<usize>,
+                               mut f     : impl FnMut(OldHistory) -> Result<bool, MigrationError> )
+    -> Result<(), MigrationError>
+{
+    let sql = format!("SELECT id_activity, datetime, {} FROM {}",
+                      schema::quote_ident(&tables.hours_column),
+                      schema::quote_ident(&tables.history));
+
+    let map = |row : &rusqlite::Row| {
+        Ok((get::<i32>(row, 0)?, get::<String>(row, 1)?, get::<f64>(row, 2)?))
+    };
+
+    for_each_row(db_old, &sql, page_size, map, |(id_activity, datetime, hours_on_day)| {
+        let d = match tables.source_timezone
+        {
+            Some(zone) => parse_datetime_between(&datetime,
+                              tables.datetime_timezone.unwrap_or(SourceZone::UTC), zone),
+            None       => parse_datetime(&datetime),
+        };
+        let d = d.ok_or_else(|| MigrationError::Schema(format!(
+            "history of activity {}: can't read datetime {:?}", id_activity, datetime)))?;
+
+        f(OldHistory {
+            id_activity,
+            year         : d.year(),
+            month        : d.month() as i32,
+            day          : d.day() as i32,
+            weeknumber   : d.iso_week().week() as i32,
+            hours_on_day,
+            date         : d.format("%Y-%m-%d").to_string(),
+        })
+    })
+}
+
+/// Reads all rows of the old `history` table.
+///
+/// Histories of the old variant with a single `datetime` column instead
+/// of year/month/day are read too, the day fields derived from it.
+///
+/// ```
+/// use rusqlite::Connection;
+/// use timetracker_transitiontool::read_old_history;
+///
+/// let db = Connection::open_in_memory()?;
+/// db.execute_batch(
+///     "CREATE TABLE history (id_activity, year, month, day, weeknumber, hours_on_day, date);
+///      INSERT INTO history VALUES (1, 2024, 1, 1, 1, 2.0, '2024-01-01');")?;
+///
+/// let history = read_old_history(&db)?;
+/// assert_eq!(history[0].date, "2024-01-01");
+/// assert_eq!(history[0].hours_on_day, 2.0);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn read_old_history ( db_old : &Connection )
+    -> Result<Vec<OldHistory>, MigrationError>
+{
+    read_old_history_in(db_old, &OldTables::default())
+}
+
+/// Like [`read_old_history`], for an old db with renamed tables.
+pub fn read_old_history_in ( db_old : &Connection, tables : &OldTables )
+    -> Result<Vec<OldHistory>, MigrationError>
+{
+    let mut oldhis : Vec<OldHistory> = Vec::new();
+    for_each_history(db_old, tables, |e| { oldhis.push(e); Ok(true) })?;
+
+    Ok(oldhis)
+}
@@ -0,0 +1,122 @@
+use crate::round::RoundMode;
+
+/*
+ * Everything deciding which rows get migrated and how their values get
+ * transformed on the way, in one place; the command line tool fills it
+ * from its flags
+ */
+
+/// How a migration transforms the old data, for [`crate::migrate()`];
+/// [`Default`] is what the command line tool does without any flags.
+#[derive(Debug, Clone)]
+pub struct MigrationOptions {
+    /// Leave out deactivated activities and their history.
+    pub skip_inactive          : bool,
+    /// Name patterns of activities to migrate (all if empty) resp. not to
+    /// migrate: substrings, or globs if containing `*` or `?`.
+    pub include_name           : Vec<String>,
+    pub exclude_name           : Vec<String>,
+    /// Only this one activity (exact name, or the single one matching it
+    /// like `include_name` does) and its history.
+    pub activity               : Option<String>,
+    /// Sum up history rows of an activity on the same day into one.
+    pub compact_history        : bool,
+    /// Keep only the first history row of an activity on a date, and have
+    /// the new db refuse a second one (`UNIQUE(id, date)`).
+    pub dedup_history          : bool,
+    /// Sum up history rows of an activity with the same date text into
+    /// one (accidental double entries, as `--report-source-dups` lists).
+    pub merge_source_dups      : bool,
+    /// Set activity totals to the sum of their history again once the
+    /// history got altered (eg by compacting).
+    pub recompute_totals       : bool,
+    /// Only the first n history rows resp. activities, after filtering.
+    pub limit                  : Option<usize>,
+    pub limit_activities       : Option<usize>,
+    /// Only n history rows picked at random (the same ones for the same
+    /// seed), and the activities they belong to.
+    pub sample                 : Option<usize>,
+    pub sample_seed            : u64,
+    /// Leave out activities left without history after all of the above.
+    pub prune_empty_activities : bool,
+    /// Decimals hours are rounded to, and how ties are broken.
+    pub precision              : u32,
+    pub round_mode             : RoundMode,
+    /// Hours two totals may differ by and still count as equal.
+    pub tolerance              : f64,
+    /// First and last year a history row's day may be in; one outside
+    /// (or not a day at all) is an invalid date.
+    pub years                  : (i32, i32),
+    /// Leave history rows with an invalid date out instead of failing.
+    pub skip_invalid_dates     : bool,
+    /// Which side wins when a history row's date text and its numeric
+    /// fields disagree; without, mismatches are only reported.
+    pub trust_dates            : Option<DateTrust>,
+    /// What to store for activities whose added date is empty or no
+    /// date, the first choice that has one for them wins.
+    pub added_fallback         : Vec<AddedFallback>,
+}
+
+impl Default for MigrationOptions
+{
+    fn default() -> Self
+    {
+        MigrationOptions {
+            skip_inactive          : false,
+            include_name           : Vec::new(),
+            exclude_name           : Vec::new(),
+            activity               : None,
+            compact_history        : false,
+            dedup_history          : false,
+            merge_source_dups      : false,
+            recompute_totals       : true,
+            limit                  : None,
+            limit_activities       : None,
+            sample                 : None,
+            sample_seed            : 0,
+            prune_empty_activities : false,
+            precision              : 6,
+            round_mode             : Default::default(),
+            tolerance              : 0.001,
+            years                  : (1900, 2200),
+            skip_invalid_dates     : false,
+            trust_dates            : None,
+            added_fallback         : Vec::new(),
+        }
+    }
+}
+
+/// Where the date of a history row comes from if its `date` text and its
+/// year/month/day fields disagree.
+#[derive(Debug, Clone, Copy)]
+pub enum DateTrust {
+    /// The fields; the text gets rewritten.
+    Numeric,
+    /// The text; the fields get rewritten.
+    String,
+}
+
+/// Where an activity without a usable added date gets one from.
+#[derive(Debug, Clone, Copy)]
+pub enum AddedFallback {
+    /// Its first day with history.
+    EarliestHistory,
+    /// The day of the run.
+    Now,
+    /// 1970-01-01.
+    Epoch,
+}
+
+impl AddedFallback
+{
+    /// Its name on the command line.
+    pub fn name ( &self ) -> &'static str
+    {
+        match self
+        {
+            AddedFallback::EarliestHistory => "earliest-history",
+            AddedFallback::Now             => "now",
+            AddedFallback::Epoch           => "epoch",
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use std::io::IsTerminal;
+
+use indicatif::ProgressBar;
+use indicatif::ProgressStyle;
+
+/*
+ * Progress of the history insert; a bar on interactive terminals,
+ * otherwise (or with --quiet) a line every tenth of the way
+ */
+
+pub struct Progress {
+    bar   : Option<ProgressBar>,
+    total : Option<u64>,	// unknown while streaming
+    done  : u64,
+}
+
+const LINE_EVERY_UNKNOWN : u64 = 10_000;
+const LINE_EVERY_MIN     : u64 = 1_000;
+
+impl Progress
+{
+    pub fn new ( total : Option<u64>, quiet : bool ) -> Progress
+    {
+        let bar = if !quiet && std::io::stdout().is_terminal()
+        {
+            let bar = match total
+            {
+                Some(n) =>
+                {
+                    let b = ProgressBar::new(n);
+                    b.set_style(ProgressStyle::with_template(
+                        "history {bar:40} {pos}/{len} rows ({eta})")
+                        .unwrap_or_else(|_| ProgressStyle::default_bar()));
+                    b
+                }
+                None =>
+                {
+                    let b = ProgressBar::new_spinner();
+                    b.set_style(ProgressStyle::with_template("history {spinner} {pos} rows")
+                        .unwrap_or_else(|_| ProgressStyle::default_spinner()));
+                    b
+                }
+            };
+            Some(bar)
+        }
+        else
+        {
+            None
+        };
+
+        Progress { bar, total, done : 0 }
+    }
+
+    pub fn inc ( &mut self )
+    {
+        self.done += 1;
+
+        if let Some(bar) = &self.bar
+        {
+            bar.inc(1);
+            return;
+        }
+
+        let every = match self.total
+        {
+            Some(n) => (n / 10).max(LINE_EVERY_MIN),
+            None    => LINE_EVERY_UNKNOWN,
+        };
+        if self.done.is_multiple_of(every)
+        {
+            match self.total
+            {
+                Some(n) => println!("history: {}/{} rows", self.done, n),
+                None    => println!("history: {} rows", self.done),
+            }
+        }
+    }
+
+    pub fn finish ( self )
+    {
+        if let Some(bar) = self.bar
+        {
+            bar.finish();
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use std::fs;
+use std::path;
+
+use serde_json::json;
+use serde_json::Value;
+
+use crate::error::MigrationError;
+use crate::old::OldActivity;
+use crate::old::OldHistory;
+
+/*
+ * Rows that didn't make it into the new db (--quarantine)
+ *
+ * written as json in the layout of --input-format json, every row with an
+ * additional "reason" (ignored when reading it back in), so fixed rows can
+ * be migrated from the file directly; or as csv if the path ends in .csv
+ */
+
+#[derive(Default)]
+pub struct Quarantine {
+    activities : Vec<Value>,
+    history    : Vec<Value>,
+}
+
+fn with_reason ( mut row : Value, reason : &str ) -> Value
+{
+    if let Value::Object(m) = &mut row
+    {
+        m.insert("reason".to_string(), json!(reason));
+    }
+    row
+}
+
+const CSV_HEADERS : [&str; 14] = [
+    "table", "reason",
+    "id", "group_id", "name", "added_when", "is_activated", "hours_total",
+    "year", "month", "day", "weeknumber", "hours_on_day", "date",
+];
+
+fn csv_field ( v : Option<&Value> ) -> String
+{
+    let s = match v
+    {
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Null) | None => String::new(),
+        Some(v) => v.to_string(),
+    };
+
+    if s.contains([',', '"', '\n'])
+    {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    }
+    else
+    {
+        s
+    }
+}
+
+impl Quarantine
+{
+    pub fn activity ( &mut self, e : &OldActivity, reason : &str )
+    {
+        self.activities.push(with_reason(json!(e), reason));
+    }
+
+    pub fn history ( &mut self, e : &OldHistory, reason : &str )
+    {
+        self.history.push(with_reason(json!(e), reason));
+    }
+
+    pub fn len ( &self ) -> usize
+    {
+        self.activities.len() + self.history.len()
+    }
+
+    pub fn write ( &self, path : &path::Path ) -> Result<(), MigrationError>
+    {
+        let csv = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("csv"));
+
+        let out = if csv
+        {
+            let mut lines = vec![CSV_HEADERS.join(",")];
+            let tables = [("activities", &self.activities), ("history", &self.history)];
+
+            for (table, rows) in tables
+            {
+                for r in rows
+                {
+                    let cells : Vec<String> = CSV_HEADERS.iter()
+                        .map(|h| match *h
+                        {
+                            "table"                  => table.to_string(),
+                            "id" if table == "history" => csv_field(r.get("id_activity")),
+                            _                        => csv_field(r.get(*h)),
+                        })
+                        .collect();
+                    lines.push(cells.join(","));
+                }
+            }
+            lines.join("\n") + "\n"
+        }
+        else
+        {
+            let dump = json!({ "activities" : self.activities, "history" : self.history });
+            serde_json::to_string_pretty(&dump).unwrap_or_default() + "\n"
+        };
+
+        fs::write(path, out).map_err(|e| MigrationError::Io(format!(
+            "can't write quarantine file {:?}: {}", path, e)))
+    }
+}
@@ -0,0 +1,85 @@
+use std::fs;
+use std::path;
+
+use rusqlite::Connection;
+use serde_json::json;
+use serde_json::Value;
+
+use crate::args::OutputFormat;
+use crate::args::Report;
+use crate::error;
+use crate::error::MigrationError;
+use crate::inspect;
+use crate::round6;
+
+/*
+ * Aggregations over an already migrated new db (--report), printed like
+ * the inspection modes
+ */
+
+pub fn print ( format : &OutputFormat, report : &Report, db_new : &Connection )
+    -> Result<(), MigrationError>
+{
+    match report
+    {
+        Report::Monthly => monthly(format, db_new),
+    }
+}
+
+fn monthly ( format : &OutputFormat, db_new : &Connection ) -> Result<(), MigrationError>
+{
+    let mut stmt = db_new
+        .prepare("SELECT year, month, SUM(hoursonday), COUNT(DISTINCT date)
+                  FROM tt_history GROUP BY year, month ORDER BY year, month")
+        .map_err(error::schema)?;
+
+    let iter = stmt.query_map([], |row| {
+        Ok(vec![
+            json!(row.get::<_, i32>(0)?),
+            json!(row.get::<_, i32>(1)?),
+            json!(round6(row.get::<_, f64>(2)?)),
+            json!(row.get::<_, i64>(3)?),
+        ])
+    }).map_err(error::schema)?;
+
+    let mut rows : Vec<Vec<Value>> = Vec::new();
+    for e in iter { rows.push(e.map_err(error::schema)?); }
+
+    inspect::print_rows(format, &["year", "month", "hours", "days"], &rows);
+
+    Ok(())
+}
+
+/*
+ * --export-weekly, hours per activity and iso week of the migrated
+ * tt_history as csv; returns the number of rows written
+ */
+
+pub fn export_weekly ( db_new : &Connection, path : &path::Path ) -> Result<usize, MigrationError>
+{
+    let mut stmt = db_new
+        .prepare("SELECT h.id, COALESCE(a.name, ''), h.isoweekyear, h.isoweek,
+                         SUM(h.hoursonday), COUNT(DISTINCT h.date)
+                  FROM tt_history h LEFT JOIN tt_activities a ON a.id = h.id
+                  GROUP BY h.id, h.isoweekyear, h.isoweek
+                  ORDER BY h.isoweekyear, h.isoweek, h.id")
+        .map_err(error::partial)?;
+
+    let iter = stmt.query_map([], |row| {
+        Ok(format!("{},{},{},{},{},{}",
+            row.get::<_, i32>(0)?,
+            inspect::csv_field(&row.get::<_, String>(1)?),
+            row.get::<_, i32>(2)?,
+            row.get::<_, i32>(3)?,
+            round6(row.get::<_, f64>(4)?),
+            row.get::<_, i64>(5)?))
+    }).map_err(error::partial)?;
+
+    let mut lines = vec!["activity,name,isoweekyear,isoweek,hours,days".to_string()];
+    for e in iter { lines.push(e.map_err(error::partial)?); }
+
+    fs::write(path, lines.join("\n") + "\n").map_err(|e| MigrationError::Io(format!(
+        "can't write weekly summary {:?}: {}", path, e)))?;
+
+    Ok(lines.len() - 1)
+}
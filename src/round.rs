@@ -0,0 +1,73 @@
+use std::sync::OnceLock;
+
+/*
+ * Rounding of hours; the old app stored float sums with noise in the
+ * last digits, the new one keeps 6 decimals
+ */
+
+/// How ties (exactly halfway between two results) are broken.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum RoundMode {
+    /// Away from zero, `f64::round`.
+    #[default]
+    HalfUp,
+    /// To the even neighbour (banker's rounding).
+    HalfEven,
+}
+
+/// Rounds `val` to `digits` decimals.
+///
+/// ```
+/// use timetracker_transitiontool::round::{round_to, RoundMode};
+///
+/// assert_eq!(round_to( 2.5,  0, RoundMode::HalfUp),    3.0);
+/// assert_eq!(round_to( 2.5,  0, RoundMode::HalfEven),  2.0);
+/// assert_eq!(round_to( 3.5,  0, RoundMode::HalfEven),  4.0);
+/// assert_eq!(round_to(-2.5,  0, RoundMode::HalfUp),   -3.0);
+/// assert_eq!(round_to(-2.5,  0, RoundMode::HalfEven), -2.0);
+/// assert_eq!(round_to( 0.25, 1, RoundMode::HalfUp),    0.3);
+/// assert_eq!(round_to( 0.25, 1, RoundMode::HalfEven),  0.2);
+/// assert_eq!(round_to( 1.2345678, 6, RoundMode::HalfEven), 1.234568);
+/// ```
+pub fn round_to ( val : f64, digits : i32, mode : RoundMode ) -> f64
+{
+    let scale  = 10f64.powi(digits);
+    let scaled = val * scale;
+
+    let rounded = match mode
+    {
+        RoundMode::HalfUp   => scaled.round(),
+        RoundMode::HalfEven => scaled.round_ties_even(),
+    };
+
+    rounded / scale
+}
+
+/*
+ * hours get rounded to MigrationOptions::precision decimals (6 unless
+ * set differently), with its round_mode; set once before anything gets
+ * rounded
+ */
+
+static ROUNDING : OnceLock<(u32, RoundMode)> = OnceLock::new();
+
+/// Sets the decimals and tie breaking of [`round6`] for the rest of the
+/// process; only the first call counts.
+pub fn set_rounding ( digits : u32, mode : RoundMode )
+{
+    let _ = ROUNDING.set((digits, mode));
+}
+
+/// Rounds hours the way they're written to the new db: 6 decimals,
+/// half-up, unless [`set_rounding`] said otherwise.
+///
+/// ```
+/// use timetracker_transitiontool::round::round6;
+///
+/// assert_eq!(round6(1.2345674), 1.234567);
+/// ```
+pub fn round6 ( val : f64 ) -> f64
+{
+    let (digits, mode) = ROUNDING.get().copied().unwrap_or((6, RoundMode::default()));
+    round_to(val, digits as i32, mode)
+}
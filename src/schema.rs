@@ -0,0 +1,191 @@
+use std::collections::HashSet;
+
+use rusqlite::Connection;
+
+use crate::error;
+use crate::error::MigrationError;
+use crate::old::OldTables;
+
+/*
+ * Inspection of a db's layout through table_list and table_info, both of
+ * which see the dbs attached to the connection too
+ */
+
+pub fn table_names ( db : &Connection ) -> Result<HashSet<String>, MigrationError>
+{
+    let mut stmt = db
+        .prepare("SELECT name FROM pragma_table_list WHERE type = 'table' \
+                  AND name NOT IN ('sqlite_schema', 'sqlite_temp_schema')")
+        .map_err(error::io)?;
+
+    let iter = stmt
+        .query_map([], |row| row.get::<_, String>(0))
+        .map_err(error::io)?;
+
+    let mut names : HashSet<String> = HashSet::new();
+    for e in iter { names.insert(e.map_err(error::io)?); }
+
+    Ok(names)
+}
+
+/*
+ * table/column names given by the user end up inside statements, only
+ * plain identifiers (^[A-Za-z_][A-Za-z0-9_]*$) are accepted for them;
+ * quoting alone would do, but there's no need to allow anything else
+ */
+
+/// Whether `name` is a plain identifier that may be put into a statement.
+pub fn is_plain_ident ( name : &str ) -> bool
+{
+    let mut chars = name.chars();
+
+    match chars.next()
+    {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' =>
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
+
+/// Errors unless `name` is a plain identifier, see [`is_plain_ident`].
+pub fn check_ident ( name : &str ) -> Result<(), MigrationError>
+{
+    if is_plain_ident(name)
+    {
+        return Ok(());
+    }
+
+    Err(MigrationError::Schema(format!(
+        "invalid table/column name {:?}, only letters, digits and _ are allowed \
+         (not starting with a digit)", name)))
+}
+
+/// Quotes a table/column name for use in a statement.
+pub fn quote_ident ( name : &str ) -> String
+{
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// (name, declared type) of every column of `table`.
+pub fn columns ( db : &Connection, table : &str ) -> Result<Vec<(String, String)>, MigrationError>
+{
+    let mut stmt = db
+        .prepare("SELECT name, type FROM pragma_table_info(?1)")
+        .map_err(error::io)?;
+
+    let iter = stmt
+        .query_map([table], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))
+        .map_err(error::io)?;
+
+    let mut cols : Vec<(String, String)> = Vec::new();
+    for e in iter { cols.push(e.map_err(error::io)?); }
+
+    Ok(cols)
+}
+
+pub fn column_names ( db : &Connection, table : &str ) -> Result<Vec<String>, MigrationError>
+{
+    Ok(columns(db, table)?.into_iter().map(|(name, _)| name).collect())
+}
+
+/// How an old history table stores the day of a row.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HistoryLayout {
+    /// `year`, `month`, `day`, `weeknumber` and `date` columns.
+    Split,
+    /// A single `datetime` text column instead, used by one old variant.
+    Combined,
+}
+
+/// Old layouts `--from-version` can force, by name. The old app never
+/// numbered its db layouts, so they are named after how history stores
+/// the day.
+pub const OLD_VERSIONS : [(&str, HistoryLayout); 2] = [
+    ("classic",  HistoryLayout::Split),		// the archived C++ app
+    ("datetime", HistoryLayout::Combined),
+];
+
+pub fn old_version ( name : &str ) -> Option<HistoryLayout>
+{
+    OLD_VERSIONS.iter().find(|(n, _)| *n == name).map(|(_, l)| *l)
+}
+
+/// The layout of the old history: the one forced in `tables`, or else
+/// detected by [`history_layout`].
+pub fn old_history_layout ( db : &Connection, tables : &OldTables )
+    -> Result<HistoryLayout, MigrationError>
+{
+    match tables.layout
+    {
+        Some(l) => Ok(l),
+        None    => history_layout(db, &tables.history),
+    }
+}
+
+pub fn history_layout ( db : &Connection, table : &str ) -> Result<HistoryLayout, MigrationError>
+{
+    let cols = column_names(db, table)?;
+    let has  = |c : &str| cols.iter().any(|n| n == c);
+
+    if has("datetime") && !has("year") && !has("month") && !has("day")
+    {
+        Ok(HistoryLayout::Combined)
+    }
+    else
+    {
+        Ok(HistoryLayout::Split)
+    }
+}
+
+/*
+ * make sure the source actually is an old db before reading from it
+ */
+
+pub fn check_old_layout ( db : &Connection, tables : &OldTables ) -> Result<(), MigrationError>
+{
+    let names = table_names(db)?;
+
+    if names.contains("tt_activities") || names.contains("tt_history")
+    {
+        return Err(MigrationError::Schema(
+            "this looks like an already-migrated database \
+             (found tt_activities/tt_history), refusing to migrate it again"
+            .to_string()));
+    }
+
+    /*
+     * some partial old dbs come without history, whether that's fine is
+     * up to the caller
+     */
+
+    if !names.contains(&tables.activities)
+    {
+        return Err(MigrationError::Schema(
+            format!("old db has no table '{}'", tables.activities)));
+    }
+
+    if names.contains(&tables.history) && (tables.renamed_columns() || tables.layout.is_some())
+    {
+        let cols = column_names(db, &tables.history)?;
+        let mut wanted = vec![tables.hours_column.as_str()];
+        match old_history_layout(db, tables)?
+        {
+            HistoryLayout::Split    =>
+                wanted.extend(["year", "month", "day", "weeknumber", &tables.date_column]),
+            HistoryLayout::Combined =>
+                wanted.push("datetime"),
+        }
+
+        for c in wanted
+        {
+            if !cols.iter().any(|n| n == c)
+            {
+                return Err(MigrationError::Schema(format!(
+                    "old table {} has no column '{}' (it has: {})",
+                    tables.history, c, cols.join(", "))));
+            }
+        }
+    }
+
+    Ok(())
+}
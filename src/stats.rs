@@ -0,0 +1,106 @@
+use rusqlite::Connection;
+use serde_json::json;
+
+use crate::error;
+use crate::error::MigrationError;
+use crate::round6;
+
+/*
+ * What a migration run did, printed as summary at the end
+ */
+
+#[derive(Default)]
+pub struct MigrationStats {
+    pub activities   : usize,			// inserted into tt_activities
+    pub history_rows : usize,			// inserted into tt_history
+    pub first_date   : Option<String>,	// of all of tt_history
+    pub last_date    : Option<String>,
+    pub years        : Vec<(i32, f64)>,	// hours of tt_history per isoweekyear
+}
+
+impl MigrationStats
+{
+    /*
+     * date range is read back from the new db, so it also covers rows
+     * that were there before this run
+     */
+
+    pub fn read_date_range ( &mut self, db_new : &Connection ) -> Result<(), MigrationError>
+    {
+        (self.first_date, self.last_date) = db_new
+            .query_row("SELECT MIN(date), MAX(date) FROM tt_history", [],
+                       |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(error::partial)?;
+
+        Ok(())
+    }
+
+    /*
+     * per year totals, also read back from the new db: a year missing or
+     * way off is the quickest sign of a botched migration
+     */
+
+    pub fn read_years ( &mut self, db_new : &Connection ) -> Result<(), MigrationError>
+    {
+        let mut stmt = db_new
+            .prepare("SELECT isoweekyear, SUM(hoursonday) FROM tt_history
+                      GROUP BY isoweekyear ORDER BY isoweekyear")
+            .map_err(error::partial)?;
+
+        let iter = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(error::partial)?;
+
+        self.years.clear();
+        for e in iter { self.years.push(e.map_err(error::partial)?); }
+
+        Ok(())
+    }
+
+    pub fn print ( &self )
+    {
+        println!();
+        println!("Summary");
+        println!("    activities migrated   : {}", self.activities);
+        println!("    history rows migrated : {}", self.history_rows);
+
+        match (&self.first_date, &self.last_date)
+        {
+            (Some(first), Some(last)) =>
+                println!("    history spans {} to {}", first, last),
+            _ =>
+                println!("    history is empty"),
+        }
+
+        if !self.years.is_empty()
+        {
+            println!();
+            println!("    {:<6}  {:>12}", "year", "hours");
+            for (year, hours) in &self.years
+            {
+                println!("    {:<6}  {:>12}", year, round6(*hours));
+            }
+        }
+    }
+
+    /*
+     * --json, the same as one json object
+     */
+
+    pub fn print_json ( &self, warnings : &[String] )
+    {
+        let years : Vec<serde_json::Value> = self.years.iter()
+            .map(|(year, hours)| json!({ "isoweekyear" : year, "hours" : round6(*hours) }))
+            .collect();
+
+        let summary = json!({
+            "activities_migrated"   : self.activities,
+            "history_rows_migrated" : self.history_rows,
+            "first_date"            : self.first_date,
+            "last_date"             : self.last_date,
+            "years"                 : years,
+            "warnings"              : warnings,
+        });
+        println!("{}", serde_json::to_string_pretty(&summary).unwrap_or_default());
+    }
+}
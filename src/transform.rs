@@ -0,0 +1,742 @@
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use chrono::Datelike;
+use chrono::NaiveDate;
+
+use crate::options::AddedFallback;
+use crate::options::DateTrust;
+use crate::old;
+use crate::round::round6;
+use crate::old::OldActivity;
+use crate::old::OldHistory;
+use crate::warn::Warnings;
+use crate::error::MigrationError;
+
+/*
+ * Transformations applied to the old rows before they get inserted
+ */
+
+/*
+ * merge all history rows of an activity for the same day into one,
+ * the first row of a day keeps its position, date and weeknumber;
+ * returns the compacted rows, how many rows got merged away and the ids
+ * of the activities that had rows merged
+ */
+
+pub fn compact_history ( oldhis : Vec<OldHistory> ) -> (Vec<OldHistory>, usize, HashSet<i32>)
+{
+    let n_before = oldhis.len();
+    let mut index : HashMap<(i32, i32, i32, i32), usize> = HashMap::new();
+    let mut compacted : Vec<OldHistory> = Vec::new();
+    let mut touched   : HashSet<i32> = HashSet::new();
+
+    for e in oldhis
+    {
+        let key = (e.id_activity, e.year, e.month, e.day);
+
+        if let Some(&i) = index.get(&key)
+        {
+            compacted[i].hours_on_day += e.hours_on_day;
+            touched.insert(e.id_activity);
+        }
+        else
+        {
+            index.insert(key, compacted.len());
+            compacted.push(e);
+        }
+    }
+
+    for e in compacted.iter_mut() { e.hours_on_day = round6(e.hours_on_day); }
+
+    let n_merged = n_before - compacted.len();
+    (compacted, n_merged, touched)
+}
+
+/*
+ * history rows of an activity sharing a date text (the key of the new
+ * tt_history), as indices into `oldhis` in the order of their first row;
+ * only dates with more than one row
+ */
+
+fn same_date_groups ( oldhis : &[OldHistory] ) -> Vec<Vec<usize>>
+{
+    let mut index  : HashMap<(i32, &str), usize> = HashMap::new();
+    let mut groups : Vec<Vec<usize>> = Vec::new();
+
+    for (i, e) in oldhis.iter().enumerate()
+    {
+        match index.get(&(e.id_activity, e.date.as_str()))
+        {
+            Some(&g) => groups[g].push(i),
+            None     =>
+            {
+                index.insert((e.id_activity, e.date.as_str()), groups.len());
+                groups.push(vec![i]);
+            }
+        }
+    }
+
+    groups.retain(|g| g.len() > 1);
+    groups
+}
+
+/*
+ * a date of an activity with more than one history row
+ */
+
+#[derive(Debug)]
+pub struct SourceDup {
+    pub id    : i32,
+    pub date  : String,
+    pub hours : Vec<f64>,		// of each row, in their order
+}
+
+/*
+ * --report-source-dups, the dates with more than one row
+ */
+
+pub fn source_dups ( oldhis : &[OldHistory] ) -> Vec<SourceDup>
+{
+    listed(oldhis, &same_date_groups(oldhis))
+}
+
+fn listed ( oldhis : &[OldHistory], groups : &[Vec<usize>] ) -> Vec<SourceDup>
+{
+    groups.iter()
+        .map(|g| SourceDup {
+            id    : oldhis[g[0]].id_activity,
+            date  : oldhis[g[0]].date.clone(),
+            hours : g.iter().map(|&i| oldhis[i].hours_on_day).collect(),
+        })
+        .collect()
+}
+
+/*
+ * --merge-source-dups, the first row of a date gets the hours of all of
+ * them, the others are dropped; returns the merged rows, the groups as
+ * source_dups() lists them and the ids of the activities that had rows
+ * merged
+ */
+
+pub fn merge_source_dups ( oldhis : Vec<OldHistory> )
+    -> (Vec<OldHistory>, Vec<SourceDup>, HashSet<i32>)
+{
+    let groups  = same_date_groups(&oldhis);
+    let dups    = listed(&oldhis, &groups);
+    let touched = dups.iter().map(|d| d.id).collect();
+
+    let mut drop : HashSet<usize> = HashSet::new();
+    let mut sums : HashMap<usize, f64> = HashMap::new();
+    for g in &groups
+    {
+        sums.insert(g[0], round6(g.iter().map(|&i| oldhis[i].hours_on_day).sum()));
+        drop.extend(&g[1..]);
+    }
+
+    let merged = oldhis.into_iter().enumerate()
+        .filter(|(i, _)| !drop.contains(i))
+        .map(|(i, mut e)| {
+            if let Some(&h) = sums.get(&i) { e.hours_on_day = h; }
+            e
+        })
+        .collect();
+
+    (merged, dups, touched)
+}
+
+/*
+ * --zero-fill-gaps, a 0 hours row for every day between the first and the
+ * last day of an activity that has none, appended after the rows read
+ * (by activity, then day); activities merged into another (`folded`)
+ * count as that one. returns the number of rows added per activity
+ */
+
+pub fn zero_fill_gaps ( oldhis : &mut Vec<OldHistory>, folded : &HashMap<i32, i32> ) -> BTreeMap<i32, usize>
+{
+    let mut days : BTreeMap<i32, BTreeSet<NaiveDate>> = BTreeMap::new();
+    for e in oldhis.iter()
+    {
+        let day = u32::try_from(e.month).ok()
+            .zip(u32::try_from(e.day).ok())
+            .and_then(|(m, d)| NaiveDate::from_ymd_opt(e.year, m, d));
+        if let Some(d) = day
+        {
+            days.entry(*folded.get(&e.id_activity).unwrap_or(&e.id_activity)).or_default().insert(d);
+        }
+    }
+
+    let mut filled = BTreeMap::new();
+    for (id, logged) in days
+    {
+        let (Some(&first), Some(&last)) = (logged.first(), logged.last()) else { continue };
+        let gaps : Vec<NaiveDate> = first.iter_days()
+            .take_while(|d| *d <= last)
+            .filter(|d| !logged.contains(d))
+            .collect();
+
+        for d in &gaps
+        {
+            oldhis.push(OldHistory {
+                id_activity  : id,
+                year         : d.year(),
+                month        : d.month() as i32,
+                day          : d.day() as i32,
+                weeknumber   : d.iso_week().week() as i32,
+                hours_on_day : 0.,
+                date         : d.format("%Y-%m-%d").to_string(),
+            });
+        }
+        if !gaps.is_empty()
+        {
+            filled.insert(id, gaps.len());
+        }
+    }
+
+    filled
+}
+
+/*
+ * --dedup-history, keep the first row of an activity on a date and drop
+ * the others; returns the kept rows, how many got dropped and the ids of
+ * the activities that lost rows
+ */
+
+pub fn dedup_history ( oldhis : Vec<OldHistory> ) -> (Vec<OldHistory>, usize, HashSet<i32>)
+{
+    let n_before = oldhis.len();
+    let mut seen    : HashSet<(i32, String)> = HashSet::new();
+    let mut touched : HashSet<i32> = HashSet::new();
+
+    let kept : Vec<OldHistory> = oldhis.into_iter()
+        .filter(|e| {
+            let first = seen.insert((e.id_activity, e.date.clone()));
+            if !first { touched.insert(e.id_activity); }
+            first
+        })
+        .collect();
+
+    let n_dropped = n_before - kept.len();
+    (kept, n_dropped, touched)
+}
+
+/*
+ * once history rows of an activity got altered its stored total may no
+ * longer match them; set it to the sum of its (final) history again and
+ * return (id, name, before, after) of every total that changed
+ */
+
+pub fn reconcile_totals ( oldact : &mut [OldActivity],
+                          oldhis : &[OldHistory],
+                          ids    : &HashSet<i32> )
+    -> Vec<(i32, String, f64, f64)>
+{
+    let mut sums : HashMap<i32, f64> = HashMap::new();
+    for e in oldhis.iter().filter(|e| ids.contains(&e.id_activity))
+    {
+        *sums.entry(e.id_activity).or_insert(0.) += e.hours_on_day;
+    }
+
+    let mut changed = Vec::new();
+    for e in oldact.iter_mut().filter(|e| ids.contains(&e.id))
+    {
+        let before = round6(e.hours_total);
+        let after  = round6(sums.get(&e.id).copied().unwrap_or(0.));
+
+        if before != after
+        {
+            changed.push((e.id, e.name.clone(), before, after));
+        }
+        e.hours_total = after;
+    }
+
+    changed
+}
+
+/*
+ * --rename-activity, names are matched exactly; returns (id, old name,
+ * new name) of every rename applied and the old names that matched none
+ */
+
+pub fn rename_activities ( oldact  : &mut [OldActivity],
+                           renames : &[(String, String)] )
+    -> (Vec<(i32, String, String)>, Vec<String>)
+{
+    let mut applied   = Vec::new();
+    let mut unmatched = Vec::new();
+
+    for (from, to) in renames
+    {
+        let mut matched = false;
+        for e in oldact.iter_mut().filter(|e| &e.name == from)
+        {
+            applied.push((e.id, e.name.clone(), to.clone()));
+            e.name = to.clone();
+            matched = true;
+        }
+        if !matched
+        {
+            unmatched.push(from.clone());
+        }
+    }
+
+    (applied, unmatched)
+}
+
+/*
+ * --merge-by-name, names are the same once case and surrounding or
+ * repeated blanks are ignored
+ */
+
+pub fn normalized_name ( name : &str ) -> String
+{
+    name.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+/*
+ * old activities whose names are the same normalized: (normalized name,
+ * their ids) of every collision, ids in the order of the activities, the
+ * collisions in the order of their first one
+ */
+
+pub fn duplicate_names ( oldact : &[OldActivity] ) -> Vec<(String, Vec<i32>)>
+{
+    let mut groups : Vec<(String, Vec<i32>)> = Vec::new();
+    let mut index  : HashMap<String, usize>  = HashMap::new();
+
+    for e in oldact
+    {
+        let name = normalized_name(&e.name);
+        match index.get(&name)
+        {
+            Some(&i) => groups[i].1.push(e.id),
+            None     =>
+            {
+                index.insert(name.clone(), groups.len());
+                groups.push((name, vec![e.id]));
+            }
+        }
+    }
+
+    groups.retain(|(_, ids)| ids.len() > 1);
+    groups
+}
+
+/*
+ * --on-duplicate-name merge: the first activity of a collision takes the
+ * others' totals, and (mapped by the returned other id -> first id) their
+ * history; the others aren't migrated themselves
+ */
+
+pub fn merge_duplicates ( oldact : &mut Vec<OldActivity>, groups : &[(String, Vec<i32>)] )
+    -> HashMap<i32, i32>
+{
+    let mut folded = HashMap::new();
+    for (_, ids) in groups
+    {
+        for id in &ids[1..]
+        {
+            folded.insert(*id, ids[0]);
+        }
+    }
+
+    let mut totals : HashMap<i32, f64> = HashMap::new();
+    for e in oldact.iter().filter(|e| folded.contains_key(&e.id))
+    {
+        *totals.entry(folded[&e.id]).or_insert(0.) += e.hours_total;
+    }
+    oldact.retain(|e| !folded.contains_key(&e.id));
+    for e in oldact.iter_mut()
+    {
+        e.hours_total += totals.get(&e.id).copied().unwrap_or(0.);
+    }
+
+    folded
+}
+
+/*
+ * --on-duplicate-name suffix: the first activity of a collision keeps its
+ * name, the others get " (2)", " (3)", ... (skipping names that are taken);
+ * returns (id, old name, new name) of every one renamed
+ */
+
+pub fn suffix_duplicates ( oldact : &mut [OldActivity], groups : &[(String, Vec<i32>)] )
+    -> Vec<(i32, String, String)>
+{
+    let mut taken : HashSet<String> = oldact.iter().map(|e| normalized_name(&e.name)).collect();
+    let mut renamed = Vec::new();
+
+    for (_, ids) in groups
+    {
+        let mut n = 1;
+        for id in &ids[1..]
+        {
+            let Some(e) = oldact.iter_mut().find(|e| e.id == *id) else { continue };
+            let name = loop
+            {
+                n += 1;
+                let name = format!("{} ({})", e.name.trim(), n);
+                if taken.insert(normalized_name(&name)) { break name; }
+            };
+            renamed.push((e.id, std::mem::replace(&mut e.name, name.clone()), name));
+        }
+    }
+
+    renamed
+}
+
+/*
+ * --max-name-length, (id, length in characters) of every longer name; with
+ * `truncate` those get cut to `max` characters, the last one an ellipsis
+ */
+
+pub fn long_names ( oldact : &mut [OldActivity], max : usize, truncate : bool )
+    -> Vec<(i32, usize)>
+{
+    let mut long = Vec::new();
+
+    for e in oldact.iter_mut()
+    {
+        let len = e.name.chars().count();
+        if len <= max
+        {
+            continue;
+        }
+        long.push((e.id, len));
+        if truncate
+        {
+            e.name = e.name.chars().take(max - 1).collect::<String>() + "\u{2026}";
+        }
+    }
+
+    long
+}
+
+/*
+ * --prefer-existing: whether an activity total already in the new db
+ * wins over the old one, by being larger or having more decimals (the
+ * new app keeps exact times)
+ */
+
+pub fn keep_existing_total ( existing : f64, old : f64 ) -> bool
+{
+    let decimals = |h : f64| {
+        round6(h).to_string().split_once('.').map_or(0, |(_, d)| d.len())
+    };
+
+    round6(existing) > round6(old) || decimals(existing) > decimals(old)
+}
+
+/*
+ * --sample, n history rows chosen at random with a splitmix64 generator
+ * seeded by `seed` (partial fisher-yates over the row indices), kept in
+ * their original order
+ */
+
+fn splitmix64 ( state : &mut u64 ) -> u64
+{
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+pub fn sample_history ( oldhis : &mut Vec<OldHistory>, n : usize, seed : u64 )
+{
+    if oldhis.len() <= n
+    {
+        return;
+    }
+
+    let mut state   = seed;
+    let mut indices : Vec<usize> = (0..oldhis.len()).collect();
+    for i in 0..n
+    {
+        let j = i + (splitmix64(&mut state) % (indices.len() - i) as u64) as usize;
+        indices.swap(i, j);
+    }
+
+    let picked : HashSet<usize> = indices[..n].iter().copied().collect();
+    let mut i = 0;
+    oldhis.retain(|_| { i += 1; picked.contains(&(i - 1)) });
+}
+
+/*
+ * remove every activity for which `drop` is true together with its
+ * history (no orphans in the new db); returns the dropped activities
+ * and how many history rows went with them
+ */
+
+pub fn drop_activities ( oldact : &mut Vec<OldActivity>,
+                         oldhis : &mut Vec<OldHistory>,
+                         drop   : impl Fn(&OldActivity) -> bool )
+    -> (Vec<OldActivity>, usize)
+{
+    let (dropped, kept) : (Vec<OldActivity>, Vec<OldActivity>) =
+        std::mem::take(oldact).into_iter().partition(|e| drop(e));
+    *oldact = kept;
+
+    let ids : HashSet<i32> = dropped.iter().map(|e| e.id).collect();
+    let n_his = oldhis.len();
+    oldhis.retain(|e| !ids.contains(&e.id_activity));
+
+    (dropped, n_his - oldhis.len())
+}
+
+/*
+ * name patterns for --include-name/--exclude-name; with `*` or `?` in it
+ * a pattern is a glob over the whole name, otherwise a substring, both
+ * case-insensitive. a glob knows classes too, `[abc]`, `[a-z]` and `[!x]`;
+ * a `[` without its `]` is just a `[`
+ */
+
+fn glob ( pat : &[char], name : &[char] ) -> bool
+{
+    match pat.first()
+    {
+        None       => name.is_empty(),
+        Some('*')  => (0..=name.len()).any(|i| glob(&pat[1..], &name[i..])),
+        Some('?')  => !name.is_empty() && glob(&pat[1..], &name[1..]),
+        Some('[')  => match class(&pat[1..], name.first())
+        {
+            Some((hit, rest)) => hit && glob(rest, &name[1..]),
+            None              => name.first() == Some(&'[') && glob(&pat[1..], &name[1..]),
+        },
+        Some(c)    => name.first() == Some(c) && glob(&pat[1..], &name[1..]),
+    }
+}
+
+/*
+ * the class at the start of `pat` (past its `[`): whether `c` is in it,
+ * and the pattern after its `]`; None if it has no `]`. a `]` right at
+ * the start is one of the class
+ */
+
+fn class<'a> ( pat : &'a [char], c : Option<&char> ) -> Option<(bool, &'a [char])>
+{
+    let negated = pat.first() == Some(&'!');
+    let body    = &pat[negated as usize..];
+    let end     = body.iter().skip(1).position(|c| *c == ']')? + 1;
+
+    let Some(c) = c else { return Some((false, &body[end + 1..])) };
+    let mut hit = false;
+    let mut i   = 0;
+    while i < end
+    {
+        match body.get(i + 1) == Some(&'-') && i + 2 < end
+        {
+            true  => { hit |= (body[i]..=body[i + 2]).contains(c); i += 3; },
+            false => { hit |= body[i] == *c; i += 1; },
+        }
+    }
+
+    Some((hit != negated, &body[end + 1..]))
+}
+
+pub fn name_matches ( pattern : &str, name : &str ) -> bool
+{
+    let pattern = pattern.to_lowercase();
+    let name    = name.to_lowercase();
+
+    if pattern.contains(['*', '?'])
+    {
+        let p : Vec<char> = pattern.chars().collect();
+        let n : Vec<char> = name.chars().collect();
+        glob(&p, &n)
+    }
+    else
+    {
+        name.contains(&pattern)
+    }
+}
+
+pub fn name_selected ( include : &[String], exclude : &[String], name : &str ) -> bool
+{
+    (include.is_empty() || include.iter().any(|p| name_matches(p, name)))
+        && !exclude.iter().any(|p| name_matches(p, name))
+}
+
+/*
+ * --strip-suffix-glob, the shortest end of a name matching `pattern`
+ * (a glob as for --include-name, case-insensitive) is cut off along with
+ * the blanks before it; a name that would be left empty stays as it is.
+ * returns (id, old name, new name, suffix) of every name changed
+ */
+
+pub fn strip_suffixes ( oldact : &mut [OldActivity], pattern : &str )
+    -> Vec<(i32, String, String, String)>
+{
+    let pat : Vec<char> = pattern.to_lowercase().chars().collect();
+    let mut stripped = Vec::new();
+
+    for e in oldact.iter_mut()
+    {
+        let chars : Vec<char> = e.name.chars().collect();
+        let lower : Vec<char> = chars.iter().map(|c| c.to_lowercase().next().unwrap_or(*c)).collect();
+
+        let Some(i) = (1..chars.len()).rev().find(|i| glob(&pat, &lower[*i..])) else { continue };
+        let name : String = chars[..i].iter().collect::<String>().trim_end().to_string();
+        if name.trim().is_empty()
+        {
+            continue;
+        }
+
+        let suffix = chars[i..].iter().collect();
+        stripped.push((e.id, std::mem::replace(&mut e.name, name.clone()), name, suffix));
+    }
+
+    stripped
+}
+
+/*
+ * --activity: an exact (case-insensitive) name match wins, otherwise all
+ * activities the name matches as pattern are candidates
+ */
+
+pub fn activity_candidates<'a> ( oldact : &'a [OldActivity], name : &str ) -> Vec<&'a OldActivity>
+{
+    let exact : Vec<&OldActivity> = oldact.iter()
+        .filter(|e| e.name.to_lowercase() == name.to_lowercase())
+        .collect();
+
+    if !exact.is_empty()
+    {
+        return exact;
+    }
+
+    oldact.iter().filter(|e| name_matches(name, &e.name)).collect()
+}
+
+/*
+ * --invalid-date-policy, why a history row has no day to migrate it as:
+ * the year outside of `years` or year/month/day not being a day (what
+ * --trust string would make of the date text counts)
+ */
+
+pub fn date_problem ( e : &OldHistory, years : (i32, i32), trust : &Option<DateTrust> ) -> Option<String>
+{
+    let mut day = (e.year, e.month, e.day);
+    if let (Some(DateTrust::String), Ok(d)) = (trust, NaiveDate::parse_from_str(&e.date, "%Y-%m-%d"))
+    {
+        day = (d.year(), d.month() as i32, d.day() as i32);
+    }
+
+    let (year, month, d) = day;
+    if year < years.0 || year > years.1
+    {
+        return Some(format!("year {} outside of {}..={}", year, years.0, years.1));
+    }
+    match NaiveDate::from_ymd_opt(year, month.try_into().unwrap_or(0), d.try_into().unwrap_or(0))
+    {
+        Some(_) => None,
+        None    => Some(format!("{}-{:02}-{:02} isn't a day", year, month, d)),
+    }
+}
+
+/*
+ * the old history keeps the day twice, as `date` text and as numeric
+ * year/month/day; warn about rows where both disagree and, if asked to,
+ * make the trusted one win (weeknumber follows the numeric fields)
+ */
+
+pub fn check_dates ( oldhis : &mut [OldHistory], trust : &Option<DateTrust>, warnings : &Warnings )
+    -> Result<usize, MigrationError>
+{
+    let mut n_mismatch = 0;
+
+    for e in oldhis.iter_mut()
+    {
+        let parsed  = NaiveDate::parse_from_str(&e.date, "%Y-%m-%d").ok();
+        let numeric = NaiveDate::from_ymd_opt(
+            e.year, e.month.try_into().unwrap_or(0), e.day.try_into().unwrap_or(0));
+
+        if parsed.is_some() && parsed == numeric
+        {
+            continue;
+        }
+
+        n_mismatch += 1;
+        warnings.warn(&format!("history of activity {}: date {:?} vs numeric {}-{:02}-{:02}",
+                               e.id_activity, e.date, e.year, e.month, e.day))?;
+
+        match (trust, parsed, numeric)
+        {
+            (Some(DateTrust::Numeric), _, Some(d)) =>
+            {
+                e.date = d.format("%Y-%m-%d").to_string();
+                println!("         using numeric, date is now {:?}", e.date);
+            }
+            (Some(DateTrust::String), Some(d), _) =>
+            {
+                e.year       = d.year();
+                e.month      = d.month() as i32;
+                e.day        = d.day() as i32;
+                e.weeknumber = d.iso_week().week() as i32;
+                println!("         using string, numeric is now {}-{:02}-{:02}",
+                         e.year, e.month, e.day);
+            }
+            (Some(_), _, _) =>
+            {
+                println!("         trusted side isn't a valid date, left as is");
+            }
+            (None, _, _) => {}
+        }
+    }
+
+    Ok(n_mismatch)
+}
+
+/*
+ * an activity without a usable added date, and what fill_added() did
+ * about it: `after` is the day put in with the name of the fallback that
+ * gave it, `None` if none did
+ */
+
+#[derive(Debug)]
+pub struct AddedFill {
+    pub id     : i32,
+    pub name   : String,
+    pub before : String,
+    pub after  : Option<(String, &'static str)>,
+}
+
+/*
+ * activities whose `added` is empty or no readable date get the first
+ * fallback of `chain` that has a day for them (earliest-history only has
+ * one for activities with history); returns every one of them, those no
+ * fallback worked for are left as they are
+ */
+
+pub fn fill_added ( oldact     : &mut [OldActivity],
+                    first_days : &HashMap<i32, NaiveDate>,
+                    chain      : &[AddedFallback],
+                    today      : NaiveDate )
+    -> Vec<AddedFill>
+{
+    let mut changed = Vec::new();
+
+    for e in oldact.iter_mut().filter(|e| old::parse_datetime(&e.added_when).is_none())
+    {
+        let found = chain.iter().find_map(|f| {
+            let day = match f
+            {
+                AddedFallback::EarliestHistory => first_days.get(&e.id).copied(),
+                AddedFallback::Now             => Some(today),
+                AddedFallback::Epoch           => NaiveDate::from_ymd_opt(1970, 1, 1),
+            };
+            day.map(|d| (d.format("%Y-%m-%d").to_string(), f.name()))
+        });
+
+        let before = e.added_when.clone();
+        if let Some((after, _)) = &found
+        {
+            e.added_when = after.clone();
+        }
+        changed.push(AddedFill { id : e.id, name : e.name.clone(), before, after : found });
+    }
+
+    changed
+}
@@ -0,0 +1,181 @@
+use std::fs;
+
+use serde::Deserialize;
+
+use crate::error::MigrationError;
+use crate::old::OldActivity;
+use crate::old::OldHistory;
+use crate::round::round_to;
+use crate::round::RoundMode;
+
+/*
+ * --transform-config, a json file of transforms per field, each field's
+ * list applied in order to every value of it:
+ *
+ * {
+ *   "activities" : { "name"         : [ "trim", { "replace" : ["Uni", "University"] } ],
+ *                    "hours_total"  : [ { "multiply" : 0.5 } ] },
+ *   "history"    : { "hours_on_day" : [ { "multiply" : 0.5 }, { "at_most" : 24 } ] }
+ * }
+ *
+ * text:    "trim", "upper", "lower", { "replace" : [from, to] },
+ *          { "prefix" : s }, { "suffix" : s }
+ * numbers: { "multiply" : f }, { "add" : f }, { "at_least" : f },
+ *          { "at_most" : f }, { "round" : decimals }
+ *
+ * nothing but these, no expressions or code. totals of activities whose
+ * history got changed are recomputed from it afterwards (as after
+ * --compact-history), scaling the history is enough to scale them too
+ */
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum NumberOp {
+    Multiply(f64),
+    Add(f64),
+    AtLeast(f64),
+    AtMost(f64),
+    Round(i32),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum TextOp {
+    Trim,
+    Upper,
+    Lower,
+    Replace(String, String),
+    Prefix(String),
+    Suffix(String),
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ActivityFields {
+    name        : Vec<TextOp>,
+    hours_total : Vec<NumberOp>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct HistoryFields {
+    hours_on_day : Vec<NumberOp>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransformConfig {
+    activities : ActivityFields,
+    history    : HistoryFields,
+}
+
+fn number ( ops : &[NumberOp], val : f64, mode : RoundMode ) -> f64
+{
+    ops.iter().fold(val, |v, op| match *op
+    {
+        NumberOp::Multiply(f) => v * f,
+        NumberOp::Add(f)      => v + f,
+        NumberOp::AtLeast(f)  => v.max(f),
+        NumberOp::AtMost(f)   => v.min(f),
+        NumberOp::Round(d)    => round_to(v, d, mode),
+    })
+}
+
+fn text ( ops : &[TextOp], val : &str ) -> String
+{
+    ops.iter().fold(val.to_string(), |v, op| match op
+    {
+        TextOp::Trim              => v.trim().to_string(),
+        TextOp::Upper             => v.to_uppercase(),
+        TextOp::Lower             => v.to_lowercase(),
+        TextOp::Replace(from, to) => v.replace(from.as_str(), to),
+        TextOp::Prefix(s)         => format!("{}{}", s, v),
+        TextOp::Suffix(s)         => format!("{}{}", v, s),
+    })
+}
+
+impl TransformConfig
+{
+    pub fn read ( path : &str ) -> Result<TransformConfig, MigrationError>
+    {
+        let text = fs::read_to_string(path)
+            .map_err(|e| MigrationError::Io(format!("{:?}: {}", path, e)))?;
+        let config : TransformConfig = serde_json::from_str(&text)
+            .map_err(|e| MigrationError::Schema(format!("{:?}: {}", path, e)))?;
+
+        /*
+         * bounded to what still gives hours: finite factors and summands
+         * (a negative factor would flip the sign of every row), decimals
+         * the new db can hold
+         */
+
+        for op in config.activities.hours_total.iter().chain(&config.history.hours_on_day)
+        {
+            let bad = match *op
+            {
+                NumberOp::Multiply(f) => !f.is_finite() || f < 0.,
+                NumberOp::Add(f) | NumberOp::AtLeast(f) | NumberOp::AtMost(f) => !f.is_finite(),
+                NumberOp::Round(d)    => !(0..=9).contains(&d),
+            };
+            if bad
+            {
+                return Err(MigrationError::Schema(format!("{:?}: {:?} is out of range", path, op)));
+            }
+        }
+
+        Ok(config)
+    }
+
+    pub fn changes_history ( &self ) -> bool
+    {
+        !self.history.hours_on_day.is_empty()
+    }
+
+    /*
+     * returns (id, old name, new name) of every name changed, the ids of
+     * names that would have been left blank (they stay as they were) and
+     * the number of totals changed
+     */
+
+    pub fn activities ( &self, oldact : &mut [OldActivity], mode : RoundMode )
+        -> (Vec<(i32, String, String)>, Vec<i32>, usize)
+    {
+        let mut renamed = Vec::new();
+        let mut blank   = Vec::new();
+        let mut totals  = 0;
+
+        for e in oldact.iter_mut()
+        {
+            let name = text(&self.activities.name, &e.name);
+            if name.trim().is_empty() && !e.name.trim().is_empty()
+            {
+                blank.push(e.id);
+            }
+            else if name != e.name
+            {
+                renamed.push((e.id, std::mem::replace(&mut e.name, name.clone()), name));
+            }
+
+            let total = number(&self.activities.hours_total, e.hours_total, mode);
+            if total != e.hours_total
+            {
+                e.hours_total = total;
+                totals += 1;
+            }
+        }
+
+        (renamed, blank, totals)
+    }
+
+    /*
+     * true if the row's hours changed
+     */
+
+    pub fn history ( &self, e : &mut OldHistory, mode : RoundMode ) -> bool
+    {
+        let hours = number(&self.history.hours_on_day, e.hours_on_day, mode);
+        let changed = hours != e.hours_on_day;
+        e.hours_on_day = hours;
+        changed
+    }
+}
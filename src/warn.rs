@@ -0,0 +1,56 @@
+use std::cell::RefCell;
+
+use crate::error::MigrationError;
+
+/*
+ * every WARNING of a run goes through here, so they can be counted and,
+ * with --strict, the first one ends the run (as Aborted, the new db's
+ * transaction is rolled back by not committing it)
+ *
+ * they go to stderr, like errors, progress and results stay on stdout;
+ * --json lists them in the summary as well
+ *
+ * a shared reference is enough to warn, so it can be used from within
+ * the closures reading and writing rows
+ */
+
+pub struct Warnings {
+    strict   : bool,
+    messages : RefCell<Vec<String>>,
+}
+
+impl Warnings
+{
+    pub fn new ( strict : bool ) -> Warnings
+    {
+        Warnings { strict, messages : RefCell::new(Vec::new()) }
+    }
+
+    pub fn strict ( &self ) -> bool
+    {
+        self.strict
+    }
+
+    pub fn count ( &self ) -> usize
+    {
+        self.messages.borrow().len()
+    }
+
+    pub fn messages ( &self ) -> Vec<String>
+    {
+        self.messages.borrow().clone()
+    }
+
+    pub fn warn ( &self, msg : &str ) -> Result<(), MigrationError>
+    {
+        eprintln!("WARNING: {}", msg);
+        self.messages.borrow_mut().push(msg.to_string());
+
+        match self.strict
+        {
+            true  => Err(MigrationError::Aborted(
+                         "--strict is set, stopping at the warning above".to_string())),
+            false => Ok(()),
+        }
+    }
+}
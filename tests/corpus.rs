@@ -0,0 +1,988 @@
+use std::fs;
+use std::io::Write;
+use std::path;
+use std::process;
+
+use rusqlite::Connection;
+
+/*
+ * Migrates a corpus of small old dbs, each shaped like one of the tricky
+ * cases seen in real ones, with the built binary, and checks what ends up
+ * in the new db and what the run reports
+ *
+ * the old dbs are created here, in the classic layout of the archived
+ * C++ app or the one with a single datetime column; nothing binary is
+ * committed
+ */
+
+const BIN : &str = env!("CARGO_BIN_EXE_timetracker_transitiontool");
+
+type Activity<'a> = (i32, &'a str, &'a str, f64);	// id, name, added, hours total
+type History<'a>  = (i32, i32, i32, i32, i32, Option<f64>, &'a str);
+type Datetime<'a> = (i32, &'a str, f64);	// id, datetime, hours on day
+
+/*
+ * one fixture: an old db in its own folder, which also serves as HOME,
+ * so nothing outside of it gets touched
+ */
+
+struct Fixture {
+    dir : path::PathBuf,
+}
+
+impl Fixture
+{
+    fn new ( name : &str, act : &[Activity], his : &[History] ) -> Fixture
+    {
+        let (dir, db) = Fixture::create(name, act,
+            "CREATE TABLE history (id_activity INTEGER, year INTEGER, month INTEGER,
+                 day INTEGER, weeknumber INTEGER, hours_on_day REAL, date TEXT);");
+        for (id, year, month, day, week, hours, date) in his
+        {
+            db.execute("INSERT INTO history VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                       rusqlite::params![id, year, month, day, week, hours, date]).unwrap();
+        }
+
+        Fixture { dir }
+    }
+
+    fn datetime ( name : &str, act : &[Activity], his : &[Datetime] ) -> Fixture
+    {
+        let (dir, db) = Fixture::create(name, act,
+            "CREATE TABLE history (id_activity INTEGER, datetime TEXT, hours_on_day REAL);");
+        for (id, datetime, hours) in his
+        {
+            db.execute("INSERT INTO history VALUES (?1, ?2, ?3)",
+                       rusqlite::params![id, datetime, hours]).unwrap();
+        }
+
+        Fixture { dir }
+    }
+
+    fn create ( name : &str, act : &[Activity], history : &str ) -> (path::PathBuf, Connection)
+    {
+        let dir = std::env::temp_dir()
+            .join(format!("tt_corpus_{}_{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let db = Connection::open(dir.join("old.db")).unwrap();
+        db.execute_batch(
+            "CREATE TABLE activities (id INTEGER PRIMARY KEY, group_id INTEGER, name TEXT,
+                 added_when TEXT, is_activated INTEGER, hours_total REAL);").unwrap();
+        db.execute_batch(history).unwrap();
+        for (id, name, added, hours) in act
+        {
+            db.execute("INSERT INTO activities VALUES (?1, 1, ?2, ?3, 1, ?4)",
+                       rusqlite::params![id, name, added, hours]).unwrap();
+        }
+
+        (dir, db)
+    }
+
+    fn old_db ( &self ) -> String { self.dir.join("old.db").display().to_string() }
+    fn new_db ( &self ) -> String { self.dir.join("new.db").display().to_string() }
+
+    /*
+     * migrates into a fresh new db, returns the exit code and stdout
+     */
+
+    fn migrate ( &self, flags : &[&str] ) -> (i32, String)
+    {
+        let _ = fs::remove_file(self.new_db());
+        let (old, new) = (self.old_db(), self.new_db());
+        let mut args = vec!["--db-path", new.as_str(), "--seed-for-tests", "1"];
+        args.extend_from_slice(flags);
+        args.push(&old);
+        self.run(&args)
+    }
+
+    fn doctor ( &self ) -> (i32, String)
+    {
+        let old = self.old_db();
+        self.run(&["doctor", old.as_str()])
+    }
+
+    fn run ( &self, args : &[&str] ) -> (i32, String)
+    {
+        self.run_with_input(args, "")
+    }
+
+    /*
+     * with `input` typed at the prompts; stderr follows stdout
+     */
+
+    fn run_with_input ( &self, args : &[&str], input : &str ) -> (i32, String)
+    {
+        let (code, out, err) = self.output(args, input);
+        (code, out + &err)
+    }
+
+    fn output ( &self, args : &[&str], input : &str ) -> (i32, String, String)	// code, stdout, stderr
+    {
+        self.output_with_env(args, input, &[])
+    }
+
+    fn output_with_env ( &self, args : &[&str], input : &str, env : &[(&str, &str)] )
+        -> (i32, String, String)
+    {
+        let mut child = process::Command::new(BIN)
+            .args(args)
+            .env("HOME", &self.dir)
+            .env("XDG_CONFIG_HOME", self.dir.join(".config"))
+            .env_remove("TT_OLD_DB")
+            .envs(env.iter().copied())
+            .stdin(process::Stdio::piped())
+            .stdout(process::Stdio::piped())
+            .stderr(process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+
+        let out = child.wait_with_output().unwrap();
+        (out.status.code().unwrap_or(-1),
+         String::from_utf8_lossy(&out.stdout).into_owned(),
+         String::from_utf8_lossy(&out.stderr).into_owned())
+    }
+
+    fn activities ( &self ) -> Vec<(i32, String, f64)>
+    {
+        let db = Connection::open(self.new_db()).unwrap();
+        let mut stmt = db.prepare("SELECT id, name, hourstotal FROM tt_activities ORDER BY id").unwrap();
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap().map(Result::unwrap).collect()
+    }
+
+    fn history ( &self ) -> Vec<(i32, String, i32, i32, f64)>	// id, date, isoweek, isoweekyear, hours
+    {
+        let db = Connection::open(self.new_db()).unwrap();
+        let mut stmt = db.prepare("SELECT id, date, isoweek, isoweekyear, hoursonday
+                                   FROM tt_history ORDER BY rowid").unwrap();
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?)))
+            .unwrap().map(Result::unwrap).collect()
+    }
+}
+
+impl Drop for Fixture
+{
+    fn drop ( &mut self )
+    {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn doctor_count ( out : &str, problem : &str ) -> Option<usize>
+{
+    out.lines()
+        .find(|l| l.starts_with(problem))
+        .and_then(|l| l[problem.len()..].split_whitespace().next())
+        .and_then(|n| n.parse().ok())
+}
+
+#[test]
+fn orphaned_history ()
+{
+    let f = Fixture::new("orphaned_history",
+        &[(1, "Reading", "2020-01-01", 1.5)],
+        &[(1, 2024, 1, 1, 1, Some(1.5), "2024-01-01"),
+          (9, 2024, 3, 5, 10, Some(1.0), "2024-03-05")]);
+
+    let (_, out) = f.doctor();
+    assert_eq!(doctor_count(&out, "orphaned history"), Some(1), "{}", out);
+
+    // migrated as is, the new db doesn't enforce its foreign key
+    let (code, out) = f.migrate(&[]);
+    assert_eq!(code, 0, "{}", out);
+    assert_eq!(f.history().len(), 2);
+    assert_eq!(f.history()[1].0, 9);
+
+    let (code, out) = f.migrate(&["--check-foreign-keys"]);
+    assert_eq!(code, 4, "{}", out);
+    assert!(out.contains("Foreign key check: 1 violations"), "{}", out);
+}
+
+#[test]
+fn ghost_activities ()
+{
+    let f = Fixture::new("ghost_activities",
+        &[(1, "Reading", "2020-01-01", 1.5),
+          (2, "Ghost", "2020-01-01", 4.0)],
+        &[(1, 2024, 1, 1, 1, Some(1.5), "2024-01-01")]);
+
+    let (_, out) = f.doctor();
+    assert_eq!(doctor_count(&out, "ghost activities"), Some(1), "{}", out);
+
+    let (code, out) = f.migrate(&[]);
+    assert_eq!(code, 0, "{}", out);
+    assert_eq!(f.activities().len(), 2);
+    assert_eq!(f.activities()[1], (2, "Ghost".to_string(), 4.0));
+
+    let (code, out) = f.migrate(&["--prune-empty-activities"]);
+    assert_eq!(code, 0, "{}", out);
+    assert!(out.contains("Pruning 1 activities without history"), "{}", out);
+    assert_eq!(f.activities(), vec![(1, "Reading".to_string(), 1.5)]);
+}
+
+#[test]
+fn boundary_iso_weeks ()
+{
+    let f = Fixture::new("boundary_iso_weeks",
+        &[(1, "Reading", "2020-01-01", 4.0)],
+        &[(1, 2020, 12, 31, 53, Some(1.0), "2020-12-31"),
+          (1, 2021,  1,  3, 53, Some(1.0), "2021-01-03"),
+          (1, 2021,  1,  4,  1, Some(1.0), "2021-01-04"),
+          (1, 2024, 12, 30,  1, Some(1.0), "2024-12-30")]);
+
+    let (code, out) = f.migrate(&[]);
+    assert_eq!(code, 0, "{}", out);
+
+    let weeks : Vec<(String, i32, i32)> = f.history().into_iter()
+        .map(|(_, date, week, year, _)| (date, week, year))
+        .collect();
+    assert_eq!(weeks, vec![
+        ("2020-12-31".to_string(), 53, 2020),
+        ("2021-01-03".to_string(), 53, 2020),
+        ("2021-01-04".to_string(),  1, 2021),
+        ("2024-12-30".to_string(),  1, 2025),
+    ]);
+
+    let (code, out) = f.migrate(&["--json"]);
+    assert_eq!(code, 0, "{}", out);
+    assert!(out.contains("\"isoweekyear\": 2025"), "{}", out);
+
+    let csv = f.dir.join("weekly.csv").display().to_string();
+    let (code, out) = f.migrate(&["--export-weekly", &csv]);
+    assert_eq!(code, 0, "{}", out);
+    assert_eq!(fs::read_to_string(&csv).unwrap(),
+        "activity,name,isoweekyear,isoweek,hours,days\n\
+         1,Reading,2020,53,2,2\n\
+         1,Reading,2021,1,1,1\n\
+         1,Reading,2025,1,1,1\n");
+}
+
+#[test]
+fn converted_timezone ()
+{
+    /*
+     * written in +02:00, moved to -08:00: ten hours back, so 10:00 Monday
+     * is the first time to stay on its day, the Monday of week 1 of 2025
+     */
+    let f = Fixture::datetime("converted_timezone",
+        &[(1, "Reading", "2024-01-01", 4.0)],
+        &[(1, "2024-12-30 09:59:59", 1.0),
+          (1, "2024-12-30 10:00:00", 1.0),
+          (1, "2024-06-01T03:30",     1.0),
+          (1, "2024-06-02T07:59:59Z", 1.0)]);
+
+    let (code, out) = f.migrate(&["--convert-timezone", "--from-tz", "+02:00", "--to-tz", "-08:00"]);
+    assert_eq!(code, 0, "{}", out);
+    assert!(out.contains("(written in +02:00) in -08:00: 3 history rows move"), "{}", out);
+
+    let weeks : Vec<(String, i32, i32)> = f.history().into_iter()
+        .map(|(_, date, week, year, _)| (date, week, year))
+        .collect();
+    assert_eq!(weeks, vec![
+        ("2024-12-29".to_string(), 52, 2024),
+        ("2024-12-30".to_string(),  1, 2025),
+        ("2024-05-31".to_string(), 22, 2024),
+        ("2024-06-01".to_string(), 22, 2024),
+    ]);
+
+    let (code, out) = f.migrate(&["--convert-timezone", "--from-tz", "-08:00", "--to-tz", "+02:00"]);
+    assert_eq!(code, 0, "{}", out);
+    let days : Vec<String> = f.history().into_iter().map(|(_, date, _, _, _)| date).collect();
+    assert_eq!(days, ["2024-12-30", "2024-12-30", "2024-06-01", "2024-06-02"]);
+
+    let (code, _) = f.migrate(&["--convert-timezone", "--from-tz", "+02:00"]);
+    assert_eq!(code, 1);
+}
+
+#[test]
+fn invalid_dates ()
+{
+    let f = Fixture::new("invalid_dates",
+        &[(1, "Reading", "2020-01-01", 3.0)],
+        &[(1, 2024,    1,  1, 1, Some(1.0), "2024-01-01"),
+          (1, 999999,  1,  1, 1, Some(1.0), "999999-01-01"),
+          (1, 2024,    2, 30, 9, Some(1.0), "2024-02-30")]);
+
+    let (code, out) = f.migrate(&[]);
+    assert_eq!(code, 2, "{}", out);
+    assert!(out.contains("\"999999-01-01\"  year 999999 outside of 1900..=2200"), "{}", out);
+    assert!(out.contains("\"2024-02-30\"  2024-02-30 isn't a day"), "{}", out);
+    assert!(!path::Path::new(&f.new_db()).exists());
+
+    for streamed in [&[][..], &["--max-memory", "0"][..], &["--max-memory", "18446744073709551615"][..]]
+    {
+        let mut flags = vec!["--invalid-date-policy", "skip"];
+        flags.extend_from_slice(streamed);
+        let (code, out) = f.migrate(&flags);
+        assert_eq!(code, 0, "{}", out);
+        let days : Vec<String> = f.history().into_iter().map(|(_, date, _, _, _)| date).collect();
+        assert_eq!(days, ["2024-01-01"]);
+    }
+
+    let (code, _) = f.migrate(&["--year-range", "2200..1900"]);
+    assert_eq!(code, 1);
+}
+
+#[test]
+fn null_hours ()
+{
+    let f = Fixture::new("null_hours",
+        &[(1, "Reading", "2020-01-01", 1.5)],
+        &[(1, 2024, 1, 1, 1, Some(1.5), "2024-01-01"),
+          (1, 2024, 1, 2, 1, None, "2024-01-02")]);
+
+    let (_, out) = f.doctor();
+    assert_eq!(doctor_count(&out, "null hours"), Some(1), "{}", out);
+
+    // refused as a schema mismatch before anything is written
+    let (code, out) = f.migrate(&[]);
+    assert_eq!(code, 2, "{}", out);
+    assert!(out.contains("schema mismatch"), "{}", out);
+    assert!(!path::Path::new(&f.new_db()).exists());
+}
+
+#[test]
+fn added_fallback ()
+{
+    let f = Fixture::new("added_fallback",
+        &[(1, "Reading", "someday", 1.5),
+          (2, "Coding", "", 0.0)],
+        &[(1, 2024, 1, 3, 1, Some(1.5), "2024-01-03")]);
+    Connection::open(f.old_db()).unwrap()
+        .execute("UPDATE activities SET added_when = NULL WHERE id = 2", []).unwrap();
+
+    let (code, out) = f.migrate(&["--added-fallback", "earliest-history,epoch"]);
+    assert_eq!(code, 0, "{}", out);
+    assert!(out.contains("Added date fallback: 2 activities"), "{}", out);
+
+    let db = Connection::open(f.new_db()).unwrap();
+    let mut stmt = db.prepare("SELECT id, added FROM tt_activities ORDER BY id").unwrap();
+    let added : Vec<(i64, String)> = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap().map(Result::unwrap).collect();
+    assert_eq!(added, [(1, "2024-01-03".to_string()), (2, "1970-01-01".to_string())]);
+}
+
+#[test]
+fn made_fixture ()
+{
+    let days : Vec<chrono::NaiveDate> = chrono::NaiveDate::from_ymd_opt(2023, 2, 1).unwrap()
+        .iter_days().take(300).collect();
+    let dates : Vec<String> = days.iter().map(|d| d.to_string()).collect();
+    let mut his : Vec<History> = days.iter().zip(&dates)
+        .map(|(d, date)| {
+            use chrono::Datelike;
+            (1, d.year(), d.month() as i32, d.day() as i32, d.iso_week().week() as i32, Some(1.0), date.as_str())
+        })
+        .collect();
+    his[150].5 = None;
+    his.push((1, 2023, 12, 31, 52, Some(2.0), "2023-12-31"));
+    his.push((7, 2023, 12, 1, 48, Some(2.0), "2023-12-01"));
+
+    let f = Fixture::new("made_fixture",
+        &[(1, "Reading", "2023-01-01", 302.0), (2, "Secret plans", "2023-01-01", 0.0)], &his);
+
+    let fixture = f.dir.join("fixture.db").display().to_string();
+    let (code, out) = f.run(&["--make-fixture", &fixture, &f.old_db()]);
+    assert_eq!(code, 0, "{}", out);
+    assert!(out.contains("2 activities (names scrubbed) and 200 of 302 history rows"), "{}", out);
+
+    let schema = |p : &str| -> Vec<String> {
+        let db = Connection::open(p).unwrap();
+        let mut stmt = db.prepare("SELECT sql FROM sqlite_master ORDER BY name").unwrap();
+        stmt.query_map([], |row| row.get(0)).unwrap().map(Result::unwrap).collect()
+    };
+    assert_eq!(schema(&fixture), schema(&f.old_db()));
+
+    let db = Connection::open(&fixture).unwrap();
+    let names : Vec<String> = db.prepare("SELECT name FROM activities ORDER BY id").unwrap()
+        .query_map([], |row| row.get(0)).unwrap().map(Result::unwrap).collect();
+    assert_eq!(names, ["xxxxxxx", "xxxxxx xxxxx"]);
+    let kept = |cond : &str| -> i64 {
+        db.query_row(&format!("SELECT count(*) FROM history WHERE {}", cond), [], |row| row.get(0)).unwrap()
+    };
+    assert_eq!(kept("hours_on_day IS NULL"), 1);
+    assert_eq!(kept("date IN ('2023-02-01', '2023-11-27', '2023-12-31')"), 3);
+    assert_eq!(kept("id_activity = 7"), 1);
+
+    // the fixture fails the way the old db does
+    let new = f.new_db();
+    let (code, out) = f.run(&["--db-path", &new, &fixture]);
+    assert_eq!(code, 2, "{}", out);
+
+    let (code, _) = f.run(&["--make-fixture", &fixture, &f.old_db()]);
+    assert_eq!(code, 5);
+}
+
+#[test]
+fn loosely_typed ()
+{
+    let f = Fixture::new("loosely_typed", &[(1, "Reading", "2020-01-01", 3.0)], &[]);
+    Connection::open(f.old_db()).unwrap().execute_batch(
+        "DROP TABLE history;
+         CREATE TABLE history (id_activity, year, month, day, weeknumber, hours_on_day, date);
+         INSERT INTO history VALUES (1, '2024', 1, 1, 1.0, ' 1.5', '2024-01-01'),
+                                    (1, 2024, 1, 2, 1, 1.5, '2024-01-02');").unwrap();
+
+    let (code, out) = f.migrate(&[]);
+    assert_eq!(code, 0, "{}", out);
+    assert!(out.contains("WARNING: history.hours_on_day holds 1 values of type text instead of real"), "{}", out);
+    assert!(out.contains("3 warnings, see above"), "{}", out);
+
+    // warnings on stderr only, and in the json summary
+    let (old, new) = (f.old_db(), f.new_db());
+    let _ = fs::remove_file(&new);
+    let (code, out, err) = f.output(&["--db-path", &new, "--seed-for-tests", "1", "--json", &old], "");
+    assert_eq!(code, 0, "{}{}", out, err);
+    assert!(!out.contains("WARNING"), "{}", out);
+    assert_eq!(err.lines().filter(|l| l.starts_with("WARNING: ")).count(), 3, "{}", err);
+    let json = &out[out.find("{\n").unwrap()..out.rfind("\n}").unwrap() + 2];
+    let summary : serde_json::Value = serde_json::from_str(json).unwrap();
+    assert_eq!(summary["warnings"].as_array().map(Vec::len), Some(3), "{}", out);
+    let hours : Vec<f64> = f.history().into_iter().map(|(_, _, _, _, h)| h).collect();
+    assert_eq!(hours, [1.5, 1.5]);
+
+    let _ = fs::remove_file(&new);
+    let (code, out, err) = f.output(&["--db-path", &new, "--seed-for-tests", "1", "--strict-types", &old], "");
+    assert_eq!(code, 2, "{}{}", out, err);
+    assert!(err.contains("history.year holds 1 values of type text instead of integer (e.g. '2024')"), "{}", err);
+    assert!(!path::Path::new(&f.new_db()).exists());
+}
+
+#[test]
+fn duplicate_rows ()
+{
+    let f = Fixture::new("duplicate_rows",
+        &[(1, "Reading", "2020-01-01", 3.0)],
+        &[(1, 2024, 1, 1, 1, Some(1.5), "2024-01-01"),
+          (1, 2024, 1, 1, 1, Some(1.5), "2024-01-01")]);
+
+    let (_, out) = f.doctor();
+    assert_eq!(doctor_count(&out, "duplicate rows"), Some(1), "{}", out);
+
+    let (code, out) = f.migrate(&[]);
+    assert_eq!(code, 0, "{}", out);
+    assert_eq!(f.history().len(), 2);
+
+    let (code, out) = f.migrate(&["--dedup-history"]);
+    assert_eq!(code, 0, "{}", out);
+    assert!(out.contains("Dedup: 1 history rows skipped"), "{}", out);
+    assert_eq!(f.history().len(), 1);
+    assert_eq!(f.activities()[0].2, 1.5);
+
+    let (code, out) = f.migrate(&["--compact-history"]);
+    assert_eq!(code, 0, "{}", out);
+    assert_eq!(f.history().len(), 1);
+    assert_eq!(f.history()[0].4, 3.0);
+    assert_eq!(f.activities()[0].2, 3.0);
+
+    let old = f.old_db();
+    let (code, out) = f.run(&["--report-source-dups", "--format", "csv", &old]);
+    assert_eq!(code, 0, "{}", out);
+    assert!(out.contains("1,Reading,2024-01-01,2,1.5 + 1.5,3"), "{}", out);
+
+    let (code, out) = f.migrate(&["--merge-source-dups"]);
+    assert_eq!(code, 0, "{}", out);
+    assert!(out.contains("2024-01-01  1.5 + 1.5 -> 3 hours"), "{}", out);
+    assert_eq!(f.history().len(), 1);
+    assert_eq!(f.history()[0].4, 3.0);
+}
+
+#[test]
+fn duplicate_names ()
+{
+    let f = Fixture::new("duplicate_names",
+        &[(1, "Reading", "2020-01-01", 1.0), (2, " reading ", "2020-01-01", 2.0),
+          (3, "Coding", "2020-01-01", 3.0), (4, "Reading (2)", "2020-01-01", 0.0)],
+        &[(1, 2024, 1, 1, 1, Some(1.0), "2024-01-01"),
+          (2, 2024, 1, 2, 1, Some(2.0), "2024-01-02"),
+          (3, 2024, 1, 3, 1, Some(3.0), "2024-01-03")]);
+
+    // nothing merges unless asked to
+    let (code, out) = f.migrate(&["--merge-by-name"]);
+    assert_eq!(code, 3, "{}", out);
+    assert!(out.contains("\"reading\"  1 (Reading), 2 ( reading )"), "{}", out);
+    assert!(!path::Path::new(&f.new_db()).exists());
+
+    let (code, out) = f.migrate(&["--merge-by-name", "--on-duplicate-name", "merge"]);
+    assert_eq!(code, 0, "{}", out);
+    assert_eq!(f.activities(), [(1, "Reading".to_string(), 3.0), (3, "Coding".to_string(), 3.0),
+                                (4, "Reading (2)".to_string(), 0.0)]);
+    let ids : Vec<i32> = f.history().into_iter().map(|(id, _, _, _, _)| id).collect();
+    assert_eq!(ids, [1, 1, 3]);
+
+    let (code, out) = f.migrate(&["--merge-by-name", "--on-duplicate-name", "suffix"]);
+    assert_eq!(code, 0, "{}", out);
+    let names : Vec<String> = f.activities().into_iter().map(|(_, name, _)| name).collect();
+    assert_eq!(names, ["Reading", "reading (3)", "Coding", "Reading (2)"]);
+
+    let (code, _) = f.migrate(&["--on-duplicate-name", "merge"]);
+    assert_eq!(code, 1);
+
+    // a stripped suffix leaves "Reading" three times
+    let (code, out) = f.migrate(&["--strip-suffix-glob", "[([]*[])]", "--merge-by-name",
+                                  "--on-duplicate-name", "merge"]);
+    assert_eq!(code, 0, "{}", out);
+    assert!(out.contains("\"Reading (2)\" -> \"Reading\""), "{}", out);
+    assert_eq!(f.activities(), [(1, "Reading".to_string(), 3.0), (3, "Coding".to_string(), 3.0)]);
+
+    // --dedup-activities-by-name lists them, or merges them on its own
+    let (code, out) = f.migrate(&["--dedup-activities-by-name", "report", "--format", "csv"]);
+    assert_eq!(code, 0, "{}", out);
+    assert!(out.contains("reading,2, reading ,2.0,1,1"), "{}", out);
+    assert!(!path::Path::new(&f.new_db()).exists());
+
+    let (code, out) = f.migrate(&["--dedup-activities-by-name", "merge"]);
+    assert_eq!(code, 0, "{}", out);
+    assert!(out.contains("\"reading\"  merged into 1"), "{}", out);
+    assert_eq!(f.activities().len(), 3);
+}
+
+#[test]
+fn non_ascii_names ()
+{
+    let names = ["Lesen über Bücher", "日本語の勉強", "🏃 running"];
+    let f = Fixture::new("non_ascii_names",
+        &[(1, names[0], "2020-01-01", 1.5),
+          (2, names[1], "2020-01-01", 0.0),
+          (3, names[2], "2020-01-01", 0.0)],
+        &[(1, 2024, 1, 1, 1, Some(1.5), "2024-01-01")]);
+
+    let (_, out) = f.run(&["--name-encoding-report", &f.old_db()]);
+    assert!(!out.contains("invalid UTF-8"), "{}", out);
+
+    let (code, out) = f.migrate(&[]);
+    assert_eq!(code, 0, "{}", out);
+    let migrated : Vec<String> = f.activities().into_iter().map(|(_, name, _)| name).collect();
+    assert_eq!(migrated, names);
+
+    // cut by characters, not bytes
+    let (code, out) = f.migrate(&["--max-name-length", "5", "--truncate-names"]);
+    assert_eq!(code, 0, "{}", out);
+    assert_eq!(f.activities()[1].1.chars().count(), 5);
+}
+
+#[test]
+fn already_migrated_source ()
+{
+    let f = Fixture::new("already_migrated_source",
+        &[(1, "Reading", "2020-01-01", 1.5)],
+        &[(1, 2024, 1, 1, 1, Some(1.5), "2024-01-01")]);
+
+    let (code, out) = f.migrate(&[]);
+    assert_eq!(code, 0, "{}", out);
+
+    // the new db given as the old one
+    let (code, _, err) = f.output(&["--db-path", &f.dir.join("again.db").display().to_string(),
+                                    &f.new_db()], "");
+    assert_eq!(code, 2, "{}", err);
+    assert!(err.contains("this looks like an already-migrated database"), "{}", err);
+}
+
+#[test]
+fn foreign_key_violation ()
+{
+    let f = Fixture::new("foreign_key_violation",
+        &[(1, "Reading", "2020-01-01", 1.5)],
+        &[(1, 2024, 1, 1, 1, Some(1.5), "2024-01-01")]);
+
+    let (code, out) = f.migrate(&[]);
+    assert_eq!(code, 0, "{}", out);
+
+    // a row of an activity the new db doesn't have, slipped in past the check
+    let db = Connection::open(f.new_db()).unwrap();
+    db.execute_batch("PRAGMA foreign_keys=OFF;
+                      INSERT INTO tt_history (id, year, month, day, isoweek, isoweekyear, hoursonday, date)
+                      VALUES (99, 2024, 1, 2, 1, 2024, 1.0, '2024-01-02');").unwrap();
+    drop(db);
+
+    let (old, new) = (f.old_db(), f.new_db());
+    let (code, out) = f.run(&["--db-path", &new, "--prefer-existing", "--check-foreign-keys", &old]);
+    assert_eq!(code, 4, "{}", out);
+    assert!(out.contains("Foreign key check: 1 violations"), "{}", out);
+    assert!(out.contains("tt_history rowid 2 references a missing row of tt_activities"), "{}", out);
+
+    let (code, out) = f.run(&["--db-path", &new, "--prefer-existing", "--check-foreign-keys", "--force", &old]);
+    assert_eq!(code, 0, "{}", out);
+    assert!(out.contains("Foreign key check: 1 violations"), "{}", out);
+    assert!(out.contains("--force is set, continuing anyway"), "{}", out);
+}
+
+#[test]
+fn atomic_file ()
+{
+    let f = Fixture::new("atomic_file",
+        &[(1, "Reading", "2020-01-01", 1.5)],
+        &[(1, 2024, 1, 1, 1, Some(1.5), "2024-01-01")]);
+
+    let (code, out) = f.migrate(&["--atomic-file", "--verify-checksums"]);
+    assert_eq!(code, 0, "{}", out);
+    assert_eq!(f.activities().len(), 1);
+
+    // migrating the same activity again fails, and leaves the db as it was
+    let before = fs::read(f.new_db()).unwrap();
+    let (old, new) = (f.old_db(), f.new_db());
+    let (code, out) = f.run(&["--db-path", &new, "--atomic-file", &old]);
+    assert_eq!(code, 4, "{}", out);
+    assert_eq!(fs::read(f.new_db()).unwrap(), before);
+
+    let temps : Vec<String> = fs::read_dir(&f.dir).unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|n| n.contains(".tmp-"))
+        .collect();
+    assert!(temps.is_empty(), "{:?}", temps);
+
+    // rows only in the -wal of the new db survive the rename
+    let db = Connection::open(f.new_db()).unwrap();
+    db.pragma_update(None, "journal_mode", "wal").unwrap();
+    db.pragma_update(None, "wal_autocheckpoint", 0).unwrap();
+    db.execute("INSERT INTO tt_activities (id, name, added) VALUES (50, 'Chess', '2020-01-01')", []).unwrap();
+    assert!(path::Path::new(&format!("{}-wal", new)).exists());
+
+    let (code, out) = f.run(&["--db-path", &new, "--atomic-file", "--prefer-existing", &old]);
+    assert_eq!(code, 0, "{}", out);
+    drop(db);
+    assert_eq!(f.activities().len(), 2);
+}
+
+#[test]
+fn out_key ()
+{
+    let f = Fixture::new("out_key",
+        &[(1, "Reading", "2020-01-01", 1.5)],
+        &[(1, 2024, 1, 1, 1, Some(1.5), "2024-01-01")]);
+
+    let (code, out) = f.migrate(&["--out-key", "s3cret", "--verify-checksums", "--atomic-file"]);
+    if !cfg!(feature = "sqlcipher")
+    {
+        assert_eq!(code, 1, "{}", out);
+        assert!(out.contains("--out-key needs a build with sqlcipher"), "{}", out);
+        return;
+    }
+    assert_eq!(code, 0, "{}", out);
+
+    // unreadable without the key, and a wrong one is refused
+    let db = Connection::open(f.new_db()).unwrap();
+    assert!(db.query_row("SELECT count(*) FROM tt_activities", [], |row| row.get::<_, i64>(0)).is_err());
+    db.pragma_update(None, "key", "s3cret").unwrap();
+    assert_eq!(db.query_row("SELECT count(*) FROM tt_history", [], |row| row.get::<_, i64>(0)).unwrap(), 1);
+
+    let (old, new) = (f.old_db(), f.new_db());
+    let (code, out) = f.run(&["--db-path", &new, "--out-key", "wrong", &old]);
+    assert_eq!(code, 5, "{}", out);
+}
+
+#[test]
+fn bench ()
+{
+    let f = Fixture::new("bench",
+        &[(1, "Reading", "2020-01-01", 1.5)],
+        &[(1, 2024, 1, 1, 1, Some(1.5), "2024-01-01")]);
+
+    let old = f.old_db();
+    let (code, out) = f.run(&["--bench", "--verify-checksums", "--format", "csv", &old]);
+    assert_eq!(code, 0, "{}", out);
+    for phase in ["read", "transform", "insert", "verify", "total"]
+    {
+        assert!(out.lines().any(|l| l.starts_with(&format!("{},", phase))), "{}", out);
+    }
+    assert!(out.contains("Insert: 1 history rows in"), "{}", out);
+
+    // nothing but the old db in the folder
+    let files : Vec<String> = fs::read_dir(&f.dir).unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|n| !n.starts_with('.'))
+        .collect();
+    assert_eq!(files, ["old.db"]);
+}
+
+#[test]
+fn transform_config ()
+{
+    let f = Fixture::new("transform_config",
+        &[(1, " Reading", "2020-01-01", 3.0), (2, "Coding", "2020-01-01", 0.0)],
+        &[(1, 2024, 1, 1, 1, Some(1.0), "2024-01-01"),
+          (1, 2024, 1, 2, 1, Some(2.0), "2024-01-02")]);
+
+    let config = f.dir.join("transforms.json").display().to_string();
+    fs::write(&config, r#"{ "activities" : { "name" : [ "trim", "upper" ] },
+                           "history"    : { "hours_on_day" : [ { "multiply" : 1.5 }, { "at_most" : 2 } ] } }"#).unwrap();
+
+    let (code, out) = f.migrate(&["--transform-config", &config]);
+    assert_eq!(code, 0, "{}", out);
+    assert!(out.contains("Transform config: hours of 1 history rows changed"), "{}", out);
+    assert_eq!(f.activities(), [(1, "READING".to_string(), 3.5), (2, "CODING".to_string(), 0.0)]);
+    let hours : Vec<f64> = f.history().into_iter().map(|(_, _, _, _, h)| h).collect();
+    assert_eq!(hours, [1.5, 2.0]);
+
+    // nothing beyond the listed transforms
+    fs::write(&config, r#"{ "history" : { "hours_on_day" : [ { "multiply" : -1 } ] } }"#).unwrap();
+    let (code, _) = f.migrate(&["--transform-config", &config]);
+    assert_eq!(code, 2);
+    fs::write(&config, r#"{ "history" : { "date" : [ "upper" ] } }"#).unwrap();
+    let (code, _) = f.migrate(&["--transform-config", &config]);
+    assert_eq!(code, 2);
+    assert!(!path::Path::new(&f.new_db()).exists());
+}
+
+#[test]
+fn schema_only ()
+{
+    let f = Fixture::new("schema_only", &[(1, "Reading", "2020-01-01", 1.5)], &[]);
+
+    let script = f.dir.join("schema.sql").display().to_string();
+    let (code, out) = f.run(&["--output-sql-schema-only", &script, "--dedup-history", "--store-minutes"]);
+    assert_eq!(code, 0, "{}", out);
+    assert!(out.contains("Schema: 3 statements written"), "{}", out);
+    assert!(!path::Path::new(&f.new_db()).exists());
+
+    // the same tables a migrated db gets, empty
+    let db = Connection::open_in_memory().unwrap();
+    db.execute_batch(&fs::read_to_string(&script).unwrap()).unwrap();
+    db.execute_batch("INSERT INTO tt_activities (id, name, added) VALUES (1, 'Reading', '2020-01-01');
+                      INSERT INTO tt_history (id, year, month, day, isoweek, isoweekyear, date, minutesonday)
+                      VALUES (1, 2024, 1, 1, 1, 2024, '2024-01-01', 90)").unwrap();
+    assert!(db.execute_batch("INSERT INTO tt_history (id, year, month, day, isoweek, isoweekyear, date)
+                              VALUES (1, 2024, 1, 1, 1, 2024, '2024-01-01')").is_err());
+
+    // --validate-new-against-app takes the schema as sql or as a db
+    let (code, out) = f.run(&["--validate-new-against-app", &script, "--dedup-history", "--store-minutes"]);
+    assert_eq!(code, 0, "{}", out);
+    assert!(out.contains("App schema: the tables written match"), "{}", out);
+
+    let app_db = f.dir.join("app.db").display().to_string();
+    db.execute("VACUUM INTO ?1", [&app_db]).unwrap();
+    let (code, out) = f.run(&["--validate-new-against-app", &app_db, "--format", "csv"]);
+    assert_eq!(code, 2, "{}", out);
+    assert!(out.contains("App schema: 2 differences"), "{}", out);
+    assert!(out.contains("tt_history,minutesonday,-,INTEGER,"), "{}", out);
+}
+
+#[test]
+fn print_config ()
+{
+    let f = Fixture::new("print_config", &[], &[]);
+
+    // the old db doesn't have to exist
+    let (code, out) = f.run(&["--print-config", "--watch", "--abort-on-warning", "--format", "csv",
+                              "/nowhere/old.db"]);
+    assert_eq!(code, 0, "{}", out);
+    let new = f.dir.join(".config").join("timetracker").join("productivity.db");
+    assert!(out.contains("old db,/nowhere/old.db,argument"), "{}", out);
+    assert!(out.contains(&format!("new db,{},env (XDG_CONFIG_HOME)", new.display())), "{}", out);
+    assert!(out.contains("--strict,true,flag (--abort-on-warning)"), "{}", out);
+    assert!(out.contains("--since-last-run,true,implied"), "{}", out);
+    assert!(out.contains("--max-memory,256,default"), "{}", out);
+    assert!(!new.exists());
+}
+
+#[test]
+fn config_dir_is_a_file ()
+{
+    let f = Fixture::new("config_dir_is_a_file",
+        &[(1, "Reading", "2020-01-01", 1.5)],
+        &[(1, 2024, 1, 1, 1, Some(1.5), "2024-01-01")]);
+
+    let config = f.dir.join(".config");
+    fs::write(&config, "not a folder").unwrap();
+
+    let old = f.old_db();
+    let (code, out) = f.run(&[&old]);
+    assert_eq!(code, 5, "{}", out);
+    assert!(out.contains(&format!("{:?} is in the way of the new db's folder", config)), "{}", out);
+
+    // the same for --db-path below a file
+    let below = config.join("new.db").display().to_string();
+    let (code, out) = f.run(&["--db-path", &below, &old]);
+    assert_eq!(code, 5, "{}", out);
+    assert!(out.contains("it's a file and not a folder"), "{}", out);
+}
+
+#[test]
+fn activity_checksums ()
+{
+    let f = Fixture::new("activity_checksums",
+        &[(1, "Reading", "2020-01-01", 3.0), (2, "Coding", "2020-01-01", 1.0)],
+        &[(1, 2024, 1, 1, 1, Some(1.0), "2024-01-01"),
+          (1, 2024, 1, 2, 1, Some(2.0), "2024-01-02"),
+          (2, 2024, 1, 1, 1, Some(1.0), "2024-01-01")]);
+
+    let (old, new) = (f.old_db(), f.new_db());
+    let incremental = ["--db-path", &new, "--since-last-run", "--activity-checksums", &old];
+    let (code, out) = f.run(&incremental);
+    assert_eq!(code, 0, "{}", out);
+
+    // an old day edited, which the day of the last run alone would miss
+    Connection::open(f.old_db()).unwrap().execute_batch(
+        "UPDATE history SET hours_on_day = 2.5 WHERE id_activity = 1 AND day = 1;
+         UPDATE activities SET hours_total = 4.5 WHERE id = 1;").unwrap();
+
+    let (code, out) = f.run(&incremental);
+    assert_eq!(code, 0, "{}", out);
+    assert!(out.contains("Activity checksums: 1 activities changed since the last run"), "{}", out);
+    assert!(out.contains("Unchanged since the last run, skipping: 1 activities, 1 history rows"), "{}", out);
+    let hours : Vec<f64> = f.history().into_iter().map(|(_, _, _, _, h)| h).collect();
+    assert_eq!(hours, [2.5, 2.0, 1.0]);
+
+    let (code, out) = f.run(&incremental);
+    assert_eq!(code, 0, "{}", out);
+    assert!(out.contains("Activity checksums: 0 activities changed"), "{}", out);
+
+    let (code, _) = f.migrate(&["--activity-checksums"]);
+    assert_eq!(code, 1);
+}
+
+#[test]
+fn zero_fill_gaps ()
+{
+    let f = Fixture::new("zero_fill_gaps",
+        &[(1, "Reading", "2020-01-01", 3.0), (2, "Coding", "2020-01-01", 1.0)],
+        &[(1, 2020, 12, 31, 53, Some(1.0), "2020-12-31"),
+          (2, 2021,  1,  2, 53, Some(1.0), "2021-01-02"),
+          (1, 2021,  1,  4,  1, Some(2.0), "2021-01-04")]);
+
+    let (code, out) = f.migrate(&["--zero-fill-gaps"]);
+    assert_eq!(code, 0, "{}", out);
+    assert!(out.contains("Zero-filling gaps: 3 days without history of 1 activities"), "{}", out);
+    assert_eq!(&f.history()[3..], [(1, "2021-01-01".to_string(), 53, 2020, 0.0),
+                                   (1, "2021-01-02".to_string(), 53, 2020, 0.0),
+                                   (1, "2021-01-03".to_string(), 53, 2020, 0.0)]);
+    assert_eq!(f.activities()[0].2, 3.0);
+}
+
+#[test]
+fn split_old_db ()
+{
+    let f = Fixture::new("split_old_db",
+        &[(1, "Reading", "2020-01-01", 3.0)],
+        &[(1, 2020, 1, 2, 1, Some(1.0), "2020-01-02"),
+          (1, 2020, 1, 3, 1, Some(2.0), "2020-01-03")]);
+
+    let history_db = f.dir.join("history.db").display().to_string();
+    {
+        let old = Connection::open(f.old_db()).unwrap();
+        let sql : String = old.query_row(
+            "SELECT sql FROM sqlite_master WHERE name = 'history'", [], |row| row.get(0)).unwrap();
+        Connection::open(&history_db).unwrap().execute_batch(&sql).unwrap();
+        old.execute("ATTACH DATABASE ?1 AS h", [&history_db]).unwrap();
+        old.execute_batch("INSERT INTO h.history SELECT * FROM main.history;
+                           DROP TABLE main.history;").unwrap();
+    }
+
+    let (code, out) = f.run(&["--old-activities-db", &f.old_db(), "--old-history-db", &history_db,
+                              "--db-path", &f.new_db()]);
+    assert_eq!(code, 0, "{}", out);
+    assert_eq!(f.history().len(), 2);
+    assert_eq!(f.activities()[0].2, 3.0);
+
+    let (code, _) = f.run(&["--old-activities-db", &f.old_db(), "--db-path", &f.new_db()]);
+    assert_eq!(code, 1);
+    let (code, out) = f.run(&["--old-activities-db", &history_db, "--old-history-db", &history_db,
+                              "--db-path", &f.new_db()]);
+    assert_eq!(code, 2, "{}", out);
+}
+
+#[test]
+fn copy_local ()
+{
+    let f = Fixture::new("copy_local",
+        &[(1, "Reading", "2020-01-01", 1.5)],
+        &[(1, 2024, 1, 1, 1, Some(1.5), "2024-01-01")]);
+
+    // a uri is copied from the file it names
+    let uri = format!("file:{}?mode=ro", f.old_db());
+    let (code, out) = f.run(&["--db-path", &f.new_db(), "--copy-local", &uri]);
+    assert_eq!(code, 0, "{}", out);
+    assert_eq!(f.history().len(), 1);
+
+    let copy = out.lines()
+        .find_map(|l| l.strip_prefix("Reading from a local copy: "))
+        .unwrap_or_else(|| panic!("{}", out));
+    assert!(!path::Path::new(copy.trim_matches('"')).exists());
+}
+
+#[test]
+fn only_recompute ()
+{
+    // totals that aren't even numbers
+    let f = Fixture::new("only_recompute",
+        &[(1, "Reading", "2020-01-01", 0.0), (2, "Coding", "2020-01-01", 0.0)],
+        &[(1, 2024, 1, 1, 1, Some(1.5), "2024-01-01"),
+          (1, 2024, 1, 2, 1, Some(2.0), "2024-01-02")]);
+    Connection::open(f.old_db()).unwrap()
+        .execute_batch("UPDATE activities SET hours_total = 'garbage'").unwrap();
+
+    let (code, out) = f.migrate(&["--only-recompute"]);
+    assert_eq!(code, 0, "{}", out);
+    assert!(out.contains("Totals from history: 2 activities"), "{}", out);
+    assert_eq!(f.activities(), [(1, "Reading".to_string(), 3.5), (2, "Coding".to_string(), 0.0)]);
+
+    let (code, _) = f.migrate(&["--only-recompute", "--verify-checksums"]);
+    assert_eq!(code, 1);
+}
+
+#[test]
+fn emitted_sql_script ()
+{
+    let f = Fixture::new("emitted_sql_script",
+        &[(1, "O'Brien's \"?1\" notes", "2020-01-01", 1.5),
+          (2, "Straße; DROP TABLE", "2020-01-01", 0.0)],
+        &[(1, 2024, 1, 1, 1, Some(1.5), "2024-01-01")]);
+
+    let (code, out) = f.migrate(&[]);
+    assert_eq!(code, 0, "{}", out);
+    let (act, his) = (f.activities(), f.history());
+
+    // the script recreates the same new db, which isn't written itself
+    let script = f.dir.join("migration.sql").display().to_string();
+    let (code, out) = f.migrate(&["--emit-sql", &script]);
+    assert_eq!(code, 0, "{}", out);
+    assert!(!path::Path::new(&f.new_db()).exists());
+
+    Connection::open(f.new_db()).unwrap()
+        .execute_batch(&fs::read_to_string(&script).unwrap()).unwrap();
+    assert_eq!(f.activities(), act);
+    assert_eq!(f.history(), his);
+}
+
+#[test]
+fn prompted_paths ()
+{
+    let f = Fixture::new("prompted_paths",
+        &[(1, "Reading", "2020-01-01", 1.5)],
+        &[(1, 2024, 1, 1, 1, Some(1.5), "2024-01-01")]);
+    let new = f.new_db();
+
+    // HOME is the fixture's folder, so ~ is where old.db is
+    let typed = [
+        "\"~/old.db\"\n".to_string(),
+        " '~/old.db' \n".to_string(),
+        format!("'{}//./old.db/'\n", f.dir.display()),
+    ];
+    for input in &typed
+    {
+        let _ = fs::remove_file(&new);
+        let (code, out) = f.run_with_input(&["--db-path", &new], input);
+        assert_eq!(code, 0, "{:?}: {}", input, out);
+        assert!(out.contains(&format!("from {:?}", f.old_db())), "{:?}: {}", input, out);
+        assert_eq!(f.activities().len(), 1);
+    }
+
+    let _ = fs::remove_file(&new);
+    let (code, out) = f.migrate(&[]);
+    assert_eq!(code, 0, "{}", out);
+    assert!(out.contains(&format!("point the new app at its db: {:?}", new)), "{}", out);
+    assert!(out.contains("if something looks wrong:    delete it and run again"), "{}", out);
+    let _ = fs::remove_file(&new);
+    let (_, out) = f.migrate(&["--quiet"]);
+    assert!(!out.contains("Next steps"), "{}", out);
+
+    // TT_OLD_DB stands in for OLD_PATH, the prompt isn't reached
+    let _ = fs::remove_file(&new);
+    let (code, out, err) = f.output_with_env(&["--db-path", &new], "", &[("TT_OLD_DB", &f.old_db())]);
+    assert_eq!(code, 0, "{}", err);
+    assert!(out.contains(&format!("Reading old db path from TT_OLD_DB: {:?}", f.old_db())), "{}", out);
+    assert_eq!(f.activities().len(), 1);
+}